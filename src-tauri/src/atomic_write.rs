@@ -0,0 +1,259 @@
+// 画像ファイルの安全な上書き用のモジュール
+//
+// 元ファイルを直接開いて書き込むと、途中でのクラッシュ・容量不足・エンコードエラーで
+// 唯一の画像が壊れるため、同じディレクトリの一時ファイルに書き込んでから rename で置き換える。
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, FileTimes};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tauri::{AppHandle, Manager, Runtime};
+
+/// 一時ファイル名の重複を避けるための連番（一括書き込みの並列処理向け）
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// 書き込み後に元の更新日時・アクセス日時を復元するか（既定で有効、起動時に保存済みの設定を反映）
+static PRESERVE_TIMESTAMPS: AtomicBool = AtomicBool::new(true);
+
+/// メタデータ書き込みの設定を保存するファイル名（アプリデータディレクトリ内）
+const OPTIONS_FILE: &str = "metadata-write-options.json";
+
+/// メタデータ書き込みの設定（アプリデータディレクトリに保存され、次回起動時も引き継ぐ）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataWriteOptions {
+    /// レーティング等の編集後も元の更新日時・アクセス日時を保持する（日付順の並びを変えない）
+    pub preserve_timestamps: bool,
+}
+
+impl Default for MetadataWriteOptions {
+    fn default() -> Self {
+        Self {
+            preserve_timestamps: true,
+        }
+    }
+}
+
+/// 設定ファイルのパス
+fn options_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    app.path()
+        .app_data_dir()
+        .map(|data_dir| data_dir.join(OPTIONS_FILE))
+        .map_err(|e| format!("アプリデータディレクトリの取得に失敗: {}", e))
+}
+
+/// 保存済みのメタデータ書き込み設定を読み込んで適用（起動時）
+///
+/// 設定ファイルが無い・読み込めない場合は既定値のまま続行する。
+pub fn load_metadata_write_options<R: Runtime>(app: &AppHandle<R>) {
+    let path = match options_path(app) {
+        Ok(path) => path,
+        Err(e) => {
+            log::warn!("メタデータ書き込み設定を読み込めません: {}", e);
+            return;
+        }
+    };
+    let json = match fs::read(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            log::warn!("メタデータ書き込み設定の読み込みに失敗: {}", e);
+            return;
+        }
+    };
+    match serde_json::from_slice::<MetadataWriteOptions>(&json) {
+        Ok(options) => PRESERVE_TIMESTAMPS.store(options.preserve_timestamps, Ordering::Relaxed),
+        Err(e) => log::warn!(
+            "メタデータ書き込み設定の形式が不正です（既定値を使用）: {}",
+            e
+        ),
+    }
+}
+
+/// メタデータ書き込みの設定を取得（Tauri API）
+#[tauri::command]
+pub fn get_metadata_write_options() -> MetadataWriteOptions {
+    MetadataWriteOptions {
+        preserve_timestamps: PRESERVE_TIMESTAMPS.load(Ordering::Relaxed),
+    }
+}
+
+/// メタデータ書き込みの設定を変更して保存（Tauri API）
+///
+/// 保存に失敗しても今回のセッションには反映される。
+#[tauri::command]
+pub fn set_metadata_write_options<R: Runtime>(
+    app: AppHandle<R>,
+    options: MetadataWriteOptions,
+) -> Result<(), String> {
+    PRESERVE_TIMESTAMPS.store(options.preserve_timestamps, Ordering::Relaxed);
+
+    let path = options_path(&app)?;
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)
+            .map_err(|e| format!("アプリデータディレクトリの作成に失敗: {}", e))?;
+    }
+    let json = serde_json::to_vec_pretty(&options)
+        .map_err(|e| format!("メタデータ書き込み設定のシリアライズに失敗: {}", e))?;
+    fs::write(&path, json).map_err(|e| format!("メタデータ書き込み設定の保存に失敗: {}", e))
+}
+
+/// 既存ファイルをアトミックに置き換え
+///
+/// 一時ファイルへの書き込み・fsync の後に rename するため、失敗しても元ファイルはそのまま残る。
+/// 元ファイルのパーミッションと作成日時（対応OSのみ）を引き継ぐ。
+/// 更新日時・アクセス日時は設定が有効な場合のみ引き継ぎ、無効な場合は現在時刻になる。
+pub fn write_file_atomic(path: &str, data: &[u8]) -> Result<(), String> {
+    // シンボリックリンクの場合はリンク先を置き換える
+    let target =
+        fs::canonicalize(path).map_err(|e| format!("書き込み先の解決に失敗: {} ({})", e, path))?;
+    let metadata = fs::metadata(&target).map_err(|e| format!("ファイル情報の取得に失敗: {}", e))?;
+    let directory = target
+        .parent()
+        .ok_or_else(|| format!("親ディレクトリが見つかりません: {}", path))?;
+
+    let (temp_path, mut file) = create_temp_file(directory, &target)?;
+    let result = file
+        .write_all(data)
+        .and_then(|_| file.set_permissions(metadata.permissions()))
+        .and_then(|_| file.set_times(preserved_times(&metadata)))
+        .and_then(|_| file.sync_all());
+    drop(file);
+
+    if let Err(e) = result.and_then(|_| fs::rename(&temp_path, &target)) {
+        if let Err(remove_error) = fs::remove_file(&temp_path) {
+            log::warn!(
+                "一時ファイルの削除に失敗: {} ({})",
+                temp_path.display(),
+                remove_error
+            );
+        }
+        return Err(format!("書き込みエラー: {}", e));
+    }
+
+    // rename 自体を永続化するためにディレクトリも同期（Unix のみ）
+    #[cfg(unix)]
+    if let Err(e) = File::open(directory).and_then(|dir| dir.sync_all()) {
+        log::warn!("ディレクトリの同期に失敗: {} ({})", directory.display(), e);
+    }
+
+    Ok(())
+}
+
+/// 書き込み先と同じディレクトリに一時ファイルを作成（rename を同一ファイルシステム内に保つ）
+fn create_temp_file(directory: &Path, target: &Path) -> Result<(PathBuf, File), String> {
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    loop {
+        let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp_path = directory.join(format!(
+            ".{}.{}-{}.tmp",
+            file_name,
+            std::process::id(),
+            counter
+        ));
+        match File::options()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("一時ファイルの作成に失敗: {}", e)),
+        }
+    }
+}
+
+/// 新しいファイルに引き継ぐ元ファイルの日時
+///
+/// rename で別のファイルに置き換わるため、作成日時は常に引き継ぐ。
+fn preserved_times(metadata: &fs::Metadata) -> FileTimes {
+    let mut times = FileTimes::new();
+    if PRESERVE_TIMESTAMPS.load(Ordering::Relaxed) {
+        if let Ok(modified) = metadata.modified() {
+            times = times.set_modified(modified);
+        }
+        if let Ok(accessed) = metadata.accessed() {
+            times = times.set_accessed(accessed);
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    if let Ok(created) = metadata.created() {
+        use std::os::macos::fs::FileTimesExt;
+        times = times.set_created(created);
+    }
+    #[cfg(windows)]
+    if let Ok(created) = metadata.created() {
+        use std::os::windows::fs::FileTimesExt;
+        times = times.set_created(created);
+    }
+
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    /// テスト毎に独立した一時ディレクトリ
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("atomic-write-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn replaces_content_and_keeps_modified_time() {
+        let directory = test_directory("modified");
+        let path = directory.join("image.png");
+        fs::write(&path, b"old").unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_times(FileTimes::new().set_modified(modified))
+            .unwrap();
+
+        write_file_atomic(path.to_str().unwrap(), b"new").unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+        // 一時ファイルが残っていない
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = test_directory("permissions");
+        let path = directory.join("image.png");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        write_file_atomic(path.to_str().unwrap(), b"new").unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn missing_file_is_not_created() {
+        let directory = test_directory("missing");
+        let path = directory.join("missing.png");
+
+        assert!(write_file_atomic(path.to_str().unwrap(), b"new").is_err());
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,657 @@
+// ComfyUI の prompt / workflow チャンク解析用のモジュール
+use crate::sd_parameters::{SdParameterSource, SdParameters};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/// ノードグラフを辿る最大深さ（循環・巨大グラフ対策）
+const MAX_TRAVERSAL_DEPTH: usize = 32;
+
+/// サンプラーとして扱うノード種別
+const SAMPLER_CLASS_TYPES: &[&str] = &[
+    "KSampler",
+    "KSamplerAdvanced",
+    "SamplerCustom",
+    "SamplerCustomAdvanced",
+];
+
+/// SamplerCustom 系で設定値を持つノードを接続する入力（RandomNoise・CFGGuider・KSamplerSelect・BasicScheduler 等）
+const SAMPLER_SETTING_INPUTS: &[&str] = &["noise", "guider", "sampler", "sigmas"];
+
+/// workflow（UI形式）で解析するサンプラー（ウィジェット値の並びが既知のもののみ）
+const WORKFLOW_SAMPLER_CLASS_TYPES: &[&str] = &["KSampler", "KSamplerAdvanced"];
+
+/// API形式のノード（prompt チャンクの1要素）
+struct ComfyNode<'a> {
+    id: &'a str,
+    class_type: &'a str,
+    inputs: &'a Map<String, Value>,
+}
+
+/// API形式のノードグラフ（prompt チャンク）
+struct ComfyGraph<'a> {
+    nodes: Vec<ComfyNode<'a>>,
+}
+
+impl<'a> ComfyGraph<'a> {
+    /// prompt チャンクのJSONオブジェクトからグラフを構築
+    fn from_value(value: &'a Value) -> Option<Self> {
+        let object = value.as_object()?;
+
+        let mut nodes: Vec<ComfyNode<'a>> = object
+            .iter()
+            .filter_map(|(id, node)| {
+                let class_type = node.get("class_type")?.as_str()?;
+                let inputs = node.get("inputs")?.as_object()?;
+                Some(ComfyNode {
+                    id: id.as_str(),
+                    class_type,
+                    inputs,
+                })
+            })
+            .collect();
+
+        if nodes.is_empty() {
+            return None;
+        }
+
+        // ノードIDの数値順に並べる（実行順に近い順序で探索するため）
+        nodes.sort_by_key(|node| node.id.parse::<u64>().unwrap_or(u64::MAX));
+        Some(Self { nodes })
+    }
+
+    fn node(&self, id: &str) -> Option<&ComfyNode<'a>> {
+        self.nodes.iter().find(|node| node.id == id)
+    }
+
+    /// `["<node id>", <output index>]` 形式のリンクを解決
+    fn linked_node(&self, value: &Value) -> Option<&ComfyNode<'a>> {
+        let link = value.as_array()?;
+        if link.len() != 2 {
+            return None;
+        }
+        let id = match &link[0] {
+            Value::String(id) => id.clone(),
+            Value::Number(id) => id.to_string(),
+            _ => return None,
+        };
+        self.node(&id)
+    }
+
+    /// 入力値を解決（リンクの場合はリンク先ノードの同名・汎用入力を辿る）
+    fn resolve_scalar(&self, value: &Value, keys: &[&str], depth: usize) -> Option<Value> {
+        if depth > MAX_TRAVERSAL_DEPTH {
+            return None;
+        }
+
+        let Some(node) = self.linked_node(value) else {
+            return match value {
+                Value::String(_) | Value::Number(_) | Value::Bool(_) => Some(value.clone()),
+                _ => None,
+            };
+        };
+
+        // プリミティブノード等は value / seed / text などで値を持つ
+        keys.iter()
+            .chain(["value", "seed", "int", "float", "string", "text"].iter())
+            .filter_map(|key| node.inputs.get(*key))
+            .find_map(|input| self.resolve_scalar(input, keys, depth + 1))
+    }
+
+    /// サンプラーの設定値を持つノード（サンプラー自身と、SamplerCustom 系の接続先ノード）
+    fn setting_nodes<'s>(&'s self, sampler: &'s ComfyNode<'a>) -> Vec<&'s ComfyNode<'a>> {
+        let mut nodes = vec![sampler];
+        nodes.extend(
+            SAMPLER_SETTING_INPUTS
+                .iter()
+                .filter_map(|key| sampler.inputs.get(*key))
+                .filter_map(|value| self.linked_node(value)),
+        );
+        nodes
+    }
+
+    /// ノードの入力を文字列として解決
+    fn input_string(&self, node: &ComfyNode, keys: &[&str]) -> Option<String> {
+        keys.iter()
+            .filter_map(|key| node.inputs.get(*key))
+            .find_map(|value| self.resolve_scalar(value, keys, 0))
+            .map(|value| value_to_string(&value))
+            .filter(|value| !value.is_empty())
+    }
+
+    /// 条件付け（conditioning）を遡ってプロンプト文字列を収集
+    fn collect_prompt_texts(
+        &self,
+        value: &Value,
+        visited: &mut HashSet<String>,
+        depth: usize,
+        texts: &mut Vec<String>,
+    ) {
+        if depth > MAX_TRAVERSAL_DEPTH {
+            return;
+        }
+        let Some(node) = self.linked_node(value) else {
+            return;
+        };
+        if !visited.insert(node.id.to_string()) {
+            return;
+        }
+
+        // CLIPTextEncode 系ノード（SDXL の text_g / text_l にも対応）
+        if node.class_type.contains("TextEncode") {
+            for key in ["text", "text_g", "text_l", "prompt"] {
+                if let Some(text) = self.input_string(node, &[key]) {
+                    if !texts.contains(&text) {
+                        texts.push(text);
+                    }
+                }
+            }
+            return;
+        }
+
+        // ConditioningCombine 等の中間ノードはリンク入力を全て辿る
+        for input in node.inputs.values() {
+            if self.linked_node(input).is_some() {
+                self.collect_prompt_texts(input, visited, depth + 1, texts);
+            }
+        }
+    }
+
+    /// model 入力を遡ってチェックポイント名を取得
+    fn find_model_name(&self, value: &Value, depth: usize) -> Option<String> {
+        if depth > MAX_TRAVERSAL_DEPTH {
+            return None;
+        }
+        let node = self.linked_node(value)?;
+
+        if let Some(name) = self.input_string(node, &["ckpt_name", "unet_name", "model_name"]) {
+            return Some(model_display_name(&name));
+        }
+
+        // LoraLoader 等を経由している場合はさらに model 入力を辿る
+        node.inputs
+            .get("model")
+            .and_then(|model| self.find_model_name(model, depth + 1))
+    }
+
+    /// latent 入力を遡って生成サイズを取得
+    fn find_size(&self, value: &Value, depth: usize) -> Option<String> {
+        if depth > MAX_TRAVERSAL_DEPTH {
+            return None;
+        }
+        let node = self.linked_node(value)?;
+
+        let width = self.input_string(node, &["width"]);
+        let height = self.input_string(node, &["height"]);
+        if let (Some(width), Some(height)) = (width, height) {
+            return Some(format!("{}x{}", width, height));
+        }
+
+        // LatentUpscale 等を経由している場合は元の latent を辿る
+        ["samples", "latent_image", "latent"]
+            .iter()
+            .filter_map(|key| node.inputs.get(*key))
+            .find_map(|input| self.find_size(input, depth + 1))
+    }
+
+    /// model 入力を遡り、経由している LoRA ローダーを `<lora:name:te:unet>` 形式で収集
+    ///
+    /// サンプラーに繋がっていない LoRA ローダーは生成に使われていないため含めない。
+    /// 読み込み順（チェックポイント側から）に並べるため、先に上流を辿る。
+    fn collect_lora_prompts(&self, value: &Value, depth: usize, prompts: &mut Vec<String>) {
+        if depth > MAX_TRAVERSAL_DEPTH {
+            return;
+        }
+        let Some(node) = self.linked_node(value) else {
+            return;
+        };
+        if let Some(model) = node.inputs.get("model") {
+            self.collect_lora_prompts(model, depth + 1, prompts);
+        }
+
+        if let Some(name) = self.input_string(node, &["lora_name"]) {
+            let unet = self
+                .input_string(node, &["strength_model", "strength"])
+                .unwrap_or_else(|| "1".to_string());
+            let te = self.input_string(node, &["strength_clip"]);
+            prompts.push(lora_prompt(&name, te.as_deref(), &unet));
+        }
+    }
+}
+
+/// JSON値を表示用の文字列に変換
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.trim().to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// モデルファイル名からディレクトリと拡張子を除去（A1111 の "Model:" 表記に合わせる）
+fn model_display_name(file_name: &str) -> String {
+    let base = file_name.rsplit(['/', '\\']).next().unwrap_or(file_name);
+    match base.rfind('.') {
+        Some(pos) if pos > 0 => base[..pos].to_string(),
+        _ => base.to_string(),
+    }
+}
+
+/// LoRA を A1111 の `<lora:name:te:unet>` 記法に変換（te 省略時は `<lora:name:unet>`）
+fn lora_prompt(file_name: &str, te: Option<&str>, unet: &str) -> String {
+    let name = model_display_name(file_name);
+    match te {
+        Some(te) if te != unet => format!("<lora:{}:{}:{}>", name, te, unet),
+        _ => format!("<lora:{}:{}>", name, unet),
+    }
+}
+
+/// プロンプト文字列群を1つのプロンプトに結合
+fn join_prompt_texts(texts: &[String]) -> String {
+    texts
+        .iter()
+        .map(|text| text.trim().trim_end_matches(','))
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// ComfyUI の prompt（API形式）チャンクをパースする
+pub fn parse_comfyui_prompt(prompt: &str) -> Result<SdParameters, String> {
+    let value: Value = serde_json::from_str(prompt)
+        .map_err(|e| format!("ComfyUI prompt のJSON解析エラー: {}", e))?;
+    let graph = ComfyGraph::from_value(&value)
+        .ok_or_else(|| "ComfyUI prompt にノードが見つかりません".to_string())?;
+
+    let sampler = graph
+        .nodes
+        .iter()
+        .find(|node| SAMPLER_CLASS_TYPES.contains(&node.class_type))
+        .ok_or_else(|| "ComfyUI prompt にサンプラーノードが見つかりません".to_string())?;
+
+    // SamplerCustomAdvanced はモデル・条件付けをガイダーに、ステップ数等をスケジューラーに持つ
+    let setting_nodes = graph.setting_nodes(sampler);
+    let input = |keys: &[&str]| {
+        setting_nodes
+            .iter()
+            .find_map(|node| keys.iter().find_map(|key| node.inputs.get(*key)))
+    };
+    let setting = |keys: &[&str]| {
+        setting_nodes
+            .iter()
+            .find_map(|node| graph.input_string(node, keys))
+    };
+
+    let mut positive_texts = Vec::new();
+    if let Some(positive) = input(&["positive", "conditioning"]) {
+        graph.collect_prompt_texts(positive, &mut HashSet::new(), 0, &mut positive_texts);
+    }
+    let mut negative_texts = Vec::new();
+    if let Some(negative) = input(&["negative"]) {
+        graph.collect_prompt_texts(negative, &mut HashSet::new(), 0, &mut negative_texts);
+    }
+
+    // LoRA はA1111 と同じく `<lora:name:weight>` としてプロンプト末尾に付与
+    let model_input = input(&["model"]);
+    if let Some(model) = model_input {
+        graph.collect_lora_prompts(model, 0, &mut positive_texts);
+    }
+    let positive_prompt = join_prompt_texts(&positive_texts);
+    let negative_prompt = join_prompt_texts(&negative_texts);
+
+    let model = model_input.and_then(|model| graph.find_model_name(model, 0));
+    let size = ["latent_image", "latent"]
+        .iter()
+        .filter_map(|key| sampler.inputs.get(*key))
+        .find_map(|latent| graph.find_size(latent, 0));
+
+    // denoise は img2img 相当（1未満）の場合のみ記録（A1111 と同じ挙動）
+    let denoising_strength =
+        setting(&["denoise"]).filter(|denoise| denoise.parse::<f32>().map_or(true, |v| v < 1.0));
+
+    Ok(SdParameters {
+        source: SdParameterSource::ComfyUi,
+        positive_sd_tags: SdParameters::parse_sd_tags(&positive_prompt),
+        negative_sd_tags: SdParameters::parse_sd_tags(&negative_prompt),
+        positive_prompt,
+        negative_prompt,
+        steps: setting(&["steps"]),
+        sampler: setting(&["sampler_name", "sampler"]),
+        schedule_type: setting(&["scheduler"]),
+        cfg_scale: setting(&["cfg"]),
+        seed: setting(&["seed", "noise_seed"]),
+        size,
+        model,
+        denoising_strength,
+        raw: prompt.to_string(),
+        ..Default::default()
+    }
+    .with_networks())
+}
+
+/// ComfyUI の workflow（UI形式）チャンクをパースする
+///
+/// prompt チャンクが存在しない画像向けのフォールバック。
+/// ウィジェット値の並び順は標準ノードの定義に従う。
+pub fn parse_comfyui_workflow(workflow: &str) -> Result<SdParameters, String> {
+    let value: Value = serde_json::from_str(workflow)
+        .map_err(|e| format!("ComfyUI workflow のJSON解析エラー: {}", e))?;
+    let nodes = value
+        .get("nodes")
+        .and_then(|nodes| nodes.as_array())
+        .ok_or_else(|| "ComfyUI workflow にノードが見つかりません".to_string())?;
+
+    // リンクID → 接続元ノードID
+    let links: Vec<(u64, u64)> = value
+        .get("links")
+        .and_then(|links| links.as_array())
+        .map(|links| {
+            links
+                .iter()
+                .filter_map(|link| {
+                    let link = link.as_array()?;
+                    Some((link.first()?.as_u64()?, link.get(1)?.as_u64()?))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let node_by_id = |id: u64| {
+        nodes
+            .iter()
+            .find(|node| node.get("id").and_then(|v| v.as_u64()) == Some(id))
+    };
+    let node_type = |node: &Value| {
+        node.get("type")
+            .and_then(|t| t.as_str())
+            .unwrap_or("")
+            .to_string()
+    };
+    let widget = |node: &Value, index: usize| {
+        node.get("widgets_values")
+            .and_then(|values| values.as_array())
+            .and_then(|values| values.get(index))
+            .map(value_to_string)
+            .filter(|value| !value.is_empty())
+    };
+    // 指定した名前の入力に接続されたノードを取得
+    let input_source = |node: &Value, name: &str| {
+        let link_id = node
+            .get("inputs")?
+            .as_array()?
+            .iter()
+            .find(|input| input.get("name").and_then(|n| n.as_str()) == Some(name))?
+            .get("link")?
+            .as_u64()?;
+        let (_, source_id) = links.iter().find(|(id, _)| *id == link_id)?;
+        node_by_id(*source_id)
+    };
+
+    let sampler = nodes
+        .iter()
+        .find(|node| WORKFLOW_SAMPLER_CLASS_TYPES.contains(&node_type(node).as_str()))
+        .ok_or_else(|| "ComfyUI workflow にサンプラーノードが見つかりません".to_string())?;
+
+    // KSampler: [seed, control_after_generate, steps, cfg, sampler_name, scheduler, denoise]
+    // KSamplerAdvanced: [add_noise, noise_seed, control_after_generate, steps, cfg, sampler_name, scheduler, ...]
+    let offset = if node_type(sampler) == "KSamplerAdvanced" {
+        1
+    } else {
+        0
+    };
+
+    let prompt_text = |name: &str| {
+        input_source(sampler, name)
+            .filter(|node| node_type(node).contains("TextEncode"))
+            .and_then(|node| widget(node, 0))
+            .map(|text| vec![text])
+            .unwrap_or_default()
+    };
+
+    // サンプラーの model 入力に繋がる LoRA ローダーのみ（バイパス・ミュートされたノードは除く）
+    let mut lora_prompts = Vec::new();
+    let mut model_source = input_source(sampler, "model");
+    for _ in 0..MAX_TRAVERSAL_DEPTH {
+        let Some(node) = model_source else {
+            break;
+        };
+        let disabled = matches!(node.get("mode").and_then(|mode| mode.as_u64()), Some(2 | 4));
+        if !disabled && node_type(node).contains("Lora") {
+            if let Some(name) = widget(node, 0) {
+                let unet = widget(node, 1).unwrap_or_else(|| "1".to_string());
+                lora_prompts.push(lora_prompt(&name, widget(node, 2).as_deref(), &unet));
+            }
+        }
+        model_source = input_source(node, "model");
+    }
+    lora_prompts.reverse();
+
+    let mut positive_texts = prompt_text("positive");
+    positive_texts.extend(lora_prompts);
+    let positive_prompt = join_prompt_texts(&positive_texts);
+    let negative_prompt = join_prompt_texts(&prompt_text("negative"));
+
+    let model = nodes
+        .iter()
+        .find(|node| node_type(node).starts_with("CheckpointLoader"))
+        .and_then(|node| widget(node, 0))
+        .map(|name| model_display_name(&name));
+    let size = nodes
+        .iter()
+        .find(|node| node_type(node) == "EmptyLatentImage")
+        .and_then(|node| Some(format!("{}x{}", widget(node, 0)?, widget(node, 1)?)));
+
+    Ok(SdParameters {
+        source: SdParameterSource::ComfyUi,
+        positive_sd_tags: SdParameters::parse_sd_tags(&positive_prompt),
+        negative_sd_tags: SdParameters::parse_sd_tags(&negative_prompt),
+        positive_prompt,
+        negative_prompt,
+        steps: widget(sampler, 2 + offset),
+        sampler: widget(sampler, 4 + offset),
+        schedule_type: widget(sampler, 5 + offset),
+        cfg_scale: widget(sampler, 3 + offset),
+        seed: widget(sampler, offset),
+        size,
+        model,
+        raw: workflow.to_string(),
+        ..Default::default()
+    }
+    .with_networks())
+}
+
+/// prompt / workflow チャンクからSD Parametersを構築（prompt を優先）
+///
+/// 両方のチャンクがある場合、raw には `{"prompt": ..., "workflow": ...}` として両方のグラフを保持する。
+pub fn parse_comfyui_chunks(
+    prompt: Option<&str>,
+    workflow: Option<&str>,
+) -> Result<SdParameters, String> {
+    let mut parameters = match (prompt, workflow) {
+        (Some(prompt), workflow) => parse_comfyui_prompt(prompt).or_else(|prompt_error| {
+            workflow
+                .map(parse_comfyui_workflow)
+                .unwrap_or(Err(prompt_error))
+        })?,
+        (None, Some(workflow)) => parse_comfyui_workflow(workflow)?,
+        (None, None) => return Err("ComfyUI のチャンクが見つかりません".to_string()),
+    };
+
+    if let (Some(prompt), Some(workflow)) = (prompt, workflow) {
+        parameters.raw = combined_raw(prompt, workflow);
+    }
+    Ok(parameters)
+}
+
+/// prompt と workflow の両方のグラフをまとめた JSON（JSON として読めない場合は文字列のまま）
+fn combined_raw(prompt: &str, workflow: &str) -> String {
+    let graph = |text: &str| {
+        serde_json::from_str::<Value>(text).unwrap_or_else(|_| Value::String(text.to_string()))
+    };
+    let mut raw = Map::new();
+    raw.insert("prompt".to_string(), graph(prompt));
+    raw.insert("workflow".to_string(), graph(workflow));
+    Value::Object(raw).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ComfyUI の標準ワークフローに LoRA を2段挟んだ prompt（API形式）
+    ///
+    /// 12 番の LoRA ローダーはサンプラーに繋がっていない（プレビュー用の分岐）。
+    const LORA_PROMPT: &str = r#"{
+        "3": {"inputs": {"seed": 156680208700286, "steps": 20, "cfg": 8, "sampler_name": "euler", "scheduler": "normal", "denoise": 1, "model": ["11", 0], "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["5", 0]}, "class_type": "KSampler", "_meta": {"title": "KSampler"}},
+        "4": {"inputs": {"ckpt_name": "SD1.5/v1-5-pruned-emaonly.safetensors"}, "class_type": "CheckpointLoaderSimple", "_meta": {"title": "Load Checkpoint"}},
+        "5": {"inputs": {"width": 512, "height": 768, "batch_size": 1}, "class_type": "EmptyLatentImage", "_meta": {"title": "Empty Latent Image"}},
+        "6": {"inputs": {"text": "beautiful scenery nature glass bottle landscape, purple galaxy bottle,", "clip": ["11", 1]}, "class_type": "CLIPTextEncode", "_meta": {"title": "CLIP Text Encode (Prompt)"}},
+        "7": {"inputs": {"text": "text, watermark", "clip": ["11", 1]}, "class_type": "CLIPTextEncode", "_meta": {"title": "CLIP Text Encode (Prompt)"}},
+        "8": {"inputs": {"samples": ["3", 0], "vae": ["4", 2]}, "class_type": "VAEDecode", "_meta": {"title": "VAE Decode"}},
+        "9": {"inputs": {"filename_prefix": "ComfyUI", "images": ["8", 0]}, "class_type": "SaveImage", "_meta": {"title": "Save Image"}},
+        "10": {"inputs": {"lora_name": "styles/watercolor_v2.safetensors", "strength_model": 0.8, "strength_clip": 0.8, "model": ["4", 0], "clip": ["4", 1]}, "class_type": "LoraLoader", "_meta": {"title": "Load LoRA"}},
+        "11": {"inputs": {"lora_name": "detail_tweaker.safetensors", "strength_model": 0.5, "strength_clip": 1, "model": ["10", 0], "clip": ["10", 1]}, "class_type": "LoraLoader", "_meta": {"title": "Load LoRA"}},
+        "12": {"inputs": {"lora_name": "unused_style.safetensors", "strength_model": 1, "model": ["4", 0]}, "class_type": "LoraLoaderModelOnly", "_meta": {"title": "LoraLoaderModelOnly"}}
+    }"#;
+
+    /// ComfyUI の Flux テンプレート（SamplerCustomAdvanced）に LoRA を挟んだ prompt
+    const FLUX_PROMPT: &str = r#"{
+        "6": {"inputs": {"text": "cute anime girl with massive fluffy fennec ears", "clip": ["11", 0]}, "class_type": "CLIPTextEncode", "_meta": {"title": "CLIP Text Encode (Positive Prompt)"}},
+        "8": {"inputs": {"samples": ["13", 0], "vae": ["10", 0]}, "class_type": "VAEDecode", "_meta": {"title": "VAE Decode"}},
+        "9": {"inputs": {"filename_prefix": "ComfyUI", "images": ["8", 0]}, "class_type": "SaveImage", "_meta": {"title": "Save Image"}},
+        "10": {"inputs": {"vae_name": "ae.safetensors"}, "class_type": "VAELoader", "_meta": {"title": "Load VAE"}},
+        "11": {"inputs": {"clip_name1": "t5xxl_fp16.safetensors", "clip_name2": "clip_l.safetensors", "type": "flux"}, "class_type": "DualCLIPLoader", "_meta": {"title": "DualCLIPLoader"}},
+        "12": {"inputs": {"unet_name": "flux1-dev.safetensors", "weight_dtype": "default"}, "class_type": "UNETLoader", "_meta": {"title": "Load Diffusion Model"}},
+        "13": {"inputs": {"noise": ["25", 0], "guider": ["22", 0], "sampler": ["16", 0], "sigmas": ["17", 0], "latent_image": ["27", 0]}, "class_type": "SamplerCustomAdvanced", "_meta": {"title": "SamplerCustomAdvanced"}},
+        "16": {"inputs": {"sampler_name": "euler"}, "class_type": "KSamplerSelect", "_meta": {"title": "KSamplerSelect"}},
+        "17": {"inputs": {"scheduler": "simple", "steps": 20, "denoise": 1, "model": ["30", 0]}, "class_type": "BasicScheduler", "_meta": {"title": "BasicScheduler"}},
+        "22": {"inputs": {"model": ["30", 0], "conditioning": ["26", 0]}, "class_type": "BasicGuider", "_meta": {"title": "BasicGuider"}},
+        "25": {"inputs": {"noise_seed": 219670278747233}, "class_type": "RandomNoise", "_meta": {"title": "RandomNoise"}},
+        "26": {"inputs": {"guidance": 3.5, "conditioning": ["6", 0]}, "class_type": "FluxGuidance", "_meta": {"title": "FluxGuidance"}},
+        "27": {"inputs": {"width": 1024, "height": 1024, "batch_size": 1}, "class_type": "EmptySD3LatentImage", "_meta": {"title": "EmptySD3LatentImage"}},
+        "30": {"inputs": {"max_shift": 1.15, "base_shift": 0.5, "width": 1024, "height": 1024, "model": ["31", 0]}, "class_type": "ModelSamplingFlux", "_meta": {"title": "ModelSamplingFlux"}},
+        "31": {"inputs": {"lora_name": "flux/realism_lora.safetensors", "strength_model": 0.7, "model": ["12", 0]}, "class_type": "LoraLoaderModelOnly", "_meta": {"title": "LoraLoaderModelOnly"}}
+    }"#;
+
+    /// LORA_PROMPT と同じグラフの workflow（UI形式）
+    ///
+    /// 10 番の LoRA ローダーはバイパス（mode 4）されている。
+    const LORA_WORKFLOW: &str = r#"{
+        "last_node_id": 12,
+        "last_link_id": 16,
+        "nodes": [
+            {"id": 3, "type": "KSampler", "pos": [863, 186], "size": [315, 262], "flags": {}, "order": 6, "mode": 0,
+             "inputs": [{"name": "model", "type": "MODEL", "link": 14}, {"name": "positive", "type": "CONDITIONING", "link": 4}, {"name": "negative", "type": "CONDITIONING", "link": 6}, {"name": "latent_image", "type": "LATENT", "link": 2}],
+             "outputs": [{"name": "LATENT", "type": "LATENT", "links": [7], "slot_index": 0}],
+             "properties": {"Node name for S&R": "KSampler"},
+             "widgets_values": [156680208700286, "randomize", 20, 8, "euler", "normal", 1]},
+            {"id": 4, "type": "CheckpointLoaderSimple", "pos": [26, 474], "size": [315, 98], "flags": {}, "order": 0, "mode": 0,
+             "outputs": [{"name": "MODEL", "type": "MODEL", "links": [10, 15], "slot_index": 0}, {"name": "CLIP", "type": "CLIP", "links": [11], "slot_index": 1}, {"name": "VAE", "type": "VAE", "links": [8], "slot_index": 2}],
+             "properties": {"Node name for S&R": "CheckpointLoaderSimple"},
+             "widgets_values": ["SD1.5/v1-5-pruned-emaonly.safetensors"]},
+            {"id": 5, "type": "EmptyLatentImage", "pos": [473, 609], "size": [315, 106], "flags": {}, "order": 1, "mode": 0,
+             "outputs": [{"name": "LATENT", "type": "LATENT", "links": [2], "slot_index": 0}],
+             "properties": {"Node name for S&R": "EmptyLatentImage"},
+             "widgets_values": [512, 768, 1]},
+            {"id": 6, "type": "CLIPTextEncode", "pos": [415, 186], "size": [422, 164], "flags": {}, "order": 4, "mode": 0,
+             "inputs": [{"name": "clip", "type": "CLIP", "link": 13}],
+             "outputs": [{"name": "CONDITIONING", "type": "CONDITIONING", "links": [4], "slot_index": 0}],
+             "properties": {"Node name for S&R": "CLIPTextEncode"},
+             "widgets_values": ["beautiful scenery nature glass bottle landscape, purple galaxy bottle,"]},
+            {"id": 7, "type": "CLIPTextEncode", "pos": [413, 389], "size": [425, 180], "flags": {}, "order": 5, "mode": 0,
+             "inputs": [{"name": "clip", "type": "CLIP", "link": 16}],
+             "outputs": [{"name": "CONDITIONING", "type": "CONDITIONING", "links": [6], "slot_index": 0}],
+             "properties": {"Node name for S&R": "CLIPTextEncode"},
+             "widgets_values": ["text, watermark"]},
+            {"id": 10, "type": "LoraLoader", "pos": [26, 186], "size": [315, 126], "flags": {}, "order": 2, "mode": 4,
+             "inputs": [{"name": "model", "type": "MODEL", "link": 10}, {"name": "clip", "type": "CLIP", "link": 11}],
+             "outputs": [{"name": "MODEL", "type": "MODEL", "links": [12], "slot_index": 0}, {"name": "CLIP", "type": "CLIP", "links": [9], "slot_index": 1}],
+             "properties": {"Node name for S&R": "LoraLoader"},
+             "widgets_values": ["styles/watercolor_v2.safetensors", 0.8, 0.8]},
+            {"id": 11, "type": "LoraLoader", "pos": [26, 340], "size": [315, 126], "flags": {}, "order": 3, "mode": 0,
+             "inputs": [{"name": "model", "type": "MODEL", "link": 12}, {"name": "clip", "type": "CLIP", "link": 9}],
+             "outputs": [{"name": "MODEL", "type": "MODEL", "links": [14], "slot_index": 0}, {"name": "CLIP", "type": "CLIP", "links": [13, 16], "slot_index": 1}],
+             "properties": {"Node name for S&R": "LoraLoader"},
+             "widgets_values": ["detail_tweaker.safetensors", 0.5, 1]},
+            {"id": 12, "type": "LoraLoaderModelOnly", "pos": [26, 620], "size": [315, 82], "flags": {}, "order": 7, "mode": 0,
+             "inputs": [{"name": "model", "type": "MODEL", "link": 15}],
+             "outputs": [{"name": "MODEL", "type": "MODEL", "links": null}],
+             "properties": {"Node name for S&R": "LoraLoaderModelOnly"},
+             "widgets_values": ["unused_style.safetensors", 1]}
+        ],
+        "links": [
+            [2, 5, 0, 3, 3, "LATENT"], [4, 6, 0, 3, 1, "CONDITIONING"], [6, 7, 0, 3, 2, "CONDITIONING"],
+            [7, 3, 0, 8, 0, "LATENT"], [8, 4, 2, 8, 1, "VAE"], [9, 10, 1, 11, 1, "CLIP"],
+            [10, 4, 0, 10, 0, "MODEL"], [11, 4, 1, 10, 1, "CLIP"], [12, 10, 0, 11, 0, "MODEL"],
+            [13, 11, 1, 6, 0, "CLIP"], [14, 11, 0, 3, 0, "MODEL"], [15, 4, 0, 12, 0, "MODEL"],
+            [16, 11, 1, 7, 0, "CLIP"]
+        ],
+        "groups": [],
+        "config": {},
+        "extra": {"ds": {"scale": 1, "offset": [0, 0]}},
+        "version": 0.4
+    }"#;
+
+    #[test]
+    fn prompt_collects_loras_on_the_model_chain() {
+        let parameters = parse_comfyui_prompt(LORA_PROMPT).unwrap();
+        assert_eq!(
+            parameters.positive_prompt,
+            "beautiful scenery nature glass bottle landscape, purple galaxy bottle, \
+             <lora:watercolor_v2:0.8>, <lora:detail_tweaker:1:0.5>"
+        );
+        assert_eq!(parameters.negative_prompt, "text, watermark");
+        assert_eq!(parameters.model.as_deref(), Some("v1-5-pruned-emaonly"));
+        assert_eq!(parameters.size.as_deref(), Some("512x768"));
+        assert_eq!(parameters.seed.as_deref(), Some("156680208700286"));
+        assert_eq!(parameters.denoising_strength, None);
+        let names: Vec<_> = parameters
+            .networks
+            .iter()
+            .map(|n| n.name.as_str())
+            .collect();
+        assert_eq!(names, ["watercolor_v2", "detail_tweaker"]);
+    }
+
+    #[test]
+    fn sampler_custom_advanced_is_resolved_through_its_inputs() {
+        let parameters = parse_comfyui_prompt(FLUX_PROMPT).unwrap();
+        assert_eq!(
+            parameters.positive_prompt,
+            "cute anime girl with massive fluffy fennec ears, <lora:realism_lora:0.7>"
+        );
+        assert_eq!(parameters.negative_prompt, "");
+        assert_eq!(parameters.steps.as_deref(), Some("20"));
+        assert_eq!(parameters.sampler.as_deref(), Some("euler"));
+        assert_eq!(parameters.schedule_type.as_deref(), Some("simple"));
+        assert_eq!(parameters.seed.as_deref(), Some("219670278747233"));
+        assert_eq!(parameters.model.as_deref(), Some("flux1-dev"));
+        assert_eq!(parameters.size.as_deref(), Some("1024x1024"));
+        assert_eq!(parameters.denoising_strength, None);
+    }
+
+    #[test]
+    fn workflow_skips_bypassed_and_unconnected_loras() {
+        let parameters = parse_comfyui_workflow(LORA_WORKFLOW).unwrap();
+        assert_eq!(
+            parameters.positive_prompt,
+            "beautiful scenery nature glass bottle landscape, purple galaxy bottle, \
+             <lora:detail_tweaker:1:0.5>"
+        );
+        assert_eq!(parameters.negative_prompt, "text, watermark");
+        assert_eq!(parameters.steps.as_deref(), Some("20"));
+        assert_eq!(parameters.cfg_scale.as_deref(), Some("8"));
+        assert_eq!(parameters.sampler.as_deref(), Some("euler"));
+        assert_eq!(parameters.schedule_type.as_deref(), Some("normal"));
+        assert_eq!(parameters.size.as_deref(), Some("512x768"));
+    }
+
+    #[test]
+    fn prompt_is_preferred_over_workflow() {
+        let parameters = parse_comfyui_chunks(Some(LORA_PROMPT), Some(LORA_WORKFLOW)).unwrap();
+        assert!(parameters
+            .positive_prompt
+            .contains("<lora:watercolor_v2:0.8>"));
+        let raw: Value = serde_json::from_str(&parameters.raw).unwrap();
+        assert!(raw["prompt"]["3"].is_object());
+        assert!(raw["workflow"]["nodes"].is_array());
+    }
+}
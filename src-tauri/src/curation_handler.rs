@@ -0,0 +1,131 @@
+// 画像の選別情報（カラーラベル・採用/不採用・キーワード）の読み書き用のモジュール
+//
+// 別のデータベースを持たず、digiKam / Lightroom / darktable が読み書きする XMP プロパティに保存する。
+use crate::file_format::FileFormat;
+use crate::image_metadata::{extract_embedded_metadata, write_xmp_packet};
+use crate::image_types::{ImageCuration, PickFlag};
+use crate::library_index::LibraryIndex;
+use crate::operation_history::{OperationHistory, OperationKind};
+use crate::thumbnail_handler::ThumbnailState;
+use crate::xmp;
+
+/// カラーラベル
+const LABEL_PROPERTY: &str = "xmp:Label";
+/// digiKam の採用フラグ（0: なし、1: 不採用、2: 保留、3: 採用）
+const PICK_LABEL_PROPERTY: &str = "digiKam:PickLabel";
+/// Lightroom / darktable は不採用をレーティング -1 として保存する
+const RATING_PROPERTY: &str = "xmp:Rating";
+const KEYWORDS_PROPERTY: &str = "dc:subject";
+const HIERARCHICAL_KEYWORDS_PROPERTY: &str = "lr:hierarchicalSubject";
+
+/// 選別情報を読み込み（Tauri API）
+#[tauri::command]
+pub fn read_image_curation(path: String) -> Result<ImageCuration, String> {
+    let data = std::fs::read(&path).map_err(|e| format!("ファイル読み込みエラー: {}", e))?;
+    Ok(read_curation_from_bytes(&data))
+}
+
+/// バイトデータから選別情報を読み取り（中核処理）
+pub fn read_curation_from_bytes(data: &[u8]) -> ImageCuration {
+    extract_embedded_metadata(data)
+        .xmp
+        .map(|packet| read_curation_from_xmp(&packet))
+        .unwrap_or_default()
+}
+
+/// XMP パケットから選別情報を読み取り
+fn read_curation_from_xmp(packet: &str) -> ImageCuration {
+    let pick = match xmp::find_property(packet, PICK_LABEL_PROPERTY).as_deref() {
+        Some("3") => Some(PickFlag::Pick),
+        Some("1") => Some(PickFlag::Reject),
+        _ => xmp::find_property(packet, RATING_PROPERTY)
+            .filter(|rating| rating.trim() == "-1")
+            .map(|_| PickFlag::Reject),
+    };
+
+    ImageCuration {
+        label: xmp::find_property(packet, LABEL_PROPERTY).filter(|label| !label.is_empty()),
+        pick,
+        keywords: xmp::find_list(packet, KEYWORDS_PROPERTY),
+        hierarchical_keywords: xmp::find_list(packet, HIERARCHICAL_KEYWORDS_PROPERTY),
+    }
+}
+
+/// 選別情報を書き込み（Tauri API）
+///
+/// 既存の XMP の他のプロパティ（レーティング等）は保持し、選別情報のみを置き換える。
+#[tauri::command]
+pub fn write_image_curation(
+    path: String,
+    curation: ImageCuration,
+    state: tauri::State<'_, ThumbnailState>,
+    history: tauri::State<'_, OperationHistory>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<(), String> {
+    let data = std::fs::read(&path).map_err(|e| format!("ファイル読み込みエラー: {}", e))?;
+    let format = FileFormat::detect_with_hint(&data, &path)?;
+    if !format.supports_xmp_write() {
+        return Err(format!(
+            "XMPの書き込みに対応していない形式です: {}",
+            format.name()
+        ));
+    }
+
+    let packet = extract_embedded_metadata(&data)
+        .xmp
+        .unwrap_or_else(xmp::new_packet);
+    let packet = apply_curation(&packet, &curation);
+    let updated = write_xmp_packet(&data, &packet)?;
+
+    let operation = history.begin(OperationKind::WriteCuration);
+    operation.write(&path, &data, &updated)?;
+    history.commit(operation);
+    state.handler.refresh_cache(std::slice::from_ref(&path));
+    index.refresh(std::slice::from_ref(&path));
+
+    Ok(())
+}
+
+/// 選別情報を XMP パケットに反映
+fn apply_curation(packet: &str, curation: &ImageCuration) -> String {
+    let label = curation.label.as_deref().map(str::trim).unwrap_or_default();
+    let packet = if label.is_empty() {
+        xmp::remove_property(packet, LABEL_PROPERTY)
+    } else {
+        xmp::set_property(packet, LABEL_PROPERTY, label)
+    };
+
+    let mut packet = match curation.pick {
+        Some(PickFlag::Pick) => xmp::set_property(&packet, PICK_LABEL_PROPERTY, "3"),
+        Some(PickFlag::Reject) => xmp::set_property(&packet, PICK_LABEL_PROPERTY, "1"),
+        None => xmp::remove_property(&packet, PICK_LABEL_PROPERTY),
+    };
+    // 不採用を解除した場合は、他のアプリが書き込んだレーティング -1 も未評価に戻す
+    let rejected_by_rating =
+        xmp::find_property(&packet, RATING_PROPERTY).is_some_and(|rating| rating.trim() == "-1");
+    if curation.pick != Some(PickFlag::Reject) && rejected_by_rating {
+        packet = xmp::set_property(&packet, RATING_PROPERTY, "0");
+    }
+
+    let packet = xmp::set_list(
+        &packet,
+        KEYWORDS_PROPERTY,
+        &normalize_keywords(&curation.keywords),
+    );
+    xmp::set_list(
+        &packet,
+        HIERARCHICAL_KEYWORDS_PROPERTY,
+        &normalize_keywords(&curation.hierarchical_keywords),
+    )
+}
+
+/// 前後の空白を除去し、空文字列と重複を取り除く（順序は保持）
+fn normalize_keywords(keywords: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(keywords.len());
+    for keyword in keywords.iter().map(|keyword| keyword.trim()) {
+        if !keyword.is_empty() && !normalized.iter().any(|k| k == keyword) {
+            normalized.push(keyword.to_string());
+        }
+    }
+    normalized
+}
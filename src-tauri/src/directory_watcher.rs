@@ -0,0 +1,644 @@
+// ディレクトリ監視用のモジュール
+//
+// WebUI が書き出す新しい画像をポーリングせずに検出するため、OS のファイル監視
+// （inotify / FSEvents / ReadDirectoryChangesW）を notify 経由で利用する。
+// 書き込み中に連続するイベントはデバウンスし、書き込みが落ち着いてからまとめて通知する。
+use crate::file_format::FileFormat;
+use crate::thumbnail_handler::ThumbnailState;
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{EventKind, RecursiveMode};
+use notify_debouncer_full::{
+    new_debouncer, DebounceEventResult, DebouncedEvent, Debouncer, RecommendedCache,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, Manager, Runtime};
+
+/// 変更をフロントエンドに通知するイベント名
+pub const DIRECTORY_CHANGED_EVENT: &str = "directory-changed";
+/// 最後のイベントからこの時間だけ変化がなければ書き込み完了とみなす
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(500);
+
+type DirectoryDebouncer = Debouncer<notify::RecommendedWatcher, RecommendedCache>;
+
+/// 画像ファイルの変更の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectoryChangeKind {
+    Created,
+    Modified,
+    Removed,
+    Renamed,
+}
+
+/// 画像ファイルの変更
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryChange {
+    pub kind: DirectoryChangeKind,
+    pub path: String,
+    /// 名前変更前のパス（renamed のみ）
+    pub old_path: Option<String>,
+}
+
+/// `directory-changed` イベントのペイロード
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryChangedPayload {
+    /// `watch_directory` に渡されたパス
+    pub directory: String,
+    pub changes: Vec<DirectoryChange>,
+}
+
+/// 監視中のディレクトリ
+struct DirectoryWatcher {
+    /// 破棄すると監視スレッドも停止する
+    debouncer: DirectoryDebouncer,
+    recursive: bool,
+}
+
+/// ディレクトリ監視の状態管理用の構造体
+#[derive(Default)]
+pub struct DirectoryWatcherState {
+    watchers: Mutex<HashMap<String, DirectoryWatcher>>,
+}
+
+/// ディレクトリの監視を開始（Tauri API）
+///
+/// 画像の追加・更新・削除・名前変更を `directory-changed` イベントで通知する。
+/// `recursive` の場合はサブディレクトリ内の画像も対象にする（既定はディレクトリ直下のみ）。
+/// 追加・更新された画像のサムネイルは通知後にサムネイル用のスレッドプールで事前生成する。
+/// 既に同じ設定で監視中の場合は何もしない。
+#[tauri::command]
+pub fn watch_directory<R: Runtime>(
+    path: String,
+    recursive: Option<bool>,
+    app: AppHandle<R>,
+    state: tauri::State<'_, DirectoryWatcherState>,
+) -> Result<(), String> {
+    let recursive = recursive.unwrap_or(false);
+    let mut watchers = state
+        .watchers
+        .lock()
+        .map_err(|e| format!("監視状態のロックに失敗: {}", e))?;
+    match watchers.get(&path) {
+        Some(watcher) if watcher.recursive == recursive => return Ok(()),
+        // 設定が異なる場合は監視し直す
+        Some(_) => {
+            if let Some(watcher) = watchers.remove(&path) {
+                watcher.debouncer.stop_nonblocking();
+            }
+        }
+        None => {}
+    }
+
+    let directory = PathBuf::from(&path);
+    if !directory.is_dir() {
+        return Err(format!("ディレクトリが存在しません: {}", path));
+    }
+
+    let mut known_images = list_images(&directory, recursive);
+    let watched_path = path.clone();
+    let mut debouncer = new_debouncer(
+        DEBOUNCE_TIMEOUT,
+        None,
+        move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                let changes = collect_changes(&events, &mut known_images, recursive);
+                if !changes.is_empty() {
+                    notify_changes(&app, &watched_path, changes);
+                }
+            }
+            Err(errors) => {
+                for e in errors {
+                    log::warn!("ディレクトリ監視エラー: {} ({})", e, watched_path);
+                }
+            }
+        },
+    )
+    .map_err(|e| format!("ディレクトリ監視の開始に失敗: {}", e))?;
+
+    let mode = if recursive {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    debouncer
+        .watch(&directory, mode)
+        .map_err(|e| format!("ディレクトリ監視の開始に失敗: {} ({})", e, path))?;
+
+    log::info!(
+        "ディレクトリ監視を開始: {} (recursive: {})",
+        path,
+        recursive
+    );
+    watchers.insert(
+        path,
+        DirectoryWatcher {
+            debouncer,
+            recursive,
+        },
+    );
+    Ok(())
+}
+
+/// ディレクトリの監視を停止（Tauri API）
+#[tauri::command]
+pub fn unwatch_directory(
+    path: String,
+    state: tauri::State<'_, DirectoryWatcherState>,
+) -> Result<(), String> {
+    let watcher = state
+        .watchers
+        .lock()
+        .map_err(|e| format!("監視状態のロックに失敗: {}", e))?
+        .remove(&path);
+
+    if let Some(watcher) = watcher {
+        // 通知処理中の場合も待たずに戻る
+        watcher.debouncer.stop_nonblocking();
+        log::info!("ディレクトリ監視を停止: {}", path);
+    }
+    Ok(())
+}
+
+/// 対応している画像形式の拡張子かどうか
+fn is_image_path(path: &Path) -> bool {
+    path.to_str().and_then(FileFormat::from_extension).is_some()
+}
+
+/// ディレクトリ内の画像ファイルを列挙（`recursive` の場合はサブディレクトリも含める）
+///
+/// 監視と同じくシンボリックリンクのディレクトリは辿らない。
+fn list_images(directory: &Path, recursive: bool) -> HashSet<PathBuf> {
+    let mut images = HashSet::new();
+    let mut pending_directories = vec![directory.to_path_buf()];
+
+    while let Some(directory) = pending_directories.pop() {
+        let Ok(entries) = std::fs::read_dir(&directory) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_directory = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            if is_directory {
+                if recursive {
+                    pending_directories.push(path);
+                }
+            } else if path.is_file() && is_image_path(&path) {
+                images.insert(path);
+            }
+        }
+    }
+    images
+}
+
+/// デバウンス済みのイベントを画像ファイルの変更に変換
+///
+/// 種類はイベントではなく現在のファイルの有無と既知の画像一覧から判定する
+/// （一時ファイルからの rename による上書きを追加ではなく更新として扱うため）。
+fn collect_changes(
+    events: &[DebouncedEvent],
+    known_images: &mut HashSet<PathBuf>,
+    recursive: bool,
+) -> Vec<DirectoryChange> {
+    let mut changes = Vec::new();
+
+    for event in events {
+        let paths = &event.paths;
+        let event_changes = match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+                rename_changes(&paths[0], &paths[1], known_images, recursive)
+            }
+            // 日時・パーミッションのみの変更と、書き込み完了以外のアクセスは無視
+            EventKind::Modify(ModifyKind::Metadata(_)) => Vec::new(),
+            EventKind::Access(kind) if kind != AccessKind::Close(AccessMode::Write) => Vec::new(),
+            _ => paths
+                .first()
+                .map(|path| path_changes(path, known_images, recursive))
+                .unwrap_or_default(),
+        };
+
+        for change in event_changes {
+            push_change(&mut changes, change);
+        }
+    }
+
+    changes
+}
+
+/// パスの変更を判定（画像ファイル、またはサブディレクトリごとの追加・削除）
+fn path_changes(
+    path: &Path,
+    known_images: &mut HashSet<PathBuf>,
+    recursive: bool,
+) -> Vec<DirectoryChange> {
+    if is_image_path(path) && !path.is_dir() {
+        return existence_change(path, known_images).into_iter().collect();
+    }
+    directory_changes(path, known_images, recursive)
+}
+
+/// ディレクトリごとの移動・削除による変更を判定
+///
+/// 移動してきたディレクトリ内のファイルには個別のイベントが発生しないため、中身を走査して追加とする。
+/// 削除・移動で無くなったディレクトリ内の既知の画像は削除とする。
+fn directory_changes(
+    directory: &Path,
+    known_images: &mut HashSet<PathBuf>,
+    recursive: bool,
+) -> Vec<DirectoryChange> {
+    let mut changes = Vec::new();
+
+    let removed: Vec<PathBuf> = known_images
+        .iter()
+        .filter(|image| image.starts_with(directory) && !image.is_file())
+        .cloned()
+        .collect();
+    changes.extend(
+        removed
+            .iter()
+            .filter_map(|image| existence_change(image, known_images)),
+    );
+
+    if recursive && directory.is_dir() {
+        let mut added: Vec<PathBuf> = list_images(directory, true)
+            .into_iter()
+            .filter(|image| !known_images.contains(image))
+            .collect();
+        added.sort();
+        changes.extend(
+            added
+                .iter()
+                .filter_map(|image| existence_change(image, known_images)),
+        );
+    }
+
+    changes
+}
+
+/// ファイルの有無から変更の種類を判定し、既知の画像一覧を更新
+fn existence_change(path: &Path, known_images: &mut HashSet<PathBuf>) -> Option<DirectoryChange> {
+    if !is_image_path(path) {
+        return None;
+    }
+
+    let kind = match (path.is_file(), known_images.contains(path)) {
+        (true, true) => DirectoryChangeKind::Modified,
+        (true, false) => {
+            known_images.insert(path.to_path_buf());
+            DirectoryChangeKind::Created
+        }
+        (false, true) => {
+            known_images.remove(path);
+            DirectoryChangeKind::Removed
+        }
+        // 作成後すぐに削除された
+        (false, false) => return None,
+    };
+
+    Some(DirectoryChange {
+        kind,
+        path: path.to_string_lossy().into_owned(),
+        old_path: None,
+    })
+}
+
+/// 名前変更を判定（画像以外との間の変更は追加・削除、ディレクトリの名前変更は中身の削除・追加として扱う）
+fn rename_changes(
+    from: &Path,
+    to: &Path,
+    known_images: &mut HashSet<PathBuf>,
+    recursive: bool,
+) -> Vec<DirectoryChange> {
+    if to.is_dir() {
+        let mut changes = directory_changes(from, known_images, recursive);
+        changes.extend(directory_changes(to, known_images, recursive));
+        return changes;
+    }
+
+    match (is_image_path(from), is_image_path(to)) {
+        (true, true) => {
+            known_images.remove(from);
+            known_images.insert(to.to_path_buf());
+            vec![DirectoryChange {
+                kind: DirectoryChangeKind::Renamed,
+                path: to.to_string_lossy().into_owned(),
+                old_path: Some(from.to_string_lossy().into_owned()),
+            }]
+        }
+        (false, true) => existence_change(to, known_images).into_iter().collect(),
+        (true, false) => existence_change(from, known_images).into_iter().collect(),
+        (false, false) => Vec::new(),
+    }
+}
+
+/// 同じファイルへの変更をまとめて追加
+fn push_change(changes: &mut Vec<DirectoryChange>, change: DirectoryChange) {
+    let Some(index) = changes.iter().position(|c| c.path == change.path) else {
+        changes.push(change);
+        return;
+    };
+
+    match (changes[index].kind, change.kind) {
+        (_, DirectoryChangeKind::Modified) => {}
+        (DirectoryChangeKind::Created, DirectoryChangeKind::Removed) => {
+            changes.remove(index);
+        }
+        _ => changes[index] = change,
+    }
+}
+
+/// 変更を通知し、追加・更新された画像のサムネイルをバックグラウンドで事前生成
+///
+/// 監視スレッドを塞がないよう、サムネイル生成はサムネイル用のスレッドプールで行う。
+/// 通知の順序を保つため、通知自体は生成の完了を待たずに監視スレッドから送る。
+fn notify_changes<R: Runtime>(app: &AppHandle<R>, directory: &str, changes: Vec<DirectoryChange>) {
+    let updated_paths: Vec<String> = changes
+        .iter()
+        .filter(|change| change.kind != DirectoryChangeKind::Removed)
+        .map(|change| change.path.clone())
+        .collect();
+
+    let payload = DirectoryChangedPayload {
+        directory: directory.to_string(),
+        changes,
+    };
+    if let Err(e) = app.emit(DIRECTORY_CHANGED_EVENT, payload) {
+        log::warn!("ディレクトリ変更の通知に失敗: {}", e);
+    }
+
+    if updated_paths.is_empty() {
+        return;
+    }
+    let Some(state) = app.try_state::<ThumbnailState>() else {
+        return;
+    };
+    let app = app.clone();
+    state.handler.thread_pool().spawn(move || {
+        let Some(state) = app.try_state::<ThumbnailState>() else {
+            return;
+        };
+        let results = state.handler.process_thumbnails_batch(&updated_paths, &app);
+        for result in results {
+            if let Some(e) = result.error {
+                log::warn!("サムネイルの事前生成に失敗: {} ({})", result.path, e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+    use notify::Event;
+    use std::fs;
+    use std::time::Instant;
+
+    /// テスト毎に独立した一時ディレクトリ
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("directory-watcher-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn event(kind: EventKind, paths: &[&Path]) -> DebouncedEvent {
+        let event = paths.iter().fold(Event::new(kind), |event, path| {
+            event.add_path(path.to_path_buf())
+        });
+        DebouncedEvent::new(event, Instant::now())
+    }
+
+    fn created(path: &Path) -> DebouncedEvent {
+        event(EventKind::Create(CreateKind::File), &[path])
+    }
+
+    fn written(path: &Path) -> DebouncedEvent {
+        event(
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            &[path],
+        )
+    }
+
+    fn renamed(mode: RenameMode, paths: &[&Path]) -> DebouncedEvent {
+        event(EventKind::Modify(ModifyKind::Name(mode)), paths)
+    }
+
+    /// 変更の種類と、ルートからの相対パス（名前変更は変更前のパスも）
+    fn summary(root: &Path, changes: &[DirectoryChange]) -> Vec<(DirectoryChangeKind, String)> {
+        let relative = |path: &str| {
+            Path::new(path)
+                .strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/")
+        };
+        changes
+            .iter()
+            .map(|change| {
+                let path = match &change.old_path {
+                    Some(old_path) => {
+                        format!("{} -> {}", relative(old_path), relative(&change.path))
+                    }
+                    None => relative(&change.path),
+                };
+                (change.kind, path)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn atomic_overwrite_is_modified() {
+        let root = test_directory("atomic");
+        let image = root.join("image.png");
+        fs::write(&image, b"old").unwrap();
+        let mut known_images = list_images(&root, false);
+
+        // 一時ファイルに書き込んでから rename で置き換える
+        let temp = root.join(".image.png.1234-0.tmp");
+        fs::write(&temp, b"new").unwrap();
+        fs::rename(&temp, &image).unwrap();
+        let events = [
+            created(&temp),
+            written(&temp),
+            renamed(RenameMode::Both, &[&temp, &image]),
+        ];
+
+        let changes = collect_changes(&events, &mut known_images, false);
+        assert_eq!(
+            summary(&root, &changes),
+            [(DirectoryChangeKind::Modified, "image.png".to_string())]
+        );
+        assert_eq!(known_images, HashSet::from([image]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn created_and_deleted_in_one_batch() {
+        let root = test_directory("transient");
+        let mut known_images = list_images(&root, false);
+        let image = root.join("preview.png");
+        let events = [
+            created(&image),
+            written(&image),
+            event(EventKind::Remove(RemoveKind::File), &[&image]),
+        ];
+
+        assert!(collect_changes(&events, &mut known_images, false).is_empty());
+        assert!(known_images.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn created_then_written_is_one_change() {
+        let root = test_directory("created");
+        let mut known_images = list_images(&root, false);
+        let image = root.join("00001-1234.png");
+        fs::write(&image, b"png").unwrap();
+        let events = [
+            created(&image),
+            written(&image),
+            event(
+                EventKind::Access(AccessKind::Close(AccessMode::Write)),
+                &[&image],
+            ),
+        ];
+
+        let changes = collect_changes(&events, &mut known_images, false);
+        assert_eq!(
+            summary(&root, &changes),
+            [(DirectoryChangeKind::Created, "00001-1234.png".to_string())]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn directory_moved_in_while_recursive() {
+        let root = test_directory("moved-in");
+        let outside = test_directory("moved-in-source");
+        fs::create_dir_all(outside.join("batch/nested")).unwrap();
+        fs::write(outside.join("batch/a.png"), b"png").unwrap();
+        fs::write(outside.join("batch/nested/b.webp"), b"webp").unwrap();
+        fs::write(outside.join("batch/notes.txt"), b"text").unwrap();
+
+        let moved = root.join("batch");
+        for recursive in [false, true] {
+            let _ = fs::rename(&moved, outside.join("batch"));
+            let mut known_images = list_images(&root, recursive);
+            fs::rename(outside.join("batch"), &moved).unwrap();
+            // 監視外からの移動は移動先のみのイベントになる
+            let events = [renamed(RenameMode::To, &[&moved])];
+
+            let changes = collect_changes(&events, &mut known_images, recursive);
+            let expected = if recursive {
+                vec![
+                    (DirectoryChangeKind::Created, "batch/a.png".to_string()),
+                    (
+                        DirectoryChangeKind::Created,
+                        "batch/nested/b.webp".to_string(),
+                    ),
+                ]
+            } else {
+                Vec::new()
+            };
+            assert_eq!(summary(&root, &changes), expected);
+        }
+
+        // 監視外への移動は中の既知の画像の削除
+        let mut known_images = list_images(&root, true);
+        fs::rename(&moved, outside.join("batch")).unwrap();
+        let events = [renamed(RenameMode::From, &[&moved])];
+        let mut changes = summary(&root, &collect_changes(&events, &mut known_images, true));
+        changes.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            changes,
+            [
+                (DirectoryChangeKind::Removed, "batch/a.png".to_string()),
+                (
+                    DirectoryChangeKind::Removed,
+                    "batch/nested/b.webp".to_string()
+                ),
+            ]
+        );
+        assert!(known_images.is_empty());
+        fs::remove_dir_all(&root).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn renames_between_images_and_other_files() {
+        let root = test_directory("renames");
+        for name in ["a.png", "b.png", "c.png"] {
+            fs::write(root.join(name), b"png").unwrap();
+        }
+        fs::write(root.join("d.part"), b"png").unwrap();
+        let mut known_images = list_images(&root, false);
+
+        let rename = |from: &str, to: &str| {
+            let (from, to) = (root.join(from), root.join(to));
+            fs::rename(&from, &to).unwrap();
+            renamed(RenameMode::Both, &[&from, &to])
+        };
+        let events = [
+            rename("a.png", "a2.png"),
+            rename("b.png", "b.png.bak"),
+            rename("d.part", "d.png"),
+            rename("c.png", "c.txt"),
+            rename("c.txt", "c.png"),
+        ];
+
+        let changes = collect_changes(&events, &mut known_images, false);
+        assert_eq!(
+            summary(&root, &changes),
+            [
+                (DirectoryChangeKind::Renamed, "a.png -> a2.png".to_string()),
+                (DirectoryChangeKind::Removed, "b.png".to_string()),
+                (DirectoryChangeKind::Created, "d.png".to_string()),
+                (DirectoryChangeKind::Modified, "c.png".to_string()),
+            ]
+        );
+        let expected: HashSet<_> = ["a2.png", "c.png", "d.png"]
+            .iter()
+            .map(|name| root.join(name))
+            .collect();
+        assert_eq!(known_images, expected);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn push_change_merges_changes_to_the_same_file() {
+        let change = |kind, path: &str| DirectoryChange {
+            kind,
+            path: path.to_string(),
+            old_path: None,
+        };
+        let mut changes = Vec::new();
+        push_change(&mut changes, change(DirectoryChangeKind::Created, "a.png"));
+        push_change(&mut changes, change(DirectoryChangeKind::Modified, "a.png"));
+        push_change(&mut changes, change(DirectoryChangeKind::Modified, "b.png"));
+        push_change(&mut changes, change(DirectoryChangeKind::Removed, "b.png"));
+        assert_eq!(
+            changes
+                .iter()
+                .map(|c| (c.kind, c.path.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                (DirectoryChangeKind::Created, "a.png"),
+                (DirectoryChangeKind::Removed, "b.png"),
+            ]
+        );
+
+        push_change(&mut changes, change(DirectoryChangeKind::Removed, "a.png"));
+        assert_eq!(
+            changes
+                .iter()
+                .map(|c| (c.kind, c.path.as_str()))
+                .collect::<Vec<_>>(),
+            [(DirectoryChangeKind::Removed, "b.png")]
+        );
+    }
+}
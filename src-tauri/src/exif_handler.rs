@@ -1,20 +1,76 @@
-use crate::image_types::ExifImageInfo;
+use crate::file_format::FileFormat;
+use crate::image_metadata;
+use crate::image_types::{ExifImageInfo, RatingSource};
+use crate::library_index::LibraryIndex;
+use crate::operation_history::{Operation, OperationHistory, OperationKind};
+use crate::thumbnail_handler::ThumbnailState;
+use crate::xmp;
 use little_exif::exif_tag::ExifTag;
-use little_exif::filetype::FileExtension;
 use little_exif::ifd::ExifTagGroup;
 use little_exif::metadata::Metadata;
-use std::path::Path;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// 一括書き込みのファイルごとの結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchWriteResult {
+    pub path: String,
+    pub error: Option<String>,
+}
 
 /// EXIF情報を読み込み（Tauri API）
 #[tauri::command]
 pub fn read_exif_image_info(path: String) -> Result<Option<ExifImageInfo>, String> {
     let data = std::fs::read(&path).map_err(|e| format!("ファイル読み込みエラー: {}", e))?;
-    let file_extension = determine_file_extension(&path);
-    Ok(read_exif_from_bytes(&data, file_extension))
+    let format = FileFormat::detect_with_hint(&data, &path)?;
+    Ok(read_exif_from_bytes(&data, format))
 }
 
 /// バイトデータからEXIF情報を読み取り（中核処理）
-pub fn read_exif_from_bytes(data: &[u8], file_extension: FileExtension) -> Option<ExifImageInfo> {
+///
+/// レーティングは EXIF の Rating タグと XMP の xmp:Rating の両方を確認する。
+pub fn read_exif_from_bytes(data: &[u8], format: FileFormat) -> Option<ExifImageInfo> {
+    let exif_info = read_exif_tags(data, format);
+    let xmp_rating = image_metadata::extract_embedded_metadata(data)
+        .xmp
+        .and_then(|packet| read_xmp_rating(&packet));
+
+    if exif_info.is_none() && xmp_rating.is_none() {
+        return None;
+    }
+    let mut exif_info = exif_info.unwrap_or(ExifImageInfo {
+        rating: None,
+        rating_source: None,
+        create_date: None,
+        datetime_original: None,
+        modify_date: None,
+    });
+
+    // 他のアプリで片方だけ変更された場合は、digiKam / Lightroom 等が読み書きする XMP を優先
+    let (rating, rating_source) = match (exif_info.rating, xmp_rating) {
+        (Some(exif), Some(xmp)) if exif == xmp => (Some(exif), Some(RatingSource::ExifAndXmp)),
+        (_, Some(xmp)) => (Some(xmp), Some(RatingSource::Xmp)),
+        (Some(exif), None) => (Some(exif), Some(RatingSource::Exif)),
+        (None, None) => (None, None),
+    };
+    exif_info.rating = rating;
+    exif_info.rating_source = rating_source;
+    Some(exif_info)
+}
+
+/// XMP の xmp:Rating（0-5 以外の値は無視）
+fn read_xmp_rating(packet: &str) -> Option<u16> {
+    let rating = xmp::find_property(packet, "xmp:Rating")?;
+    // "3.0" のように小数で書き込むツールもある
+    let rating = rating.trim().parse::<f32>().ok()?;
+    (0.0..=5.0).contains(&rating).then(|| rating.round() as u16)
+}
+
+/// EXIFタグを読み取り
+fn read_exif_tags(data: &[u8], format: FileFormat) -> Option<ExifImageInfo> {
+    // EXIF を持たない形式（GIF / BMP）は読み取らない
+    let file_extension = format.to_exif_file_extension()?;
+
     // WebPファイルの場合、VP8形式とVP8X形式の違いによりエラーが発生する可能性があるため、
     // エラーをキャッチして適切に処理する
     match std::panic::catch_unwind(|| Metadata::new_from_vec(&data.to_vec(), file_extension)) {
@@ -32,32 +88,11 @@ pub fn read_exif_from_bytes(data: &[u8], file_extension: FileExtension) -> Optio
     }
 }
 
-/// ファイル拡張子を判定
-pub fn determine_file_extension(image_path: &str) -> FileExtension {
-    // ファイル名から拡張子を抽出（効率的な実装）
-    let extension = image_path
-        .rfind('.')
-        .map(|pos| &image_path[pos + 1..])
-        .unwrap_or("")
-        .to_lowercase();
-
-    match extension.as_str() {
-        "jpg" | "jpeg" => FileExtension::JPEG,
-        "png" => FileExtension::PNG {
-            as_zTXt_chunk: false,
-        },
-        "tiff" | "tif" => FileExtension::TIFF,
-        "webp" => FileExtension::WEBP,
-        "heif" | "heic" => FileExtension::HEIF,
-        "jxl" => FileExtension::JXL,
-        _ => FileExtension::JPEG, // デフォルト
-    }
-}
-
 /// Metadataオブジェクトから情報を抽出（共通処理）
 fn extract_exif_info_from_metadata(metadata: &Metadata) -> Option<ExifImageInfo> {
     let mut exif_info = ExifImageInfo {
         rating: None,
+        rating_source: None,
         create_date: None,
         datetime_original: None,
         modify_date: None,
@@ -86,17 +121,94 @@ fn extract_exif_info_from_metadata(metadata: &Metadata) -> Option<ExifImageInfo>
     Some(exif_info)
 }
 
-/// 画像のレーティングをEXIFに書き込み（Tauri API）
+/// 画像のレーティングをEXIFとXMPに書き込み（Tauri API）
+///
+/// EXIF の Rating / RatingPercent と XMP の xmp:Rating を同じ値に揃える。
+/// XMP の書き込みに対応していない形式（AVIF / HEIF / TIFF / JXL）は EXIF のみ更新する。
+#[tauri::command]
+pub fn write_exif_image_rating(
+    path: String,
+    rating: u32,
+    state: tauri::State<'_, ThumbnailState>,
+    history: tauri::State<'_, OperationHistory>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<(), String> {
+    validate_rating(rating)?;
+    let operation = history.begin(OperationKind::WriteRating);
+    write_rating(&path, rating, &operation)?;
+    history.commit(operation);
+    state.handler.refresh_cache(std::slice::from_ref(&path));
+    index.refresh(std::slice::from_ref(&path));
+    Ok(())
+}
+
+/// 複数画像のレーティングを並列で書き込み（Tauri API）
+///
+/// サムネイル生成と同じスレッドプールで処理し、書き込みに成功した画像のサムネイルキャッシュを更新する。
 #[tauri::command]
-pub fn write_exif_image_rating(path: String, rating: u32) -> Result<(), String> {
+pub async fn write_ratings_batch(
+    paths: Vec<String>,
+    rating: u32,
+    state: tauri::State<'_, ThumbnailState>,
+    history: tauri::State<'_, OperationHistory>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<Vec<BatchWriteResult>, String> {
+    validate_rating(rating)?;
+    let start_time = std::time::Instant::now();
+    let operation = history.begin(OperationKind::WriteRating);
+
+    let results: Vec<BatchWriteResult> = state.handler.thread_pool().install(|| {
+        paths
+            .par_iter()
+            .map(|path| BatchWriteResult {
+                path: path.clone(),
+                error: write_rating(path, rating, &operation).err(),
+            })
+            .collect()
+    });
+    history.commit(operation);
+
+    let written_paths: Vec<String> = results
+        .iter()
+        .filter(|result| result.error.is_none())
+        .map(|result| result.path.clone())
+        .collect();
+    // 画素データは変わらないため、サムネイルは再生成せず埋め込みメタデータのみ更新される
+    let refreshed_count = state.handler.refresh_cache(&written_paths);
+    log::debug!("サムネイルキャッシュを更新: {}ファイル", refreshed_count);
+    index.refresh(&written_paths);
+
+    log::info!(
+        "レーティング一括書き込み完了: {}/{}ファイル成功, 総時間: {:.1}ms",
+        written_paths.len(),
+        results.len(),
+        start_time.elapsed().as_secs_f64() * 1000.0
+    );
+
+    Ok(results)
+}
+
+/// レーティングの範囲チェック
+fn validate_rating(rating: u32) -> Result<(), String> {
     if 5 < rating {
         return Err("レーティングは0-5の範囲で指定してください".to_string());
     }
+    Ok(())
+}
 
-    let png_path = Path::new(&path);
+/// 画像のレーティングを EXIF と XMP に書き込み（中核処理）
+fn write_rating(path: &str, rating: u32, operation: &Operation) -> Result<(), String> {
+    // 拡張子ではなくファイル内容から形式を判定（拡張子違いのファイルでのパニックを防ぐ）
+    let original = std::fs::read(path).map_err(|e| format!("ファイル読み込みエラー: {}", e))?;
+    let format = FileFormat::detect_with_hint(&original, path)?;
+    let file_extension = format
+        .to_exif_file_extension()
+        .ok_or_else(|| format!("EXIFの書き込みに対応していない形式です: {}", format.name()))?;
 
     // パニックを防ぐためのエラーハンドリング
-    let mut png_data = match std::panic::catch_unwind(|| Metadata::new_from_path(&png_path)) {
+    let mut png_data = match std::panic::catch_unwind(|| {
+        Metadata::new_from_vec(&original, file_extension)
+    }) {
         Ok(Ok(metadata)) => metadata,
         Ok(Err(e)) => return Err(format!("メタデータ読み込みエラー: {}", e)),
         Err(_) => return Err(
@@ -127,9 +239,86 @@ pub fn write_exif_image_rating(path: String, rating: u32) -> Result<(), String>
         ExifTagGroup::GENERIC,
     ));
 
+    let mut file_data = original.clone();
     png_data
-        .write_to_file(&png_path)
+        .write_to_vec(&mut file_data, file_extension)
         .map_err(|e| format!("書き込みエラー: {}", e))?;
 
-    Ok(())
+    if format.supports_xmp_write() {
+        let packet = image_metadata::extract_embedded_metadata(&file_data)
+            .xmp
+            .unwrap_or_else(xmp::new_packet);
+        let packet = xmp::set_property(&packet, "xmp:Rating", &rating.to_string());
+        file_data = image_metadata::write_xmp_packet(&file_data, &packet)?;
+    } else {
+        log::info!(
+            "XMPの書き込みに対応していない形式のため、EXIFのみ更新します: {} ({})",
+            path,
+            format.name()
+        );
+    }
+
+    // 元に戻せるよう、書き込みに成功した場合は書き込み前のメタデータを記録
+    operation.write(path, &original, &file_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png_handler::read_png_sd_parameters_from_bytes;
+    use image::{ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    /// NovelAI と同じく、列優先でアルファチャンネルの LSB に stealth pnginfo を埋め込んだPNG
+    fn stealth_png(json: &str) -> Vec<u8> {
+        let mut bytes = b"stealth_pnginfo".to_vec();
+        bytes.extend_from_slice(&((json.len() * 8) as u32).to_be_bytes());
+        bytes.extend_from_slice(json.as_bytes());
+
+        let mut image = RgbaImage::from_pixel(64, 64, Rgba([40, 80, 120, 255]));
+        let height = image.height();
+        let bits = bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1));
+        for (index, bit) in bits.enumerate() {
+            let pixel = image.get_pixel_mut(index as u32 / height, index as u32 % height);
+            pixel[3] = (pixel[3] & !1) | bit;
+        }
+
+        let mut data = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn rating_keeps_stealth_parameters() {
+        let directory =
+            std::env::temp_dir().join(format!("exif-handler-stealth-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("novelai.png");
+        let comment = r#"{"prompt": "1girl, smile", "uc": "lowres", "steps": 28, "seed": 1234}"#;
+        let json = serde_json::json!({ "Software": "NovelAI", "Comment": comment }).to_string();
+        std::fs::write(&path, stealth_png(&json)).unwrap();
+        let path = path.to_str().unwrap();
+        assert!(read_png_sd_parameters_from_bytes(&std::fs::read(path).unwrap()).is_some());
+
+        let history = OperationHistory::open(directory.join("history")).unwrap();
+        let operation = history.begin(OperationKind::WriteRating);
+        write_rating(path, 4, &operation).unwrap();
+
+        // レーティングの書き込みで EXIF / XMP のチャンクが追加されても stealth pnginfo を読む
+        let data = std::fs::read(path).unwrap();
+        assert_eq!(
+            read_exif_from_bytes(&data, FileFormat::Png).and_then(|exif_info| exif_info.rating),
+            Some(4)
+        );
+        let parameters = read_png_sd_parameters_from_bytes(&data).unwrap();
+        assert_eq!(parameters.positive_prompt, "1girl, smile");
+        assert_eq!(parameters.negative_prompt, "lowres");
+        assert_eq!(parameters.seed.as_deref(), Some("1234"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
 }
@@ -0,0 +1,150 @@
+// ファイル内容（マジックバイト）による画像形式判定用のモジュール
+//
+// 拡張子はブラウザ保存時に食い違うことが多いため（.png として保存された WebP 等）、
+// 常にファイル先頭のシグネチャを優先し、拡張子は判定できない場合のヒントとしてのみ使用する。
+use image::ImageFormat;
+use little_exif::filetype::FileExtension;
+
+/// 対応する画像形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    Png,
+    Jpeg,
+    WebP,
+    Avif,
+    Heif,
+    Gif,
+    Tiff,
+    Jxl,
+    Bmp,
+}
+
+impl FileFormat {
+    /// 先頭バイトのシグネチャから判定
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        match data {
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n', ..] => Some(Self::Png),
+            [0xff, 0xd8, 0xff, ..] => Some(Self::Jpeg),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Self::WebP),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(Self::Gif),
+            [b'I', b'I', 0x2a, 0x00, ..] | [b'M', b'M', 0x00, 0x2a, ..] => Some(Self::Tiff),
+            [0xff, 0x0a, ..] => Some(Self::Jxl),
+            [0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a, ..] => Some(Self::Jxl),
+            [b'B', b'M', ..] => Some(Self::Bmp),
+            [_, _, _, _, b'f', b't', b'y', b'p', ..] => Self::detect_isobmff(data),
+            _ => None,
+        }
+    }
+
+    /// ISOBMFF の ftyp ブランドから AVIF / HEIF を判定
+    fn detect_isobmff(data: &[u8]) -> Option<Self> {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let ftyp = data.get(8..size.min(data.len()))?;
+
+        // major_brand(4) + minor_version(4) + compatible_brands(4 x n)
+        let brands = ftyp
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(index, _)| *index != 1)
+            .map(|(_, brand)| brand);
+
+        let mut is_heif = false;
+        for brand in brands {
+            match brand {
+                b"avif" | b"avis" => return Some(Self::Avif),
+                b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" | b"mif1" | b"msf1" => {
+                    is_heif = true
+                }
+                _ => {}
+            }
+        }
+        is_heif.then_some(Self::Heif)
+    }
+
+    /// 拡張子から推定（判定のヒント用）
+    pub fn from_extension(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path)
+            .extension()?
+            .to_str()?
+            .to_lowercase();
+
+        match extension.as_str() {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" | "jfif" => Some(Self::Jpeg),
+            "webp" => Some(Self::WebP),
+            "avif" => Some(Self::Avif),
+            "heif" | "heic" => Some(Self::Heif),
+            "gif" => Some(Self::Gif),
+            "tif" | "tiff" => Some(Self::Tiff),
+            "jxl" => Some(Self::Jxl),
+            "bmp" => Some(Self::Bmp),
+            _ => None,
+        }
+    }
+
+    /// ファイル内容から判定し、判定できない場合のみ拡張子を使用
+    pub fn detect_with_hint(data: &[u8], path: &str) -> Result<Self, String> {
+        if let Some(format) = Self::detect(data) {
+            if let Some(hint) = Self::from_extension(path).filter(|hint| *hint != format) {
+                log::debug!(
+                    "拡張子と内容の形式が一致しません: {} ({:?} として扱います、拡張子: {:?})",
+                    path,
+                    format,
+                    hint
+                );
+            }
+            return Ok(format);
+        }
+
+        Self::from_extension(path).ok_or_else(|| format!("未対応のファイル形式です: {}", path))
+    }
+
+    /// little_exif のファイル形式（EXIF 非対応の形式は None）
+    pub fn to_exif_file_extension(self) -> Option<FileExtension> {
+        match self {
+            Self::Png => Some(FileExtension::PNG {
+                as_zTXt_chunk: false,
+            }),
+            Self::Jpeg => Some(FileExtension::JPEG),
+            Self::WebP => Some(FileExtension::WEBP),
+            Self::Heif | Self::Avif => Some(FileExtension::HEIF),
+            Self::Tiff => Some(FileExtension::TIFF),
+            Self::Jxl => Some(FileExtension::JXL),
+            Self::Gif | Self::Bmp => None,
+        }
+    }
+
+    /// XMP パケットの書き込みに対応しているか
+    pub fn supports_xmp_write(self) -> bool {
+        matches!(self, Self::Png | Self::Jpeg | Self::WebP)
+    }
+
+    /// image クレートの画像形式（デコード非対応の形式は None）
+    pub fn to_image_format(self) -> Option<ImageFormat> {
+        match self {
+            Self::Png => Some(ImageFormat::Png),
+            Self::Jpeg => Some(ImageFormat::Jpeg),
+            Self::WebP => Some(ImageFormat::WebP),
+            Self::Avif => Some(ImageFormat::Avif),
+            Self::Gif => Some(ImageFormat::Gif),
+            Self::Tiff => Some(ImageFormat::Tiff),
+            Self::Bmp => Some(ImageFormat::Bmp),
+            Self::Heif | Self::Jxl => None,
+        }
+    }
+
+    /// 形式名（"png" / "jpeg" 等）
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpeg",
+            Self::WebP => "webp",
+            Self::Avif => "avif",
+            Self::Heif => "heif",
+            Self::Gif => "gif",
+            Self::Tiff => "tiff",
+            Self::Jxl => "jxl",
+            Self::Bmp => "bmp",
+        }
+    }
+}
@@ -1,7 +1,54 @@
-use crate::image_types::FileSystemInfo;
+use crate::image_types::{
+    FileSystemInfo, ImageListEntry, ImageListOptions, ImageSortKey, SdSummary,
+};
+use crate::library_index::{IndexedImage, LibraryIndex};
+use crate::library_scan::{scan_image_paths, ScanProgressReporter};
+use crate::sd_parameters::SdParameters;
+use crate::thumbnail_handler::{ThumbnailHandler, ThumbnailState};
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fs;
+use std::iter::Peekable;
 use std::path::Path;
+use std::str::Chars;
 use std::time::UNIX_EPOCH;
+use tauri::{AppHandle, Runtime};
+
+/// 変更検出用のファイルの識別情報
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub file_size: u64,
+    /// 更新日時（UNIXエポックからのナノ秒）
+    pub modified: u64,
+    /// inode の変更日時（Unix のみ）
+    pub changed: Option<i64>,
+}
+
+impl FileStamp {
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |duration| duration.as_nanos() as u64);
+
+        // 更新日時を保持したメタデータ編集でも、置き換えたファイルの ctime は変わる
+        #[cfg(unix)]
+        let changed = {
+            use std::os::unix::fs::MetadataExt;
+            Some(metadata.ctime() * 1_000_000_000 + metadata.ctime_nsec())
+        };
+        #[cfg(not(unix))]
+        let changed = None;
+
+        Self {
+            file_size: metadata.len(),
+            modified,
+            changed,
+        }
+    }
+}
 
 /// ファイルシステム情報を読み込み
 #[tauri::command]
@@ -17,6 +64,14 @@ pub fn read_file_system_info(path: String) -> Result<FileSystemInfo, String> {
     let metadata =
         fs::metadata(&file_path).map_err(|e| format!("ファイル情報取得エラー: {}", e))?;
 
+    file_system_info(file_path, &metadata)
+}
+
+/// 取得済みのメタデータからファイルシステム情報を作成
+pub fn file_system_info(
+    file_path: &Path,
+    metadata: &fs::Metadata,
+) -> Result<FileSystemInfo, String> {
     // ファイル名を取得
     let filename = file_path
         .file_name()
@@ -34,14 +89,6 @@ pub fn read_file_system_info(path: String) -> Result<FileSystemInfo, String> {
     // ファイルサイズを取得
     let file_size_bytes = metadata.len();
 
-    // ファイル作成日時を取得（Unix timestamp）
-    let file_creation_timestamp = metadata
-        .created()
-        .map_err(|e| format!("作成日時取得エラー: {}", e))?
-        .duration_since(UNIX_EPOCH)
-        .map_err(|e| format!("タイムスタンプ変換エラー: {}", e))?
-        .as_secs();
-
     // 最終更新日時を取得（Unix timestamp）
     let file_modified_timestamp = metadata
         .modified()
@@ -50,6 +97,14 @@ pub fn read_file_system_info(path: String) -> Result<FileSystemInfo, String> {
         .map_err(|e| format!("タイムスタンプ変換エラー: {}", e))?
         .as_secs();
 
+    // ファイル作成日時を取得（Unix timestamp）
+    // 作成日時を記録しないファイルシステム・カーネルでは、画像を一覧から落とさずに更新日時で代用
+    let file_creation_timestamp = metadata
+        .created()
+        .ok()
+        .and_then(|created| created.duration_since(UNIX_EPOCH).ok())
+        .map_or(file_modified_timestamp, |duration| duration.as_secs());
+
     Ok(FileSystemInfo {
         filename,
         parent_dir,
@@ -58,3 +113,252 @@ pub fn read_file_system_info(path: String) -> Result<FileSystemInfo, String> {
         file_modified_timestamp,
     })
 }
+
+/// ディレクトリ内の画像一覧を取得（Tauri API）
+///
+/// 対応形式の画像ごとに、ファイル情報・幅と高さ・レーティングとSD Parametersの概要を1回で返す。
+/// 情報はメタデータインデックスから取得し、追加・変更された画像だけを読み込んでインデックスを更新する。
+/// `recursive` の場合はサブディレクトリの画像もまとめて返し、
+/// 走査の進捗を `library-scan-progress` イベントで通知する。
+#[tauri::command]
+pub async fn list_images_in_directory<R: Runtime>(
+    directory: String,
+    options: Option<ImageListOptions>,
+    app: AppHandle<R>,
+    state: tauri::State<'_, ThumbnailState>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<Vec<ImageListEntry>, String> {
+    let options = options.unwrap_or_default();
+    let progress = ScanProgressReporter::new(app, &directory);
+    let images = index_images(
+        Path::new(&directory),
+        &options,
+        &progress,
+        &state.handler,
+        &index,
+    )?;
+
+    let mut entries: Vec<ImageListEntry> = images.into_iter().map(image_list_entry).collect();
+    sort_image_list(&mut entries, options.sort_by, options.descending);
+    Ok(entries)
+}
+
+/// ディレクトリを走査してメタデータインデックスを更新（Tauri API）
+///
+/// `search_images` はインデックスだけを検索するため、検索の前や再走査が必要な時に呼び出す。
+/// 走査の進捗は `library-scan-progress` イベントで通知し、対象の画像の数を返す。
+#[tauri::command]
+pub async fn update_library_index<R: Runtime>(
+    directory: String,
+    options: Option<ImageListOptions>,
+    app: AppHandle<R>,
+    state: tauri::State<'_, ThumbnailState>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<usize, String> {
+    let options = options.unwrap_or_default();
+    let progress = ScanProgressReporter::new(app, &directory);
+    let images = index_images(
+        Path::new(&directory),
+        &options,
+        &progress,
+        &state.handler,
+        &index,
+    )?;
+    Ok(images.len())
+}
+
+/// ディレクトリを走査し、対象の画像の情報をメタデータインデックスを更新しながら取得
+///
+/// 追加・変更された画像だけを読み込み、ファイルが無くなった項目はインデックスから削除する。
+pub fn index_images<R: Runtime>(
+    root: &Path,
+    options: &ImageListOptions,
+    progress: &ScanProgressReporter<R>,
+    handler: &ThumbnailHandler,
+    index: &LibraryIndex,
+) -> Result<Vec<IndexedImage>, String> {
+    let image_paths = scan_image_paths(root, options, progress)?;
+
+    // インデックスを読めない場合も、全画像を読み込んで一覧は返す
+    let mut indexed = index
+        .load_directory(root, options.recursive)
+        .unwrap_or_else(|e| {
+            log::warn!("{}", e);
+            HashMap::new()
+        });
+
+    let images: Vec<(IndexedImage, bool)> = handler.thread_pool().install(|| {
+        image_paths
+            .par_iter()
+            .filter_map(|path| {
+                let indexed_image = indexed.get(path.to_string_lossy().as_ref());
+                let image = read_indexed_image(path, indexed_image, handler);
+                progress.image_processed();
+                match image {
+                    Ok(image) => Some(image),
+                    Err(e) => {
+                        log::warn!("画像情報の取得に失敗: {} ({})", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect()
+    });
+    progress.finish();
+
+    // 走査で見つからなかった項目のうち、ファイルが無くなったものを削除
+    // （フィルタで除外されただけの画像や、階層の深さの上限より下の画像は残す）
+    for image in &images {
+        indexed.remove(&image.0.path);
+    }
+    let removed_paths: Vec<String> = indexed
+        .into_keys()
+        .filter(|path| !Path::new(path).exists())
+        .collect();
+    let updated_images: Vec<IndexedImage> = images
+        .iter()
+        .filter(|(_, updated)| *updated)
+        .map(|(image, _)| image.clone())
+        .collect();
+    log::debug!(
+        "インデックスを更新: {}件更新, {}件削除",
+        updated_images.len(),
+        removed_paths.len()
+    );
+    if let Err(e) = index
+        .upsert(&updated_images)
+        .and_then(|_| index.remove(&removed_paths))
+    {
+        log::warn!("{}", e);
+    }
+
+    Ok(images.into_iter().map(|(image, _)| image).collect())
+}
+
+/// インデックスの項目が最新ならそのまま使い、無い・古い場合は画像を読み込む（読み込んだ場合は true）
+fn read_indexed_image(
+    path: &Path,
+    indexed_image: Option<&IndexedImage>,
+    handler: &ThumbnailHandler,
+) -> Result<(IndexedImage, bool), String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("ファイル情報取得エラー: {}", e))?;
+    match indexed_image {
+        Some(image) if image.is_fresh(&metadata) => Ok((image.clone(), false)),
+        _ => IndexedImage::read(path, &metadata, handler).map(|image| (image, true)),
+    }
+}
+
+/// インデックスの項目から画像一覧の1項目を作成
+pub fn image_list_entry(image: IndexedImage) -> ImageListEntry {
+    ImageListEntry {
+        rating: image.rating(),
+        sd_summary: image.sd_parameters.as_ref().map(sd_summary),
+        path: image.path,
+        file_system_info: image.file_system_info,
+        dimensions: image.dimensions,
+    }
+}
+
+/// SD Parametersの概要を作成
+fn sd_summary(parameters: &SdParameters) -> SdSummary {
+    let values = parameters.typed_values();
+
+    SdSummary {
+        model: parameters.model.clone(),
+        sampler: parameters.sampler.clone(),
+        steps: values.steps,
+        cfg_scale: values.cfg_scale,
+        seed: values.seed,
+    }
+}
+
+/// 画像一覧を並び替え（同じ値の場合はファイル名順）
+pub fn sort_image_list(images: &mut [ImageListEntry], sort_by: ImageSortKey, descending: bool) {
+    let seed = |entry: &ImageListEntry| entry.sd_summary.as_ref().and_then(|sd| sd.seed);
+    let steps = |entry: &ImageListEntry| entry.sd_summary.as_ref().and_then(|sd| sd.steps);
+
+    images.sort_by(|a, b| {
+        let (a_info, b_info) = (&a.file_system_info, &b.file_system_info);
+        let ordering = match sort_by {
+            ImageSortKey::Name => {
+                directed(natural_cmp(&a_info.filename, &b_info.filename), descending)
+            }
+            ImageSortKey::Modified => directed(
+                a_info
+                    .file_modified_timestamp
+                    .cmp(&b_info.file_modified_timestamp),
+                descending,
+            ),
+            ImageSortKey::Size => directed(
+                a_info.file_size_bytes.cmp(&b_info.file_size_bytes),
+                descending,
+            ),
+            ImageSortKey::Rating => compare_present(a.rating, b.rating, descending),
+            ImageSortKey::Seed => compare_present(seed(a), seed(b), descending),
+            ImageSortKey::Steps => compare_present(steps(a), steps(b), descending),
+        };
+        ordering.then_with(|| natural_cmp(&a_info.filename, &b_info.filename))
+    });
+}
+
+/// 降順の場合は比較結果を反転
+fn directed(ordering: Ordering, descending: bool) -> Ordering {
+    if descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+/// 値の無い項目は並び順に関わらず末尾に置く
+fn compare_present<T: Ord>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => directed(a.cmp(&b), descending),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+/// 数字部分を数値として比較するファイル名の比較（"2.png" < "10.png"、大文字小文字は区別しない）
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        let ordering = match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            // 数値として同じ（"01" と "1"）場合は元の文字列で決める
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_digits = take_digits(&mut a_chars);
+                let y_digits = take_digits(&mut b_chars);
+                let x_number = x_digits.trim_start_matches('0');
+                let y_number = y_digits.trim_start_matches('0');
+                x_number
+                    .len()
+                    .cmp(&y_number.len())
+                    .then_with(|| x_number.cmp(y_number))
+            }
+            (Some(x), Some(y)) => {
+                a_chars.next();
+                b_chars.next();
+                x.to_lowercase().cmp(y.to_lowercase())
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// 連続する数字を取り出す
+fn take_digits(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        digits.push(digit);
+    }
+    digits
+}
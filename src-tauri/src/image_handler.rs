@@ -1,7 +1,15 @@
-use crate::exif_handler::read_exif_image_info;
+use crate::curation_handler::read_curation_from_bytes;
+use crate::exif_handler::read_exif_from_bytes;
+use crate::file_format::FileFormat;
 use crate::file_system_handler::read_file_system_info;
-use crate::image_types::ComprehensiveImageInfo;
-use crate::png_handler::read_png_image_info;
+use crate::image_metadata::{read_isobmff_image_properties, read_sd_parameters_from_bytes};
+use crate::image_types::{ComprehensiveImageInfo, ImageInfo};
+use crate::png_handler::read_png_details_from_bytes;
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, ImageDecoder, ImageFormat, ImageReader};
+use std::io::Cursor;
+use std::path::Path;
 
 /// バイトデータから画像を読み込み（中核処理）
 pub fn load_image_from_bytes(data: &[u8]) -> Result<image::DynamicImage, String> {
@@ -9,16 +17,108 @@ pub fn load_image_from_bytes(data: &[u8]) -> Result<image::DynamicImage, String>
         .map_err(|e| format!("バイトデータからの画像読み込みエラー: {}", e))
 }
 
+/// バイトデータから形式に依存しない画像情報を読み取り（中核処理）
+///
+/// 画素データはデコードせず、ヘッダー部分のみを解析する。
+pub fn read_image_info_from_bytes(data: &[u8]) -> Result<ImageInfo, String> {
+    let file_format = FileFormat::detect(data)
+        .ok_or_else(|| "未対応のファイル形式です（シグネチャを判定できません）".to_string())?;
+    let format_name = file_format.name().to_string();
+    let format = file_format
+        .to_image_format()
+        .ok_or_else(|| format!("未対応のファイル形式です: {}", format_name))?;
+
+    // AVIF はデコーダーが無効なため ISOBMFF のプロパティから取得
+    if format == ImageFormat::Avif {
+        let properties = read_isobmff_image_properties(data)
+            .ok_or_else(|| "AVIF解析エラー: 画像プロパティが見つかりません".to_string())?;
+        return Ok(ImageInfo {
+            format: format_name,
+            width: properties.width,
+            height: properties.height,
+            color_type: if properties.has_alpha { "Rgba" } else { "Rgb" }.to_string(),
+            bit_depth: properties.bit_depth.unwrap_or(8),
+            has_alpha: properties.has_alpha,
+            is_animated: properties.is_animated,
+            has_icc_profile: properties.has_icc_profile,
+            png: None,
+        });
+    }
+
+    let mut decoder = ImageReader::with_format(Cursor::new(data), format)
+        .into_decoder()
+        .map_err(|e| format!("画像解析エラー: {}", e))?;
+    let (width, height) = decoder.dimensions();
+    let color_type = decoder.original_color_type();
+    let has_alpha = decoder.color_type().has_alpha();
+    let has_icc_profile = decoder.icc_profile().ok().flatten().is_some();
+
+    let png = if format == ImageFormat::Png {
+        Some(read_png_details_from_bytes(data)?)
+    } else {
+        None
+    };
+
+    let is_animated = match format {
+        ImageFormat::Png => png
+            .as_ref()
+            .and_then(|png| png.animation_frames)
+            .is_some_and(|frames| 1 < frames),
+        ImageFormat::WebP => {
+            WebPDecoder::new(Cursor::new(data)).is_ok_and(|decoder| decoder.has_animation())
+        }
+        // GIF はフレーム数を数える必要があるため2フレーム目の有無のみ確認
+        ImageFormat::Gif => GifDecoder::new(Cursor::new(data))
+            .is_ok_and(|decoder| 1 < decoder.into_frames().take(2).count()),
+        _ => false,
+    };
+
+    Ok(ImageInfo {
+        format: format_name,
+        width,
+        height,
+        color_type: format!("{:?}", color_type),
+        bit_depth: (color_type.bits_per_pixel() / color_type.channel_count().max(1) as u16) as u8,
+        has_alpha,
+        is_animated,
+        has_icc_profile,
+        png,
+    })
+}
+
+/// 画像ファイルの幅・高さを取得（画素データはデコードせず、ヘッダーのみ読み込む）
+pub fn read_image_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let reader = ImageReader::open(path).ok()?.with_guessed_format().ok()?;
+    match reader.format() {
+        // AVIF / HEIF は ISOBMFF のプロパティから取得
+        Some(ImageFormat::Avif) | None => {
+            let data = std::fs::read(path).ok()?;
+            read_isobmff_image_properties(&data)
+                .map(|properties| (properties.width, properties.height))
+        }
+        Some(_) => reader.into_dimensions().ok(),
+    }
+}
+
 /// 画像の全情報を読み込み（Tauri API）
 #[tauri::command]
 pub fn read_comprehensive_image_info(path: String) -> Result<ComprehensiveImageInfo, String> {
     let file_system_info = read_file_system_info(path.clone())?;
-    let png_info = read_png_image_info(path.clone())?;
-    let exif_info = read_exif_image_info(path)?;
+    let data = std::fs::read(&path).map_err(|e| format!("ファイル読み込みエラー: {}", e))?;
+    let format = FileFormat::detect_with_hint(&data, &path)?;
+    let image_info = read_image_info_from_bytes(&data)?;
+    let exif_info = read_exif_from_bytes(&data, format);
+    // SD Parameters はここでのみ解析する（image_info 側では stealth pnginfo をデコードしない）
+    let sd_parameters = read_sd_parameters_from_bytes(&data);
 
     Ok(ComprehensiveImageInfo {
         file_system_info,
-        png_info,
+        image_info,
         exif_info,
+        curation: read_curation_from_bytes(&data),
+        sd_values: sd_parameters
+            .as_ref()
+            .map(crate::sd_parameters::SdParameters::typed_values),
+        sd_parameters,
     })
 }
@@ -0,0 +1,1094 @@
+// 画像形式に依存しないメタデータ（EXIF / XMP）抽出用のモジュール
+//
+// JPEG / WebP / AVIF に A1111・Forge が埋め込む EXIF UserComment や、
+// XMP に格納された生成パラメーターを読み取る。PNG はテキストチャンクを優先する。
+// また、レーティング等の XMP パケットを PNG / JPEG / WebP に書き戻す。
+use crate::comfyui_parameters::parse_comfyui_chunks;
+use crate::file_format::FileFormat;
+use crate::png_chunks::{PngChunk, PngChunkFile, PngTextChunkKind};
+use crate::png_handler::collect_text_entries;
+use crate::sd_parameters::SdParameters;
+use crate::xmp;
+use sha2::{Digest, Sha256};
+
+/// JPEG APP1 の EXIF 識別子
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+/// JPEG APP1 の XMP 識別子
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// JPEG セグメントのデータ部の最大長（長さフィールドの2バイトを除く）
+const JPEG_SEGMENT_MAX: usize = 65533;
+
+/// WebP VP8X のフラグ
+const VP8X_FLAG_ALPHA: u8 = 0x10;
+const VP8X_FLAG_EXIF: u8 = 0x08;
+const VP8X_FLAG_XMP: u8 = 0x04;
+
+/// TIFF タグ: ImageDescription
+const TAG_IMAGE_DESCRIPTION: u16 = 0x010e;
+/// TIFF タグ: Exif IFD へのポインタ
+const TAG_EXIF_IFD_POINTER: u16 = 0x8769;
+/// EXIF タグ: UserComment
+const TAG_USER_COMMENT: u16 = 0x9286;
+
+/// WebP のメタデータチャンク（VP8X はメタデータの有無のフラグを含むため一緒に扱う）
+const WEBP_METADATA_CHUNKS: [[u8; 4]; 3] = [*b"VP8X", *b"EXIF", *b"XMP "];
+/// スナップショットで JPEG の APP1 セグメントを表すタグ
+const JPEG_APP1_TAG: [u8; 4] = *b"APP1";
+
+/// メタデータのブロック（PNG のチャンク・JPEG の APP1 セグメント・WebP のチャンク）
+#[derive(Debug, Clone)]
+pub struct MetadataBlock {
+    /// チャンク種類（JPEG は "APP1"）
+    pub tag: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+/// 画像に埋め込まれた EXIF（TIFF 構造）と XMP パケット
+#[derive(Debug, Default)]
+pub struct EmbeddedMetadata {
+    /// TIFF ヘッダーから始まる EXIF データ
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<String>,
+}
+
+/// バイトデータから画像形式を判別して EXIF / XMP を取り出す
+pub fn extract_embedded_metadata(data: &[u8]) -> EmbeddedMetadata {
+    match FileFormat::detect(data) {
+        Some(FileFormat::Png) => extract_from_png(data),
+        Some(FileFormat::Jpeg) => extract_from_jpeg(data),
+        Some(FileFormat::WebP) => extract_from_webp(data),
+        Some(FileFormat::Avif | FileFormat::Heif) => extract_from_isobmff(data),
+        _ => EmbeddedMetadata {
+            exif: None,
+            xmp: xmp::find_xmp_packet(data),
+        },
+    }
+}
+
+/// バイトデータからSD Parametersを読み取り（形式非依存）
+///
+/// PNG はテキストチャンク → stealth pnginfo、それ以外は
+/// EXIF UserComment → EXIF の ComfyUI 文字列 → XMP の順に検索する。
+pub fn read_sd_parameters_from_bytes(data: &[u8]) -> Option<SdParameters> {
+    if FileFormat::detect(data) == Some(FileFormat::Png) {
+        return crate::png_handler::read_png_sd_parameters_from_bytes(data);
+    }
+
+    let metadata = extract_embedded_metadata(data);
+    let from_exif = metadata.exif.as_deref().and_then(|tiff| {
+        read_user_comment(tiff)
+            .and_then(|comment| parse_a1111_text(&comment))
+            .or_else(|| read_comfyui_exif(tiff))
+    });
+
+    from_exif.or_else(|| {
+        let packet = metadata.xmp.as_deref()?;
+        [
+            "exif:UserComment",
+            "dc:description",
+            "tiff:ImageDescription",
+        ]
+        .iter()
+        .filter_map(|name| xmp::find_property(packet, name))
+        .find_map(|text| parse_a1111_text(&text))
+    })
+}
+
+/// A1111 形式としてパース（設定行が無いテキストは無関係なコメントとみなして除外）
+fn parse_a1111_text(text: &str) -> Option<SdParameters> {
+    SdParameters::parse(text)
+        .ok()
+        .filter(|params| !params.settings.is_empty())
+}
+
+/// XMP パケットを画像に書き込んだバイトデータを返す（既存のパケットは置き換え）
+///
+/// PNG は iTXt チャンク、JPEG は APP1 セグメント、WebP は "XMP " チャンクに格納する。
+pub fn write_xmp_packet(data: &[u8], xmp: &str) -> Result<Vec<u8>, String> {
+    let packet = xmp::wrap_packet(xmp);
+    match FileFormat::detect(data) {
+        Some(FileFormat::Png) => write_xmp_to_png(data, &packet),
+        Some(FileFormat::Jpeg) => write_xmp_to_jpeg(data, &packet),
+        Some(FileFormat::WebP) => write_xmp_to_webp(data, &packet),
+        format => Err(format!(
+            "XMPの書き込みに対応していない形式です: {}",
+            format.map_or("unknown", FileFormat::name)
+        )),
+    }
+}
+
+/// メタデータ部分のスナップショットを作成（元に戻す用、画素データは含まない）
+///
+/// PNG はテキスト・eXIf チャンク、JPEG は EXIF / XMP の APP1 セグメント、
+/// WebP は VP8X・EXIF・"XMP " チャンクを保存する。
+pub fn snapshot_metadata(data: &[u8]) -> Result<Vec<MetadataBlock>, String> {
+    let blocks = match FileFormat::detect(data) {
+        Some(FileFormat::Png) => PngChunkFile::parse(data)?
+            .chunks
+            .into_iter()
+            .filter(is_png_metadata_chunk)
+            .map(|chunk| MetadataBlock {
+                tag: chunk.chunk_type,
+                data: chunk.data,
+            })
+            .collect(),
+        Some(FileFormat::Jpeg) => jpeg_segments(data)?
+            .0
+            .iter()
+            .filter(|segment| segment.is_exif() || segment.is_xmp())
+            .map(|segment| MetadataBlock {
+                tag: JPEG_APP1_TAG,
+                data: segment.body.to_vec(),
+            })
+            .collect(),
+        Some(FileFormat::WebP) => webp_chunks(data)?
+            .into_iter()
+            .filter(|(fourcc, _)| WEBP_METADATA_CHUNKS.contains(fourcc))
+            .map(|(tag, chunk)| MetadataBlock {
+                tag,
+                data: chunk.to_vec(),
+            })
+            .collect(),
+        format => {
+            return Err(format!(
+                "メタデータの保存に対応していない形式です: {}",
+                format.map_or("unknown", FileFormat::name)
+            ))
+        }
+    };
+    Ok(blocks)
+}
+
+/// メタデータ部分をスナップショットの内容に置き換えたバイトデータを返す（画素データ等は保持）
+pub fn restore_metadata(data: &[u8], blocks: &[MetadataBlock]) -> Result<Vec<u8>, String> {
+    match FileFormat::detect(data) {
+        Some(FileFormat::Png) => {
+            let mut png = PngChunkFile::parse(data)?;
+            png.chunks.retain(|chunk| !is_png_metadata_chunk(chunk));
+            // eXIf は IDAT より前に置く必要があるため、テキストも含めて最初の IDAT の直前に挿入
+            let position = png
+                .chunks
+                .iter()
+                .position(|chunk| chunk.is_type(b"IDAT"))
+                .unwrap_or(png.chunks.len().saturating_sub(1));
+            png.chunks.splice(
+                position..position,
+                blocks
+                    .iter()
+                    .map(|block| PngChunk::new(block.tag, block.data.clone())),
+            );
+            Ok(png.to_bytes())
+        }
+        Some(FileFormat::Jpeg) => {
+            let (segments, image_start) = jpeg_segments(data)?;
+            let restored: Vec<u8> = blocks
+                .iter()
+                .filter(|block| block.tag == JPEG_APP1_TAG)
+                .flat_map(|block| jpeg_app1_segment(&block.data))
+                .collect();
+
+            let mut out = Vec::with_capacity(data.len() + restored.len());
+            out.extend_from_slice(&data[..2]);
+            let mut inserted = false;
+            for segment in segments
+                .iter()
+                .filter(|segment| !segment.is_exif() && !segment.is_xmp())
+            {
+                if !inserted && segment.marker != 0xe0 {
+                    out.extend_from_slice(&restored);
+                    inserted = true;
+                }
+                out.extend_from_slice(segment.bytes);
+            }
+            if !inserted {
+                out.extend_from_slice(&restored);
+            }
+            out.extend_from_slice(&data[image_start..]);
+            Ok(out)
+        }
+        Some(FileFormat::WebP) => {
+            let mut chunks: Vec<([u8; 4], Vec<u8>)> = webp_chunks(data)?
+                .into_iter()
+                .filter(|(fourcc, _)| !WEBP_METADATA_CHUNKS.contains(fourcc))
+                .map(|(fourcc, chunk)| (fourcc, chunk.to_vec()))
+                .collect();
+            // VP8X は先頭、EXIF / XMP は末尾に配置
+            for block in blocks {
+                let chunk = (block.tag, block.data.clone());
+                if &block.tag == b"VP8X" {
+                    chunks.insert(0, chunk);
+                } else {
+                    chunks.push(chunk);
+                }
+            }
+            build_webp(&chunks)
+        }
+        format => Err(format!(
+            "メタデータの復元に対応していない形式です: {}",
+            format.map_or("unknown", FileFormat::name)
+        )),
+    }
+}
+
+/// メタデータ以外（画素データ等）のハッシュ
+///
+/// スナップショット対象のメタデータのみが異なるファイルは同じ値になる。
+/// 対応していない形式や解析できないファイルはファイル全体のハッシュを返す。
+pub fn image_data_digest(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    let hashed = match FileFormat::detect(data) {
+        Some(FileFormat::Png) => PngChunkFile::parse(data).ok().map(|png| {
+            for chunk in png
+                .chunks
+                .iter()
+                .filter(|chunk| !is_png_metadata_chunk(chunk))
+            {
+                hasher.update(chunk.chunk_type);
+                hasher.update(&chunk.data);
+            }
+        }),
+        Some(FileFormat::Jpeg) => jpeg_segments(data).ok().map(|(segments, image_start)| {
+            for segment in segments
+                .iter()
+                .filter(|segment| !segment.is_exif() && !segment.is_xmp())
+            {
+                hasher.update(segment.bytes);
+            }
+            hasher.update(&data[image_start..]);
+        }),
+        Some(FileFormat::WebP) => webp_chunks(data).ok().map(|chunks| {
+            for (fourcc, chunk) in chunks
+                .iter()
+                .filter(|(fourcc, _)| !WEBP_METADATA_CHUNKS.contains(fourcc))
+            {
+                hasher.update(fourcc);
+                hasher.update(chunk);
+            }
+        }),
+        _ => None,
+    };
+    if hashed.is_none() {
+        hasher = Sha256::new();
+        hasher.update(data);
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// PNG のメタデータチャンク（テキスト・eXIf）かどうか
+fn is_png_metadata_chunk(chunk: &PngChunk) -> bool {
+    chunk.is_text() || chunk.is_type(b"eXIf")
+}
+
+/// PNG の eXIf チャンクと XMP の iTXt チャンクを取り出す
+fn extract_from_png(data: &[u8]) -> EmbeddedMetadata {
+    let decoder = png::Decoder::new(std::io::Cursor::new(data));
+    let Ok(reader) = decoder.read_info() else {
+        return EmbeddedMetadata::default();
+    };
+    let info = reader.info();
+
+    EmbeddedMetadata {
+        exif: info.exif_metadata.as_ref().map(|exif| exif.to_vec()),
+        xmp: collect_text_entries(info)
+            .into_iter()
+            .find(|entry| entry.keyword == xmp::PNG_XMP_KEYWORD)
+            .and_then(|entry| xmp::find_xmp_packet(entry.text.as_bytes())),
+    }
+}
+
+/// PNG の XMP を非圧縮 iTXt チャンクとして差し替え（他のチャンクは保持）
+fn write_xmp_to_png(data: &[u8], packet: &str) -> Result<Vec<u8>, String> {
+    let mut png = PngChunkFile::parse(data)?;
+    let chunk = PngChunk::new_text(
+        PngTextChunkKind::InternationalText { compressed: false },
+        xmp::PNG_XMP_KEYWORD,
+        packet,
+    )?;
+    png.replace_text_chunk(xmp::PNG_XMP_KEYWORD, chunk);
+    Ok(png.to_bytes())
+}
+
+/// JPEG の XMP APP1 セグメントを差し替え
+///
+/// 既存の XMP セグメントは削除し、先頭の APP0（JFIF）・EXIF APP1 の直後に挿入する。
+fn write_xmp_to_jpeg(data: &[u8], packet: &str) -> Result<Vec<u8>, String> {
+    let mut body = XMP_HEADER.to_vec();
+    body.extend_from_slice(packet.as_bytes());
+    if JPEG_SEGMENT_MAX < body.len() {
+        return Err("XMPパケットがJPEGセグメントの上限を超えています".to_string());
+    }
+    let segment = jpeg_app1_segment(&body);
+
+    let (segments, image_start) = jpeg_segments(data)?;
+    let mut out = Vec::with_capacity(data.len() + segment.len());
+    out.extend_from_slice(&data[..2]);
+    let mut inserted = false;
+
+    for current in &segments {
+        let is_leading = current.marker == 0xe0 || current.is_exif();
+        if !inserted && !is_leading {
+            out.extend_from_slice(&segment);
+            inserted = true;
+        }
+        if !current.is_xmp() {
+            out.extend_from_slice(current.bytes);
+        }
+    }
+
+    if !inserted {
+        out.extend_from_slice(&segment);
+    }
+    out.extend_from_slice(&data[image_start..]);
+    Ok(out)
+}
+
+/// JPEG のマーカーセグメント
+struct JpegSegment<'a> {
+    marker: u8,
+    /// 長さフィールドより後のデータ（長さを持たないマーカーは空）
+    body: &'a [u8],
+    /// マーカーを含むセグメント全体
+    bytes: &'a [u8],
+}
+
+impl JpegSegment<'_> {
+    fn is_exif(&self) -> bool {
+        self.marker == 0xe1 && self.body.starts_with(EXIF_HEADER)
+    }
+
+    fn is_xmp(&self) -> bool {
+        self.marker == 0xe1 && self.body.starts_with(XMP_HEADER)
+    }
+}
+
+/// SOI の後から SOS の手前までのセグメントを列挙し、画像データの開始位置と共に返す
+fn jpeg_segments(data: &[u8]) -> Result<(Vec<JpegSegment<'_>>, usize), String> {
+    let mut segments = Vec::new();
+    let mut pos = 2;
+
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xff {
+            break;
+        }
+        let marker = data[pos + 1];
+        // フィルバイト・長さを持たないマーカー
+        if marker == 0xff || marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            let step = if marker == 0xff { 1 } else { 2 };
+            segments.push(JpegSegment {
+                marker,
+                body: &[],
+                bytes: &data[pos..pos + step],
+            });
+            pos += step;
+            continue;
+        }
+        // SOS 以降は画像データ
+        if marker == 0xda || marker == 0xd9 {
+            break;
+        }
+
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let end = pos + 2 + length;
+        let body = data
+            .get(pos + 4..end)
+            .ok_or_else(|| "JPEGセグメントの長さが不正です".to_string())?;
+        segments.push(JpegSegment {
+            marker,
+            body,
+            bytes: &data[pos..end],
+        });
+        pos = end;
+    }
+
+    Ok((segments, pos))
+}
+
+/// APP1 セグメント（マーカー + 長さ + データ）を作成
+fn jpeg_app1_segment(body: &[u8]) -> Vec<u8> {
+    let mut segment = vec![0xff, 0xe1];
+    segment.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+    segment.extend_from_slice(body);
+    segment
+}
+
+/// WebP の "XMP " チャンクを差し替え
+///
+/// 拡張形式（VP8X）でない場合は、画像データからキャンバスサイズを求めて VP8X チャンクを追加する。
+fn write_xmp_to_webp(data: &[u8], packet: &str) -> Result<Vec<u8>, String> {
+    let mut chunks: Vec<([u8; 4], Vec<u8>)> = webp_chunks(data)?
+        .into_iter()
+        .filter(|(fourcc, _)| fourcc != b"XMP ")
+        .map(|(fourcc, chunk)| (fourcc, chunk.to_vec()))
+        .collect();
+
+    if !chunks.iter().any(|(fourcc, _)| fourcc == b"VP8X") {
+        let vp8x = new_vp8x_chunk(&chunks)
+            .ok_or_else(|| "WebPのキャンバスサイズを取得できません".to_string())?;
+        chunks.insert(0, (*b"VP8X", vp8x));
+    }
+    if let Some((_, vp8x)) = chunks.iter_mut().find(|(fourcc, _)| fourcc == b"VP8X") {
+        if let Some(flags) = vp8x.first_mut() {
+            *flags |= VP8X_FLAG_XMP;
+        }
+    }
+    chunks.push((*b"XMP ", packet.as_bytes().to_vec()));
+
+    build_webp(&chunks)
+}
+
+/// WebP のチャンク（FourCC とデータ）
+type WebPChunk<'a> = ([u8; 4], &'a [u8]);
+
+/// WebP のチャンク列を分解（不正な長さがあればエラー）
+fn webp_chunks(data: &[u8]) -> Result<Vec<WebPChunk<'_>>, String> {
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while pos + 8 <= data.len() {
+        let fourcc = [data[pos], data[pos + 1], data[pos + 2], data[pos + 3]];
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+            as usize;
+        let chunk = data
+            .get(pos + 8..pos + 8 + size)
+            .ok_or_else(|| "WebPチャンクの長さが不正です".to_string())?;
+        chunks.push((fourcc, chunk));
+        pos += 8 + size + (size & 1);
+    }
+    Ok(chunks)
+}
+
+/// チャンク列から WebP ファイルを組み立て
+fn build_webp(chunks: &[([u8; 4], Vec<u8>)]) -> Result<Vec<u8>, String> {
+    let mut body = b"WEBP".to_vec();
+    for (fourcc, chunk) in chunks {
+        body.extend_from_slice(fourcc);
+        body.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        body.extend_from_slice(chunk);
+        if chunk.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    let riff_size =
+        u32::try_from(body.len()).map_err(|_| "WebPファイルが大きすぎます".to_string())?;
+
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&riff_size.to_le_bytes());
+    out.extend(body);
+    Ok(out)
+}
+
+/// 単純形式（VP8 / VP8L）の WebP から VP8X チャンクを作成
+fn new_vp8x_chunk(chunks: &[([u8; 4], Vec<u8>)]) -> Option<Vec<u8>> {
+    let (width, height, has_alpha) = chunks.iter().find_map(|(fourcc, chunk)| match fourcc {
+        // キーフレームヘッダー（3バイト）+ スタートコード（3バイト）の後に14ビットの幅・高さ
+        b"VP8 " => {
+            let width = u16::from_le_bytes([*chunk.get(6)?, *chunk.get(7)?]) & 0x3fff;
+            let height = u16::from_le_bytes([*chunk.get(8)?, *chunk.get(9)?]) & 0x3fff;
+            Some((width as u32, height as u32, false))
+        }
+        // シグネチャ（1バイト）の後に 幅-1（14ビット）・高さ-1（14ビット）・アルファ（1ビット）
+        b"VP8L" => {
+            let bits = u32::from_le_bytes(chunk.get(1..5)?.try_into().ok()?);
+            let width = (bits & 0x3fff) + 1;
+            let height = ((bits >> 14) & 0x3fff) + 1;
+            Some((width, height, bits >> 28 & 1 == 1))
+        }
+        _ => None,
+    })?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let has_chunk = |name: &[u8; 4]| chunks.iter().any(|(fourcc, _)| fourcc == name);
+    let mut flags = 0;
+    if has_alpha || has_chunk(b"ALPH") {
+        flags |= VP8X_FLAG_ALPHA;
+    }
+    if has_chunk(b"EXIF") {
+        flags |= VP8X_FLAG_EXIF;
+    }
+
+    // フラグ（1バイト）+ 予約（3バイト）+ キャンバス幅-1・高さ-1（各24ビット）
+    let mut vp8x = vec![flags, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    Some(vp8x)
+}
+
+/// JPEG の APP1 セグメントから EXIF / XMP を取り出す
+fn extract_from_jpeg(data: &[u8]) -> EmbeddedMetadata {
+    let mut metadata = EmbeddedMetadata::default();
+    let mut pos = 2;
+
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xff {
+            break;
+        }
+        let marker = data[pos + 1];
+        // フィルバイト・長さを持たないマーカー
+        if marker == 0xff || marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+            pos += if marker == 0xff { 1 } else { 2 };
+            continue;
+        }
+        // SOS 以降は画像データ
+        if marker == 0xda || marker == 0xd9 {
+            break;
+        }
+
+        let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let Some(segment) = data.get(pos + 4..pos + 2 + length) else {
+            break;
+        };
+        if marker == 0xe1 {
+            if let Some(tiff) = segment.strip_prefix(EXIF_HEADER) {
+                metadata.exif.get_or_insert_with(|| tiff.to_vec());
+            } else if let Some(packet) = segment.strip_prefix(XMP_HEADER) {
+                if metadata.xmp.is_none() {
+                    metadata.xmp = xmp::find_xmp_packet(packet);
+                }
+            }
+        }
+        pos += 2 + length;
+    }
+
+    metadata
+}
+
+/// WebP の EXIF / "XMP " チャンクを取り出す
+fn extract_from_webp(data: &[u8]) -> EmbeddedMetadata {
+    let mut metadata = EmbeddedMetadata::default();
+    let mut pos = 12;
+
+    while pos + 8 <= data.len() {
+        let fourcc = &data[pos..pos + 4];
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+            as usize;
+        let Some(chunk) = data.get(pos + 8..pos + 8 + size) else {
+            break;
+        };
+        match fourcc {
+            // 仕様上は TIFF ヘッダーから始まるが、"Exif\0\0" を付けて書き込むツールもある
+            b"EXIF" => {
+                metadata.exif = Some(chunk.strip_prefix(EXIF_HEADER).unwrap_or(chunk).to_vec())
+            }
+            b"XMP " => metadata.xmp = xmp::find_xmp_packet(chunk),
+            _ => {}
+        }
+        // チャンクは偶数バイト境界に揃えられる
+        pos += 8 + size + (size & 1);
+    }
+
+    metadata
+}
+
+/// ISOBMFF（AVIF / HEIF）のボックス
+struct IsoBox<'a> {
+    box_type: [u8; 4],
+    payload: &'a [u8],
+}
+
+/// ボックス列を分解（不正なサイズがあればそこで打ち切り）
+fn iso_boxes(data: &[u8]) -> Vec<IsoBox<'_>> {
+    let mut boxes = Vec::new();
+    let mut pos = 0;
+
+    while pos + 8 <= data.len() {
+        let size = read_be(data, pos, 4).unwrap_or(0) as usize;
+        let box_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+        let (header, size) = match size {
+            0 => (8, data.len() - pos), // ファイル末尾まで
+            1 => match read_be(data, pos + 8, 8) {
+                Some(large) => (16, large as usize),
+                None => break,
+            },
+            size => (8, size),
+        };
+        let Some(payload) = data.get(pos + header..pos.saturating_add(size)) else {
+            break;
+        };
+        boxes.push(IsoBox { box_type, payload });
+        pos += size;
+    }
+
+    boxes
+}
+
+/// iinf ボックスのアイテム情報
+struct ItemInfo {
+    item_id: u32,
+    item_type: [u8; 4],
+    /// item_type が "mime" の場合の MIME タイプ
+    content_type: Option<String>,
+}
+
+/// iloc ボックスのアイテム位置（ファイル内オフセットと長さの組）
+struct ItemLocation {
+    item_id: u32,
+    extents: Vec<(usize, usize)>,
+}
+
+/// ビッグエンディアンの符号なし整数（0〜8バイト）を読み取り
+fn read_be(data: &[u8], pos: usize, size: usize) -> Option<u64> {
+    let bytes = data.get(pos..pos.checked_add(size)?)?;
+    Some(bytes.iter().fold(0u64, |value, &b| (value << 8) | b as u64))
+}
+
+/// meta ボックスの子ボックス列
+fn meta_children(data: &[u8]) -> Vec<IsoBox<'_>> {
+    iso_boxes(data)
+        .into_iter()
+        .find(|b| &b.box_type == b"meta")
+        // meta は FullBox（version + flags の4バイト）
+        .map(|meta| iso_boxes(meta.payload.get(4..).unwrap_or_default()))
+        .unwrap_or_default()
+}
+
+/// ISOBMFF（AVIF / HEIF）の画像プロパティ
+#[derive(Debug, Default)]
+pub struct IsobmffImageProperties {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: Option<u8>,
+    pub has_alpha: bool,
+    pub has_icc_profile: bool,
+    pub is_animated: bool,
+}
+
+/// ISOBMFF の ipco（ispe / pixi / colr / auxC）から画像プロパティを読み取る
+///
+/// image クレートで AVIF をデコードできない環境向け。画素データには触れない。
+pub fn read_isobmff_image_properties(data: &[u8]) -> Option<IsobmffImageProperties> {
+    let boxes = iso_boxes(data);
+    let ftyp = boxes.iter().find(|b| &b.box_type == b"ftyp")?;
+    let mut properties = IsobmffImageProperties {
+        // 画像シーケンスは ftyp のブランドに "avis" / "msf1" を含む
+        is_animated: ftyp
+            .payload
+            .chunks_exact(4)
+            .enumerate()
+            .any(|(index, brand)| index != 1 && matches!(brand, b"avis" | b"msf1")),
+        ..Default::default()
+    };
+
+    let children = meta_children(data);
+    let iprp = children.iter().find(|b| &b.box_type == b"iprp")?;
+    let ipco = iso_boxes(iprp.payload)
+        .into_iter()
+        .find(|b| &b.box_type == b"ipco")?;
+
+    for property in iso_boxes(ipco.payload) {
+        let p = property.payload;
+        match &property.box_type {
+            // 複数ある場合（サムネイル・アルファ等）は最大のものを主画像とみなす
+            b"ispe" => {
+                let width = read_be(p, 4, 4)? as u32;
+                let height = read_be(p, 8, 4)? as u32;
+                let area = |w: u32, h: u32| w as u64 * h as u64;
+                if area(properties.width, properties.height) < area(width, height) {
+                    properties.width = width;
+                    properties.height = height;
+                }
+            }
+            b"pixi" => {
+                properties.bit_depth = properties.bit_depth.or(p.get(5).copied());
+            }
+            b"colr" => {
+                properties.has_icc_profile |= matches!(p.get(..4), Some(b"prof" | b"rICC"));
+            }
+            b"auxC" => {
+                let aux_type = String::from_utf8_lossy(p.get(4..).unwrap_or_default());
+                properties.has_alpha |= aux_type.contains("alpha");
+            }
+            _ => {}
+        }
+    }
+
+    Some(properties)
+}
+
+/// ISOBMFF の meta ボックスから Exif / XMP アイテムを取り出す
+fn extract_from_isobmff(data: &[u8]) -> EmbeddedMetadata {
+    let mut metadata = EmbeddedMetadata::default();
+
+    let children = meta_children(data);
+    let find = |box_type: &[u8; 4]| children.iter().find(|b| &b.box_type == box_type);
+
+    let (Some(iinf), Some(iloc)) = (find(b"iinf"), find(b"iloc")) else {
+        return metadata;
+    };
+    let locations = parse_iloc(iloc.payload).unwrap_or_default();
+    let item_data = |item_id: u32| -> Option<Vec<u8>> {
+        let location = locations.iter().find(|l| l.item_id == item_id)?;
+        let mut bytes = Vec::new();
+        for &(offset, length) in &location.extents {
+            bytes.extend_from_slice(data.get(offset..offset.checked_add(length)?)?);
+        }
+        Some(bytes)
+    };
+
+    for item in parse_iinf(iinf.payload).unwrap_or_default() {
+        let item_id = item.item_id;
+        match (&item.item_type, item.content_type.as_deref()) {
+            (b"Exif", _) => {
+                // 先頭4バイトは TIFF ヘッダーまでのオフセット
+                let Some(bytes) = item_data(item_id) else {
+                    continue;
+                };
+                let offset = read_be(&bytes, 0, 4).unwrap_or(0) as usize;
+                if let Some(tiff) = bytes.get(4 + offset..) {
+                    metadata.exif = Some(tiff.strip_prefix(EXIF_HEADER).unwrap_or(tiff).to_vec());
+                }
+            }
+            (b"mime", Some("application/rdf+xml")) => {
+                metadata.xmp = item_data(item_id).and_then(|bytes| xmp::find_xmp_packet(&bytes));
+            }
+            _ => {}
+        }
+    }
+
+    metadata
+}
+
+/// iinf ボックスのアイテム情報を列挙
+fn parse_iinf(payload: &[u8]) -> Option<Vec<ItemInfo>> {
+    let version = *payload.first()?;
+    let entries_start = if version == 0 { 6 } else { 8 };
+
+    let mut items = Vec::new();
+    for infe in iso_boxes(payload.get(entries_start..)?) {
+        if &infe.box_type != b"infe" {
+            continue;
+        }
+        let p = infe.payload;
+        // version 2 は16ビット、version 3 は32ビットのアイテムID
+        let (item_id, pos) = match p.first()? {
+            2 => (read_be(p, 4, 2)? as u32, 6),
+            3 => (read_be(p, 4, 4)? as u32, 8),
+            _ => continue,
+        };
+        let item_type: [u8; 4] = p.get(pos + 2..pos + 6)?.try_into().ok()?;
+
+        // アイテム名（NUL終端）の後に MIME タイプ（NUL終端）が続く
+        let mut strings = p.get(pos + 6..)?.split(|&b| b == 0);
+        let _item_name = strings.next();
+        let content_type = (&item_type == b"mime")
+            .then(|| strings.next())
+            .flatten()
+            .map(|s| String::from_utf8_lossy(s).into_owned());
+
+        items.push(ItemInfo {
+            item_id,
+            item_type,
+            content_type,
+        });
+    }
+    Some(items)
+}
+
+/// iloc ボックスのアイテム位置を列挙
+fn parse_iloc(payload: &[u8]) -> Option<Vec<ItemLocation>> {
+    let version = *payload.first()?;
+    let offset_size = (payload.get(4)? >> 4) as usize;
+    let length_size = (payload.get(4)? & 0x0f) as usize;
+    let base_offset_size = (payload.get(5)? >> 4) as usize;
+    let index_size = if version == 0 {
+        0
+    } else {
+        (payload.get(5)? & 0x0f) as usize
+    };
+
+    let (item_count, mut pos) = if version < 2 {
+        (read_be(payload, 6, 2)?, 8)
+    } else {
+        (read_be(payload, 6, 4)?, 10)
+    };
+
+    let mut items = Vec::new();
+    for _ in 0..item_count {
+        let id_size = if version < 2 { 2 } else { 4 };
+        let item_id = read_be(payload, pos, id_size)? as u32;
+        pos += id_size;
+
+        // construction_method 0（ファイル内オフセット）以外は非対応
+        let construction_method = if version == 0 {
+            0
+        } else {
+            pos += 2;
+            read_be(payload, pos - 2, 2)? & 0x0f
+        };
+        pos += 2; // data_reference_index
+        let base_offset = usize::try_from(read_be(payload, pos, base_offset_size)?).ok()?;
+        pos += base_offset_size;
+
+        let extent_count = read_be(payload, pos, 2)?;
+        pos += 2;
+        let mut extents = Vec::new();
+        for _ in 0..extent_count {
+            pos += index_size;
+            let offset = usize::try_from(read_be(payload, pos, offset_size)?).ok()?;
+            pos += offset_size;
+            let length = usize::try_from(read_be(payload, pos, length_size)?).ok()?;
+            pos += length_size;
+            // 不正なオフセットによる桁あふれは解析失敗として扱う
+            extents.push((base_offset.checked_add(offset)?, length));
+        }
+
+        if construction_method == 0 {
+            items.push(ItemLocation { item_id, extents });
+        }
+    }
+    Some(items)
+}
+
+/// TIFF 構造の読み取り（バイトオーダーを考慮）
+struct TiffReader<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn read_u16(&self, pos: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(pos..pos + 2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn read_u32(&self, pos: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(pos..pos + 4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// IFD0 のオフセット
+    fn first_ifd(&self) -> Option<usize> {
+        self.read_u32(4).map(|offset| offset as usize)
+    }
+
+    /// IFD のエントリを (タグ, 値のバイト列) として列挙
+    fn entries(&self, ifd_offset: usize) -> Vec<(u16, &'a [u8])> {
+        let Some(count) = self.read_u16(ifd_offset) else {
+            return Vec::new();
+        };
+
+        (0..count as usize)
+            .filter_map(|index| {
+                let entry = ifd_offset + 2 + index * 12;
+                let tag = self.read_u16(entry)?;
+                let field_type = self.read_u16(entry + 2)?;
+                let count = self.read_u32(entry + 4)? as usize;
+                let unit = match field_type {
+                    1 | 2 | 6 | 7 => 1,
+                    3 | 8 => 2,
+                    4 | 9 | 11 => 4,
+                    5 | 10 | 12 => 8,
+                    _ => return None,
+                };
+                let size = unit * count;
+                // 4バイト以下の値はエントリ内に直接格納される
+                let start = if size <= 4 {
+                    entry + 8
+                } else {
+                    self.read_u32(entry + 8)? as usize
+                };
+                Some((tag, self.data.get(start..start.checked_add(size)?)?))
+            })
+            .collect()
+    }
+
+    /// 指定IFDのタグ値
+    fn find(&self, ifd_offset: usize, tag: u16) -> Option<&'a [u8]> {
+        self.entries(ifd_offset)
+            .into_iter()
+            .find(|(entry_tag, _)| *entry_tag == tag)
+            .map(|(_, value)| value)
+    }
+
+    /// Exif IFD のオフセット
+    fn exif_ifd(&self) -> Option<usize> {
+        let pointer = self.find(self.first_ifd()?, TAG_EXIF_IFD_POINTER)?;
+        let reader = TiffReader {
+            data: pointer,
+            little_endian: self.little_endian,
+        };
+        reader.read_u32(0).map(|offset| offset as usize)
+    }
+}
+
+/// EXIF UserComment（無い場合は ImageDescription）を文字列として取得
+fn read_user_comment(tiff: &[u8]) -> Option<String> {
+    let reader = TiffReader::new(tiff)?;
+    let comment = reader
+        .exif_ifd()
+        .and_then(|ifd| reader.find(ifd, TAG_USER_COMMENT))
+        .and_then(decode_user_comment);
+
+    comment.or_else(|| {
+        let description = reader.find(reader.first_ifd()?, TAG_IMAGE_DESCRIPTION)?;
+        Some(decode_ascii(description)).filter(|text| !text.is_empty())
+    })
+}
+
+/// UserComment を文字コード識別子（先頭8バイト）に従ってデコード
+fn decode_user_comment(value: &[u8]) -> Option<String> {
+    let (charset, body) = value.split_at_checked(8)?;
+    let text = match charset {
+        b"UNICODE\0" => decode_utf16(body),
+        // ASCII・未定義（ゼロ埋め）は UTF-8 として扱う（A1111 以外のツールの互換のため）
+        _ => decode_ascii(body),
+    };
+    Some(text).filter(|text| !text.is_empty())
+}
+
+/// NUL 終端・NUL 埋めを除いて UTF-8 としてデコード
+fn decode_ascii(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+/// UTF-16 としてデコード（BOM が無い場合はゼロバイトの位置からバイトオーダーを推定）
+fn decode_utf16(body: &[u8]) -> String {
+    let (big_endian, body) = match body {
+        [0xfe, 0xff, rest @ ..] => (true, rest),
+        [0xff, 0xfe, rest @ ..] => (false, rest),
+        _ => {
+            // ASCII 主体のテキストは BE なら偶数番目、LE なら奇数番目がゼロになる
+            let even_zeros = body.iter().step_by(2).filter(|&&b| b == 0).count();
+            let odd_zeros = body.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+            (odd_zeros <= even_zeros, body)
+        }
+    };
+
+    let units: Vec<u16> = body
+        .chunks_exact(2)
+        .map(|pair| {
+            if big_endian {
+                u16::from_be_bytes([pair[0], pair[1]])
+            } else {
+                u16::from_le_bytes([pair[0], pair[1]])
+            }
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string()
+}
+
+/// ComfyUI が WebP の EXIF（IFD0 の Make / Model 等）に書き込む "prompt:" / "workflow:" 文字列
+fn read_comfyui_exif(tiff: &[u8]) -> Option<SdParameters> {
+    let reader = TiffReader::new(tiff)?;
+    let texts: Vec<String> = reader
+        .entries(reader.first_ifd()?)
+        .into_iter()
+        .map(|(_, value)| decode_ascii(value))
+        .collect();
+    let find = |prefix: &str| {
+        texts.iter().find_map(|text| {
+            let (key, value) = text.split_once(':')?;
+            key.eq_ignore_ascii_case(prefix).then_some(value)
+        })
+    };
+
+    let prompt = find("prompt");
+    let workflow = find("workflow");
+    if prompt.is_none() && workflow.is_none() {
+        return None;
+    }
+    parse_comfyui_chunks(prompt, workflow).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// version 0 / offset・length・base_offset 各8バイトの iloc ペイロード
+    fn iloc_payload(base_offset: u64, offset: u64, length: u64) -> Vec<u8> {
+        let mut payload = vec![0, 0, 0, 0, 0x88, 0x80];
+        payload.extend(1u16.to_be_bytes()); // item_count
+        payload.extend(1u16.to_be_bytes()); // item_id
+        payload.extend(0u16.to_be_bytes()); // data_reference_index
+        payload.extend(base_offset.to_be_bytes());
+        payload.extend(1u16.to_be_bytes()); // extent_count
+        payload.extend(offset.to_be_bytes());
+        payload.extend(length.to_be_bytes());
+        payload
+    }
+
+    #[test]
+    fn parse_iloc_extents() {
+        let items = parse_iloc(&iloc_payload(100, 20, 30)).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].item_id, 1);
+        assert_eq!(items[0].extents, [(120, 30)]);
+    }
+
+    /// 4x4 の画像をエンコード
+    fn encode_image(format: image::ImageFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        image::RgbImage::from_pixel(4, 4, image::Rgb([10, 20, 30]))
+            .write_to(&mut std::io::Cursor::new(&mut data), format)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn snapshot_restore_round_trip() {
+        for format in [
+            image::ImageFormat::Png,
+            image::ImageFormat::Jpeg,
+            image::ImageFormat::WebP,
+        ] {
+            let plain = encode_image(format);
+            let packet = xmp::set_property(&xmp::new_packet(), "xmp:Rating", "3");
+            let tagged = write_xmp_packet(&plain, &packet).unwrap();
+            let blocks = snapshot_metadata(&tagged).unwrap();
+            assert!(!blocks.is_empty(), "{:?}", format);
+
+            // メタデータを変更した後に記録した内容へ戻す
+            let edited =
+                write_xmp_packet(&tagged, &xmp::set_property(&packet, "xmp:Rating", "5")).unwrap();
+            let restored = restore_metadata(&edited, &blocks).unwrap();
+            let restored_xmp = extract_embedded_metadata(&restored).xmp.unwrap();
+            assert_eq!(
+                xmp::find_property(&restored_xmp, "xmp:Rating").as_deref(),
+                Some("3")
+            );
+            assert_eq!(image_data_digest(&restored), image_data_digest(&plain));
+            assert_eq!(snapshot_metadata(&restored).unwrap().len(), blocks.len());
+
+            // メタデータが無かった状態にも戻せる
+            let cleared = restore_metadata(&tagged, &snapshot_metadata(&plain).unwrap()).unwrap();
+            assert!(
+                extract_embedded_metadata(&cleared).xmp.is_none(),
+                "{:?}",
+                format
+            );
+            image::load_from_memory(&cleared).unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_iloc_rejects_overflowing_offsets() {
+        assert!(parse_iloc(&iloc_payload(u64::MAX, 1, 30)).is_none());
+        assert!(parse_iloc(&iloc_payload(u64::MAX - 1, 1, 30)).is_some());
+    }
+}
@@ -0,0 +1,946 @@
+// 画像検索用のモジュール
+//
+// メタデータインデックスの SD Parameters を対象に、プロンプトの全文検索と
+// タグ・モデル・LoRA・生成設定による条件検索を行う。検索語の構文は以下の通り（条件はすべて AND）。
+//
+//   blue hair               ファイル名・ポジティブプロンプト・モデル名の部分一致
+//   tag:"blue hair"         ポジティブプロンプトのタグ（完全一致。"_" と空白は同一視）
+//   tag:"blue hair"@>=1.2   タグの実効重みの条件付き
+//   neg:lowres              ネガティブプロンプトのタグ
+//   model:animagine         モデル名の部分一致（sampler: も同様）
+//   lora:detail_tweaker     LoRA / LyCORIS 名の部分一致（@ で倍率の条件を付けられる）
+//   steps:>=30  cfg:5..7    数値の比較（=, >, >=, <, <=, 範囲 a..b）。seed: rating: width: height: も同様
+//   -tag:hat                先頭の "-" で条件を否定
+//
+// 検索語は SQL の条件に変換してインデックスの型付きの列と全文検索用の image_text で候補を絞り込み、
+// 候補ごとに SD Parameters と照合して一致箇所を求める。
+use crate::file_system_handler::{image_list_entry, sort_image_list};
+use crate::image_types::{ImageListEntry, ImageListOptions};
+use crate::library_index::{seed_column_value, IndexedImage, LibraryIndex};
+use crate::library_scan::ScanFilter;
+use crate::sd_parameters::{NetworkKind, NetworkRef, SdParameters, SdTag, SdTypedValues};
+use crate::thumbnail_handler::ThumbnailState;
+use rayon::prelude::*;
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+/// プロンプトの一致箇所の前後に含める文字数
+const SNIPPET_CONTEXT_CHARS: usize = 30;
+/// 全文検索用の列でタグ・LoRA 名を囲む区切り文字（タグの完全一致を GLOB で判定するため）
+const NAME_SEPARATOR: char = '\u{1f}';
+
+/// 一致した項目
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchField {
+    Filename,
+    PositivePrompt,
+    PositiveTag,
+    NegativeTag,
+    Model,
+    Sampler,
+    Network,
+    Steps,
+    CfgScale,
+    Seed,
+    Rating,
+    Width,
+    Height,
+}
+
+/// 検索語に一致した箇所
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHighlight {
+    pub field: SearchField,
+    /// 一致した値（プロンプトは一致箇所の前後のみ、重み付きのタグは "(name:1.2)" 形式）
+    pub text: String,
+    /// text 内の一致範囲（UTF-16 単位。JavaScript の文字列のインデックスと同じ）
+    pub start: usize,
+    pub end: usize,
+}
+
+/// 検索結果の1項目
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSearchResult {
+    pub path: String,
+    /// 否定以外の検索語に一致した箇所
+    pub highlights: Vec<SearchHighlight>,
+}
+
+/// 数値の条件
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NumberFilter<T> {
+    Equal(T),
+    Greater(T),
+    GreaterOrEqual(T),
+    Less(T),
+    LessOrEqual(T),
+    /// 両端を含む範囲
+    Range(T, T),
+}
+
+impl<T: Copy + PartialOrd + FromStr> NumberFilter<T> {
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let number = |value: &str| value.trim().parse::<T>().ok();
+
+        if let Some((min, max)) = value.split_once("..") {
+            let (min, max) = (number(min)?, number(max)?);
+            (min <= max).then_some(Self::Range(min, max))
+        } else if let Some(value) = value.strip_prefix(">=") {
+            Some(Self::GreaterOrEqual(number(value)?))
+        } else if let Some(value) = value.strip_prefix("<=") {
+            Some(Self::LessOrEqual(number(value)?))
+        } else if let Some(value) = value.strip_prefix('>') {
+            Some(Self::Greater(number(value)?))
+        } else if let Some(value) = value.strip_prefix('<') {
+            Some(Self::Less(number(value)?))
+        } else {
+            Some(Self::Equal(number(
+                value.strip_prefix('=').unwrap_or(value),
+            )?))
+        }
+    }
+
+    fn matches(&self, value: T) -> bool {
+        match *self {
+            Self::Equal(expected) => value == expected,
+            Self::Greater(min) => min < value,
+            Self::GreaterOrEqual(min) => min <= value,
+            Self::Less(max) => value < max,
+            Self::LessOrEqual(max) => value <= max,
+            Self::Range(min, max) => min <= value && value <= max,
+        }
+    }
+
+    /// 比較する値を変換（列に保存した形式に合わせる）
+    fn map<U>(self, convert: impl Fn(T) -> U) -> NumberFilter<U> {
+        match self {
+            Self::Equal(expected) => NumberFilter::Equal(convert(expected)),
+            Self::Greater(min) => NumberFilter::Greater(convert(min)),
+            Self::GreaterOrEqual(min) => NumberFilter::GreaterOrEqual(convert(min)),
+            Self::Less(max) => NumberFilter::Less(convert(max)),
+            Self::LessOrEqual(max) => NumberFilter::LessOrEqual(convert(max)),
+            Self::Range(min, max) => NumberFilter::Range(convert(min), convert(max)),
+        }
+    }
+}
+
+impl<T: Copy + Into<Value>> NumberFilter<T> {
+    /// 列の値の比較（値が無い場合は NULL）
+    fn to_sql(self, column: &str) -> SqlCondition {
+        let (operator, params) = match self {
+            Self::Equal(expected) => ("= ?", vec![expected.into()]),
+            Self::Greater(min) => ("> ?", vec![min.into()]),
+            Self::GreaterOrEqual(min) => (">= ?", vec![min.into()]),
+            Self::Less(max) => ("< ?", vec![max.into()]),
+            Self::LessOrEqual(max) => ("<= ?", vec![max.into()]),
+            Self::Range(min, max) => ("BETWEEN ? AND ?", vec![min.into(), max.into()]),
+        };
+        SqlCondition {
+            sql: format!("{} {}", column, operator),
+            params,
+            exact: true,
+        }
+    }
+}
+
+/// 検索語の条件
+#[derive(Debug, Clone, PartialEq)]
+enum SearchCondition {
+    /// 全文検索（比較用に正規化した文字列）
+    Text(Vec<char>),
+    Tag {
+        name: Vec<char>,
+        weight: Option<NumberFilter<f32>>,
+        negative_prompt: bool,
+    },
+    Model(Vec<char>),
+    Sampler(Vec<char>),
+    Network {
+        name: Vec<char>,
+        weight: Option<NumberFilter<f32>>,
+    },
+    Steps(NumberFilter<u32>),
+    CfgScale(NumberFilter<f32>),
+    Seed(NumberFilter<u64>),
+    Rating(NumberFilter<u16>),
+    Width(NumberFilter<u32>),
+    Height(NumberFilter<u32>),
+}
+
+/// 検索語（"-" で始まる場合は否定）
+#[derive(Debug, Clone, PartialEq)]
+struct SearchTerm {
+    condition: SearchCondition,
+    negated: bool,
+}
+
+/// SQL の条件
+struct SqlCondition {
+    sql: String,
+    params: Vec<Value>,
+    /// 条件を満たす画像が検索語に一致する画像と同じか（false の場合は候補を広めに取る条件）
+    exact: bool,
+}
+
+impl SqlCondition {
+    /// 全文検索用の列の GLOB（image_text のトライグラムの索引を使えるようにサブクエリにする）
+    fn glob(columns: &[&str], pattern: String, exact: bool) -> Self {
+        let globs: Vec<String> = columns
+            .iter()
+            .map(|column| format!("{} GLOB ?", column))
+            .collect();
+        Self {
+            sql: format!(
+                "images.id IN (SELECT rowid FROM image_text WHERE {})",
+                globs.join(" OR ")
+            ),
+            params: vec![Value::Text(pattern); columns.len()],
+            exact,
+        }
+    }
+}
+
+/// 全文検索用に正規化した値（インデックスの image_text の各列）
+///
+/// タグと LoRA / LyCORIS 名は区切り文字で囲んで連結する。
+pub struct SearchText {
+    pub filename: String,
+    pub positive_prompt: String,
+    pub model: String,
+    pub sampler: String,
+    pub positive_tags: String,
+    pub negative_tags: String,
+    pub networks: String,
+}
+
+impl SearchText {
+    pub fn new(image: &IndexedImage) -> Self {
+        let parameters = image.sd_parameters.as_ref();
+        let text = |value: Option<&str>| value.map(normalize_text).unwrap_or_default();
+        let tags = |tags: Option<&Vec<SdTag>>| {
+            join_names(tags.into_iter().flatten().map(|tag| tag.name.as_str()))
+        };
+
+        Self {
+            filename: normalize_text(&image.file_system_info.filename),
+            positive_prompt: text(parameters.map(|parameters| parameters.positive_prompt.as_str())),
+            model: text(parameters.and_then(|parameters| parameters.model.as_deref())),
+            sampler: text(parameters.and_then(|parameters| parameters.sampler.as_deref())),
+            positive_tags: tags(parameters.map(|parameters| &parameters.positive_sd_tags)),
+            negative_tags: tags(parameters.map(|parameters| &parameters.negative_sd_tags)),
+            networks: join_names(
+                parameters
+                    .into_iter()
+                    .flat_map(|parameters| &parameters.networks)
+                    .filter(|network| is_lora(network))
+                    .map(|network| network.name.as_str()),
+            ),
+        }
+    }
+}
+
+/// 名前を正規化し、区切り文字で囲んで連結
+fn join_names<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let mut joined = String::from(NAME_SEPARATOR);
+    for name in names {
+        joined.push_str(&normalize_text(name));
+        joined.push(NAME_SEPARATOR);
+    }
+    joined
+}
+
+/// 画像を検索（Tauri API）
+///
+/// メタデータインデックスを検索し、一致した画像のパスと一致箇所を `options` の並び順で返す。
+/// ディレクトリは走査しないため、事前に `update_library_index`（または `list_images_in_directory`）で
+/// インデックスを更新しておく。`options` の拡張子・パターン・深さの条件は走査と同じく適用する。
+/// 検索語が空の場合はすべて一致する。
+#[tauri::command]
+pub async fn search_images(
+    directory: String,
+    query: String,
+    options: Option<ImageListOptions>,
+    state: tauri::State<'_, ThumbnailState>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<Vec<ImageSearchResult>, String> {
+    let terms = parse_query(&query)?;
+    let options = options.unwrap_or_default();
+    let filter = ScanFilter::new(&options)?;
+    let root = Path::new(&directory);
+    let start_time = std::time::Instant::now();
+
+    let (condition, params) = sql_condition(&terms);
+    let candidates = index.search(root, &condition, params)?;
+    let candidate_count = candidates.len();
+
+    let matches: Vec<(IndexedImage, Vec<SearchHighlight>)> =
+        state.handler.thread_pool().install(|| {
+            candidates
+                .into_par_iter()
+                .filter(|image| filter.is_scanned_path(root, Path::new(&image.path)))
+                .filter_map(|image| {
+                    let highlights = match_image(&image, &terms)?;
+                    Some((image, highlights))
+                })
+                .collect()
+        });
+
+    let mut highlights = HashMap::new();
+    let mut entries: Vec<ImageListEntry> = matches
+        .into_iter()
+        .map(|(image, image_highlights)| {
+            highlights.insert(image.path.clone(), image_highlights);
+            image_list_entry(image)
+        })
+        .collect();
+    sort_image_list(&mut entries, options.sort_by, options.descending);
+
+    log::info!(
+        "画像検索完了: {}/{}件一致, 総時間: {:.1}ms",
+        entries.len(),
+        candidate_count,
+        start_time.elapsed().as_secs_f64() * 1000.0
+    );
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| ImageSearchResult {
+            highlights: highlights.remove(&entry.path).unwrap_or_default(),
+            path: entry.path,
+        })
+        .collect())
+}
+
+/// 検索語をインデックスの検索条件（SQL の式とパラメーター）に変換
+///
+/// 候補を絞り込むための条件で、一致の判定は `match_image` で行う。否定の検索語は条件が
+/// 検索語と完全に対応する場合だけ含める（重みの条件は SQL では判定しない）。
+fn sql_condition(terms: &[SearchTerm]) -> (String, Vec<Value>) {
+    let mut clauses = Vec::new();
+    let mut params = Vec::new();
+    for term in terms {
+        let condition = condition_sql(&term.condition);
+        if !term.negated {
+            clauses.push(format!("({})", condition.sql));
+        } else if condition.exact {
+            // 値が無い（NULL）画像は否定の検索語に一致する
+            clauses.push(format!("NOT IFNULL(({}), 0)", condition.sql));
+        } else {
+            continue;
+        }
+        params.extend(condition.params);
+    }
+
+    if clauses.is_empty() {
+        ("1".to_string(), params)
+    } else {
+        (clauses.join(" AND "), params)
+    }
+}
+
+fn condition_sql(condition: &SearchCondition) -> SqlCondition {
+    match condition {
+        SearchCondition::Text(text) => SqlCondition::glob(
+            &["filename_text", "prompt_text", "model_text"],
+            glob_pattern("*", text, "*"),
+            true,
+        ),
+        SearchCondition::Tag {
+            name,
+            weight,
+            negative_prompt,
+        } => {
+            let separator = NAME_SEPARATOR.to_string();
+            let column = if *negative_prompt {
+                "negative_tags"
+            } else {
+                "positive_tags"
+            };
+            let pattern =
+                glob_pattern(&format!("*{}", separator), name, &format!("{}*", separator));
+            SqlCondition::glob(&[column], pattern, weight.is_none())
+        }
+        SearchCondition::Model(model) => {
+            SqlCondition::glob(&["model_text"], glob_pattern("*", model, "*"), true)
+        }
+        SearchCondition::Sampler(sampler) => {
+            SqlCondition::glob(&["sampler_text"], glob_pattern("*", sampler, "*"), true)
+        }
+        SearchCondition::Network { name, weight } => SqlCondition::glob(
+            &["network_names"],
+            glob_pattern("*", name, "*"),
+            weight.is_none(),
+        ),
+        SearchCondition::Steps(filter) => filter.to_sql("images.steps"),
+        SearchCondition::CfgScale(filter) => filter.to_sql("images.cfg_scale"),
+        SearchCondition::Seed(filter) => filter.map(seed_column_value).to_sql("images.seed"),
+        SearchCondition::Rating(filter) => filter.to_sql("images.rating"),
+        SearchCondition::Width(filter) => filter.to_sql("images.width"),
+        SearchCondition::Height(filter) => filter.to_sql("images.height"),
+    }
+}
+
+/// 正規化した文字列の GLOB パターン（"*", "?", "[" は文字として一致させる）
+fn glob_pattern(prefix: &str, text: &[char], suffix: &str) -> String {
+    let mut pattern = prefix.to_string();
+    for &c in text {
+        if matches!(c, '*' | '?' | '[') {
+            pattern.extend(['[', c, ']']);
+        } else {
+            pattern.push(c);
+        }
+    }
+    pattern.push_str(suffix);
+    pattern
+}
+
+/// 検索語を解析
+fn parse_query(query: &str) -> Result<Vec<SearchTerm>, String> {
+    split_terms(query)
+        .into_iter()
+        .map(|(term, negated)| {
+            Ok(SearchTerm {
+                condition: parse_condition(&term)?,
+                negated,
+            })
+        })
+        .collect()
+}
+
+/// 空白で検索語に分割（引用符内の空白は区切らない。先頭の "-" は否定として取り除く）
+fn split_terms(query: &str) -> Vec<(String, bool)> {
+    let mut terms = Vec::new();
+    let mut chars = query.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return terms;
+        };
+
+        let negated = first == '-' && chars.peek().is_some_and(|c| !c.is_whitespace());
+        let mut term = String::new();
+        let mut in_quotes = false;
+        if !negated {
+            in_quotes = first == '"';
+            term.push(first);
+        }
+        while let Some(c) = chars.next_if(|c| in_quotes || !c.is_whitespace()) {
+            in_quotes ^= c == '"';
+            term.push(c);
+        }
+        terms.push((term, negated));
+    }
+}
+
+/// 検索語1つを条件に変換（"key:value" 以外と、未知のキーは全文検索として扱う）
+fn parse_condition(term: &str) -> Result<SearchCondition, String> {
+    let key_value = term
+        .split_once(':')
+        .filter(|(key, _)| !key.contains('"'))
+        .map(|(key, value)| (key.to_lowercase(), value));
+
+    let Some((key, value)) = key_value else {
+        return text_condition(term);
+    };
+    if unquote(value).trim().is_empty() {
+        return Err(format!("検索条件の値がありません: {}", term));
+    }
+
+    let condition = match key.as_str() {
+        "tag" | "neg" => {
+            let (name, weight) = parse_weighted_name(value, term)?;
+            SearchCondition::Tag {
+                name,
+                weight,
+                negative_prompt: key == "neg",
+            }
+        }
+        "lora" => {
+            let (name, weight) = parse_weighted_name(value, term)?;
+            SearchCondition::Network { name, weight }
+        }
+        "model" => SearchCondition::Model(normalize(&unquote(value))),
+        "sampler" => SearchCondition::Sampler(normalize(&unquote(value))),
+        "steps" => SearchCondition::Steps(parse_number_filter(value, term)?),
+        "cfg" => SearchCondition::CfgScale(parse_number_filter(value, term)?),
+        "seed" => SearchCondition::Seed(parse_number_filter(value, term)?),
+        "rating" => SearchCondition::Rating(parse_number_filter(value, term)?),
+        "width" => SearchCondition::Width(parse_number_filter(value, term)?),
+        "height" => SearchCondition::Height(parse_number_filter(value, term)?),
+        _ => return text_condition(term),
+    };
+    Ok(condition)
+}
+
+fn text_condition(term: &str) -> Result<SearchCondition, String> {
+    let text = unquote(term);
+    if text.trim().is_empty() {
+        return Err(format!("検索語が空です: {}", term));
+    }
+    Ok(SearchCondition::Text(normalize(&text)))
+}
+
+/// "name" または "name@<数値の条件>" を解析（引用符内の "@" は名前の一部）
+fn parse_weighted_name(
+    value: &str,
+    term: &str,
+) -> Result<(Vec<char>, Option<NumberFilter<f32>>), String> {
+    let quotes_end = value.rfind('"').map_or(0, |index| index + 1);
+    let (name, weight) = match value[quotes_end..].rfind('@') {
+        Some(index) => {
+            let (name, weight) = value.split_at(quotes_end + index);
+            (name, Some(parse_number_filter(&weight[1..], term)?))
+        }
+        None => (value, None),
+    };
+
+    let name = unquote(name);
+    if name.trim().is_empty() {
+        return Err(format!("検索条件の値がありません: {}", term));
+    }
+    Ok((normalize(name.trim()), weight))
+}
+
+fn parse_number_filter<T: Copy + PartialOrd + FromStr>(
+    value: &str,
+    term: &str,
+) -> Result<NumberFilter<T>, String> {
+    NumberFilter::parse(&unquote(value))
+        .ok_or_else(|| format!("検索条件の数値が不正です: {}", term))
+}
+
+fn unquote(value: &str) -> String {
+    value.replace('"', "")
+}
+
+/// 比較用に1文字を正規化（大文字小文字を区別せず、"_" は空白とみなす）
+fn fold(c: char) -> char {
+    match c {
+        '_' => ' ',
+        c => c.to_lowercase().next().unwrap_or(c),
+    }
+}
+
+fn normalize(text: &str) -> Vec<char> {
+    text.chars().map(fold).collect()
+}
+
+fn normalize_text(text: &str) -> String {
+    text.chars().map(fold).collect()
+}
+
+/// 検索対象のネットワーク（LoRA / LyCORIS）かどうか
+fn is_lora(network: &NetworkRef) -> bool {
+    matches!(network.kind, NetworkKind::Lora | NetworkKind::Lycoris)
+}
+
+/// 正規化した文字列どうしで部分一致する範囲（バイト単位）
+fn find_normalized(haystack: &str, needle: &[char]) -> Option<Range<usize>> {
+    haystack.char_indices().find_map(|(start, _)| {
+        let mut chars = haystack[start..].char_indices();
+        for &expected in needle {
+            let (_, c) = chars.next()?;
+            if fold(c) != expected {
+                return None;
+            }
+        }
+        let end = chars
+            .next()
+            .map_or(haystack.len(), |(offset, _)| start + offset);
+        Some(start..end)
+    })
+}
+
+fn equals_normalized(text: &str, normalized: &[char]) -> bool {
+    text.chars().map(fold).eq(normalized.iter().copied())
+}
+
+/// 画像1枚を検索語と照合（すべての検索語を満たす場合は一致箇所を返す）
+fn match_image(image: &IndexedImage, terms: &[SearchTerm]) -> Option<Vec<SearchHighlight>> {
+    let parameters = image.sd_parameters.as_ref();
+    let values = parameters
+        .map(SdParameters::typed_values)
+        .unwrap_or_default();
+    let target = SearchTarget {
+        image,
+        parameters,
+        values,
+    };
+
+    let mut highlights = Vec::new();
+    for term in terms {
+        let term_highlights = target.find(&term.condition);
+        if term_highlights.is_empty() != term.negated {
+            return None;
+        }
+        highlights.extend(term_highlights);
+    }
+    Some(highlights)
+}
+
+/// 照合対象の画像（型付きの設定値は1度だけ解析する）
+struct SearchTarget<'a> {
+    image: &'a IndexedImage,
+    parameters: Option<&'a SdParameters>,
+    values: SdTypedValues,
+}
+
+impl SearchTarget<'_> {
+    /// 条件に一致した箇所（一致しない場合は空）
+    fn find(&self, condition: &SearchCondition) -> Vec<SearchHighlight> {
+        let parameters = self.parameters;
+        match condition {
+            SearchCondition::Text(text) => {
+                let filename = &self.image.file_system_info.filename;
+                let prompt = parameters.map(|parameters| parameters.positive_prompt.as_str());
+                let model = parameters.and_then(|parameters| parameters.model.as_deref());
+
+                let mut highlights = Vec::new();
+                highlights.extend(text_highlight(SearchField::Filename, filename, text));
+                highlights.extend(prompt.and_then(|prompt| snippet_highlight(prompt, text)));
+                highlights.extend(
+                    model.and_then(|model| text_highlight(SearchField::Model, model, text)),
+                );
+                highlights
+            }
+            SearchCondition::Tag {
+                name,
+                weight,
+                negative_prompt,
+            } => {
+                let (field, tags) = match (parameters, negative_prompt) {
+                    (Some(parameters), false) => {
+                        (SearchField::PositiveTag, &parameters.positive_sd_tags)
+                    }
+                    (Some(parameters), true) => {
+                        (SearchField::NegativeTag, &parameters.negative_sd_tags)
+                    }
+                    (None, _) => return Vec::new(),
+                };
+                tags.iter()
+                    .filter(|tag| equals_normalized(&tag.name, name))
+                    .filter(|tag| {
+                        weight.is_none_or(|weight| weight.matches(tag.weight.unwrap_or(1.0)))
+                    })
+                    .map(|tag| tag_highlight(field, tag))
+                    .collect()
+            }
+            SearchCondition::Model(model) => parameters
+                .and_then(|parameters| parameters.model.as_deref())
+                .and_then(|value| text_highlight(SearchField::Model, value, model))
+                .into_iter()
+                .collect(),
+            SearchCondition::Sampler(sampler) => parameters
+                .and_then(|parameters| parameters.sampler.as_deref())
+                .and_then(|value| text_highlight(SearchField::Sampler, value, sampler))
+                .into_iter()
+                .collect(),
+            SearchCondition::Network { name, weight } => parameters
+                .map(|parameters| parameters.networks.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .filter(|network| is_lora(network))
+                .filter(|network| {
+                    // A1111 と同じく第1引数（テキストエンコーダーの倍率）をその LoRA の倍率とみなす
+                    let multiplier = network.te_weight.or(network.unet_weight).unwrap_or(1.0);
+                    weight.is_none_or(|weight| weight.matches(multiplier))
+                })
+                .filter_map(|network| text_highlight(SearchField::Network, &network.name, name))
+                .collect(),
+            SearchCondition::Steps(filter) => {
+                number_highlight(SearchField::Steps, self.values.steps, filter)
+            }
+            SearchCondition::CfgScale(filter) => {
+                number_highlight(SearchField::CfgScale, self.values.cfg_scale, filter)
+            }
+            SearchCondition::Seed(filter) => {
+                number_highlight(SearchField::Seed, self.values.seed, filter)
+            }
+            SearchCondition::Rating(filter) => {
+                number_highlight(SearchField::Rating, self.image.rating(), filter)
+            }
+            SearchCondition::Width(filter) => number_highlight(
+                SearchField::Width,
+                self.image.dimensions.map(|dimensions| dimensions.width),
+                filter,
+            ),
+            SearchCondition::Height(filter) => number_highlight(
+                SearchField::Height,
+                self.image.dimensions.map(|dimensions| dimensions.height),
+                filter,
+            ),
+        }
+    }
+}
+
+/// UTF-16 単位の長さ
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+/// 短い値の部分一致
+fn text_highlight(field: SearchField, value: &str, text: &[char]) -> Option<SearchHighlight> {
+    let range = find_normalized(value, text)?;
+    Some(SearchHighlight {
+        field,
+        text: value.to_string(),
+        start: utf16_len(&value[..range.start]),
+        end: utf16_len(&value[..range.end]),
+    })
+}
+
+/// プロンプトの部分一致（一致箇所の前後のみを切り出す）
+fn snippet_highlight(prompt: &str, text: &[char]) -> Option<SearchHighlight> {
+    let range = find_normalized(prompt, text)?;
+    let snippet_start = prompt[..range.start]
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT_CHARS - 1)
+        .map_or(0, |(index, _)| index);
+    let snippet_end = prompt[range.end..]
+        .char_indices()
+        .nth(SNIPPET_CONTEXT_CHARS)
+        .map_or(prompt.len(), |(index, _)| range.end + index);
+
+    let prefix = if snippet_start > 0 { "…" } else { "" };
+    let suffix = if snippet_end < prompt.len() {
+        "…"
+    } else {
+        ""
+    };
+    let start = utf16_len(prefix) + utf16_len(&prompt[snippet_start..range.start]);
+    Some(SearchHighlight {
+        field: SearchField::PositivePrompt,
+        text: format!(
+            "{}{}{}",
+            prefix,
+            &prompt[snippet_start..snippet_end],
+            suffix
+        ),
+        start,
+        end: start + utf16_len(&prompt[range]),
+    })
+}
+
+/// タグの一致（重み付きのタグは "(name:1.2)" 形式で返し、範囲は name の部分）
+fn tag_highlight(field: SearchField, tag: &SdTag) -> SearchHighlight {
+    let (text, start) = match tag.weight {
+        Some(weight) => (format!("({}:{})", tag.name, weight), 1),
+        None => (tag.name.clone(), 0),
+    };
+    SearchHighlight {
+        field,
+        text,
+        start,
+        end: start + utf16_len(&tag.name),
+    }
+}
+
+/// 数値の一致（値の全体を一致範囲とする）
+fn number_highlight<T: Copy + PartialOrd + FromStr + Display>(
+    field: SearchField,
+    value: Option<T>,
+    filter: &NumberFilter<T>,
+) -> Vec<SearchHighlight> {
+    value
+        .filter(|value| filter.matches(*value))
+        .map(|value| {
+            let text = value.to_string();
+            SearchHighlight {
+                field,
+                end: utf16_len(&text),
+                text,
+                start: 0,
+            }
+        })
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(condition: SearchCondition, negated: bool) -> SearchTerm {
+        SearchTerm { condition, negated }
+    }
+
+    #[test]
+    fn quoted_terms() {
+        assert_eq!(
+            parse_query(r#"tag:"blue hair"  "white dress" smile"#).unwrap(),
+            [
+                term(
+                    SearchCondition::Tag {
+                        name: normalize("blue hair"),
+                        weight: None,
+                        negative_prompt: false,
+                    },
+                    false
+                ),
+                term(SearchCondition::Text(normalize("white dress")), false),
+                term(SearchCondition::Text(normalize("smile")), false),
+            ]
+        );
+        // 引用符内の ":" はキーの区切りにしない
+        assert_eq!(
+            parse_query(r#""a:b""#).unwrap(),
+            [term(SearchCondition::Text(normalize("a:b")), false)]
+        );
+        assert!(parse_query(r#"tag:"""#).is_err());
+    }
+
+    #[test]
+    fn negated_terms() {
+        assert_eq!(
+            parse_query(r#"-tag:hat -"blue hair" - smile"#).unwrap(),
+            [
+                term(
+                    SearchCondition::Tag {
+                        name: normalize("hat"),
+                        weight: None,
+                        negative_prompt: false,
+                    },
+                    true
+                ),
+                term(SearchCondition::Text(normalize("blue hair")), true),
+                // 単独の "-" は否定ではなく検索語
+                term(SearchCondition::Text(normalize("-")), false),
+                term(SearchCondition::Text(normalize("smile")), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn weighted_names() {
+        assert_eq!(
+            parse_condition(r#"tag:"blue_hair"@>=1.2"#).unwrap(),
+            SearchCondition::Tag {
+                name: normalize("blue hair"),
+                weight: Some(NumberFilter::GreaterOrEqual(1.2)),
+                negative_prompt: false,
+            }
+        );
+        assert_eq!(
+            parse_condition("lora:detail@0.5..1").unwrap(),
+            SearchCondition::Network {
+                name: normalize("detail"),
+                weight: Some(NumberFilter::Range(0.5, 1.0)),
+            }
+        );
+        // 引用符内の "@" は名前の一部
+        assert_eq!(
+            parse_condition(r#"neg:"a@b""#).unwrap(),
+            SearchCondition::Tag {
+                name: normalize("a@b"),
+                weight: None,
+                negative_prompt: true,
+            }
+        );
+        assert!(parse_condition("tag:hat@").is_err());
+        assert!(parse_condition("tag:@1").is_err());
+        assert!(parse_condition("lora:detail@high").is_err());
+    }
+
+    #[test]
+    fn number_filters() {
+        assert_eq!(
+            parse_condition("steps:30").unwrap(),
+            SearchCondition::Steps(NumberFilter::Equal(30))
+        );
+        assert_eq!(
+            parse_condition("steps:=30").unwrap(),
+            SearchCondition::Steps(NumberFilter::Equal(30))
+        );
+        assert_eq!(
+            parse_condition("cfg:5..7.5").unwrap(),
+            SearchCondition::CfgScale(NumberFilter::Range(5.0, 7.5))
+        );
+        assert_eq!(
+            parse_condition("cfg:7..7").unwrap(),
+            SearchCondition::CfgScale(NumberFilter::Range(7.0, 7.0))
+        );
+        assert_eq!(
+            parse_condition("seed:>=18446744073709551615").unwrap(),
+            SearchCondition::Seed(NumberFilter::GreaterOrEqual(u64::MAX))
+        );
+        assert_eq!(
+            parse_condition("rating:<3").unwrap(),
+            SearchCondition::Rating(NumberFilter::Less(3))
+        );
+        assert_eq!(
+            parse_condition("WIDTH:<=1024").unwrap(),
+            SearchCondition::Width(NumberFilter::LessOrEqual(1024))
+        );
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        for query in [
+            "steps:abc",
+            "steps:-1",
+            "steps:>",
+            "steps:1..",
+            "steps:..5",
+            "cfg:5..7..9",
+            "rating:70000",
+            "seed:1e3",
+            "height:",
+        ] {
+            assert!(parse_query(query).is_err(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn reversed_ranges_are_rejected() {
+        assert!(parse_query("steps:30..20").is_err());
+        assert!(parse_query("cfg:7..5").is_err());
+        assert!(parse_query("lora:detail@1..0.5").is_err());
+        assert_eq!(
+            NumberFilter::<u32>::parse("20..30"),
+            Some(NumberFilter::Range(20, 30))
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_text() {
+        assert_eq!(
+            parse_query("masterpiece:1").unwrap(),
+            [term(
+                SearchCondition::Text(normalize("masterpiece:1")),
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn sql_conditions() {
+        let terms = parse_query(r#"tag:"blue hair"@>1 -tag:hat@<1 -steps:30 a*b"#).unwrap();
+        let (sql, params) = sql_condition(&terms);
+        // 重み付きの否定は SQL では判定しない
+        assert_eq!(
+            sql,
+            "(images.id IN (SELECT rowid FROM image_text WHERE positive_tags GLOB ?)) AND \
+             NOT IFNULL((images.steps = ?), 0) AND \
+             (images.id IN (SELECT rowid FROM image_text WHERE filename_text GLOB ? \
+             OR prompt_text GLOB ? OR model_text GLOB ?))"
+        );
+        assert_eq!(
+            params[0],
+            Value::Text("*\u{1f}blue hair\u{1f}*".to_string())
+        );
+        assert_eq!(params[1], Value::Integer(30));
+        assert_eq!(params[2], Value::Text("*a[*]b*".to_string()));
+        assert_eq!(params.len(), 5);
+
+        assert_eq!(sql_condition(&[]), ("1".to_string(), Vec::new()));
+    }
+}
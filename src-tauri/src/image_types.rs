@@ -1,7 +1,7 @@
-use crate::sd_parameters::SdParameters;
+use crate::sd_parameters::{SdParameters, SdTypedValues};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileSystemInfo {
     pub filename: String,
     pub parent_dir: String,
@@ -10,6 +10,74 @@ pub struct FileSystemInfo {
     pub file_modified_timestamp: u64, // Unix timestamp (seconds)
 }
 
+/// ディレクトリ内の画像一覧の項目
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageListEntry {
+    pub path: String,
+    pub file_system_info: FileSystemInfo,
+    /// 幅・高さ（ヘッダーを解析できない場合は None）
+    pub dimensions: Option<ImageDimensions>,
+    /// レーティング（未評価の場合は None）
+    pub rating: Option<u16>,
+    /// SD Parametersの概要（埋め込まれていない場合は None）
+    pub sd_summary: Option<SdSummary>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ImageDimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+/// 一覧表示用のSD Parametersの概要
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SdSummary {
+    pub model: Option<String>,
+    pub sampler: Option<String>,
+    pub steps: Option<u32>,
+    pub cfg_scale: Option<f32>,
+    #[serde(default, with = "crate::sd_parameters::seed_string")]
+    pub seed: Option<u64>,
+}
+
+/// 画像一覧の並び順
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageSortKey {
+    /// ファイル名（数字部分は数値として比較）
+    #[default]
+    Name,
+    Modified,
+    Size,
+    Rating,
+    Seed,
+    Steps,
+}
+
+/// 画像一覧の取得条件
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageListOptions {
+    #[serde(default)]
+    pub sort_by: ImageSortKey,
+    #[serde(default)]
+    pub descending: bool,
+    /// 対象とする拡張子（ドット無し、大文字小文字は区別しない。空の場合は対応形式すべて）
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// サブディレクトリも含めてひとつの一覧にする
+    #[serde(default)]
+    pub recursive: bool,
+    /// 再帰する最大の深さ（None は無制限、0 は指定ディレクトリのみ）
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+    /// 含めるファイルのパターン（指定ディレクトリからの相対パス、例: "**/2025-06-*/*.png"）
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// 除外するパスのパターン（ディレクトリに一致した場合は配下を走査しない）
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PngImageInfo {
     pub width: u32,
@@ -17,19 +85,91 @@ pub struct PngImageInfo {
     pub bit_depth: u8,
     pub color_type: String,
     pub sd_parameters: Option<SdParameters>,
+    /// sd_parameters の数値フィールドを型付きにしたもの
+    pub sd_values: Option<SdTypedValues>,
+}
+
+/// 画像形式に依存しない画像情報
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImageInfo {
+    /// 画像形式（"png" / "jpeg" / "webp" / "avif" 等）
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+    pub color_type: String,
+    /// 1チャンネルあたりのビット深度
+    pub bit_depth: u8,
+    pub has_alpha: bool,
+    pub is_animated: bool,
+    pub has_icc_profile: bool,
+    /// PNG固有の情報（PNG以外は None）
+    pub png: Option<PngImageDetails>,
+}
+
+/// PNG固有の画像情報
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PngImageDetails {
+    pub bit_depth: u8,
+    pub color_type: String,
+    pub interlaced: bool,
+    /// APNG のフレーム数（acTL チャンクが無い場合は None）
+    pub animation_frames: Option<u32>,
+    /// tEXt / zTXt / iTXt のキーワード一覧
+    pub text_keywords: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ExifImageInfo {
     pub rating: Option<u16>,
+    /// rating の読み取り元（EXIF と XMP で値が異なる場合は XMP を優先、旧キャッシュには無い）
+    #[serde(default)]
+    pub rating_source: Option<RatingSource>,
     pub create_date: Option<String>,
     pub datetime_original: Option<String>,
     pub modify_date: Option<String>,
 }
 
+/// レーティングの格納場所
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RatingSource {
+    /// EXIF の Rating タグ（18246）のみ
+    Exif,
+    /// XMP の xmp:Rating のみ（または EXIF と値が異なる）
+    Xmp,
+    /// EXIF と XMP の両方が同じ値
+    ExifAndXmp,
+}
+
+/// 採用・不採用フラグ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PickFlag {
+    Pick,
+    Reject,
+}
+
+/// 画像の選別情報（XMP に保存）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageCuration {
+    /// カラーラベル（xmp:Label、"Red" 等）
+    pub label: Option<String>,
+    /// 採用・不採用（digiKam:PickLabel、不採用は xmp:Rating = -1 も考慮）
+    pub pick: Option<PickFlag>,
+    /// キーワード（dc:subject）
+    pub keywords: Vec<String>,
+    /// 階層キーワード（lr:hierarchicalSubject、"人物|女性" のように "|" 区切り）
+    pub hierarchical_keywords: Vec<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ComprehensiveImageInfo {
     pub file_system_info: FileSystemInfo,
-    pub png_info: PngImageInfo,
+    pub image_info: ImageInfo,
     pub exif_info: Option<ExifImageInfo>,
+    pub curation: ImageCuration,
+    /// 画像形式に依らず EXIF / XMP / テキストチャンクから抽出したSD Parameters
+    pub sd_parameters: Option<SdParameters>,
+    /// sd_parameters の数値フィールドを型付きにしたもの
+    pub sd_values: Option<SdTypedValues>,
 }
@@ -1,12 +1,26 @@
+mod atomic_write;
 mod clipboard;
+mod comfyui_parameters;
+mod curation_handler;
+mod directory_watcher;
 mod exif_handler;
+mod file_format;
 mod file_system_handler;
 mod image_handler;
+mod image_metadata;
+mod image_search;
 mod image_types;
+mod library_index;
+mod library_scan;
+mod novelai_parameters;
+mod operation_history;
+mod png_chunks;
 mod png_handler;
+mod prompt_parser;
 mod sd_parameters;
 mod thumbnail_handler;
 mod webp_metadata;
+mod xmp;
 use chrono::Local;
 use colored::*;
 use log;
@@ -32,6 +46,25 @@ pub fn run() {
                     }
                 };
             app.manage(thumbnail_state);
+
+            // 操作履歴（元に戻す）を初期化
+            let operation_history = match operation_history::OperationHistory::new(app.handle()) {
+                Ok(history) => history,
+                Err(e) => {
+                    log::error!("OperationHistoryの初期化に失敗: {}", e);
+                    return Err(e.into());
+                }
+            };
+            app.manage(operation_history);
+
+            // メタデータインデックスを初期化（開けない場合も作り直し・メモリ上で続行する）
+            app.manage(library_index::LibraryIndex::new(app.handle()));
+
+            // 保存済みのメタデータ書き込み設定を反映
+            atomic_write::load_metadata_write_options(app.handle());
+
+            // ディレクトリ監視（監視対象はフロントエンドから指定）
+            app.manage(directory_watcher::DirectoryWatcherState::default());
             Ok(())
         })
         .plugin(
@@ -70,9 +103,31 @@ pub fn run() {
             // PNG操作
             png_handler::read_png_image_info,
             png_handler::clear_png_sd_parameters,
+            png_handler::clear_png_sd_parameters_batch,
+            png_handler::write_png_sd_parameters,
+            // プロンプト解析
+            prompt_parser::parse_prompt_tree,
             // EXIF操作
             exif_handler::read_exif_image_info,
             exif_handler::write_exif_image_rating,
+            exif_handler::write_ratings_batch,
+            // 選別情報（カラーラベル・採用/不採用・キーワード）
+            curation_handler::read_image_curation,
+            curation_handler::write_image_curation,
+            // メタデータ書き込み設定
+            atomic_write::get_metadata_write_options,
+            atomic_write::set_metadata_write_options,
+            // 操作履歴
+            operation_history::undo_last_operation,
+            operation_history::list_operation_history,
+            // ディレクトリ操作
+            file_system_handler::list_images_in_directory,
+            file_system_handler::update_library_index,
+            // 検索
+            image_search::search_images,
+            // ディレクトリ監視
+            directory_watcher::watch_directory,
+            directory_watcher::unwatch_directory,
             // 統合操作
             image_handler::read_comprehensive_image_info,
             // サムネイル操作
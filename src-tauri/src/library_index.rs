@@ -0,0 +1,555 @@
+// ライブラリ全体のメタデータインデックス用のモジュール
+//
+// 大きなフォルダを開くたびに全画像のメタデータを読み直さないように、ファイル情報・EXIF・
+// SD Parameters・幅と高さ・サムネイルのキャッシュキーを SQLite に保存しておく。
+// 各項目はパス・サイズ・更新日時（Unix では inode の変更日時も）が一致する間だけ有効とし、
+// ディレクトリの走査とメタデータの書き込みのたびに差分だけ更新する。
+use crate::exif_handler::read_exif_from_bytes;
+use crate::file_format::FileFormat;
+use crate::file_system_handler::{file_system_info, FileStamp};
+use crate::image_handler::read_image_dimensions;
+use crate::image_metadata::read_sd_parameters_from_bytes;
+use crate::image_search::SearchText;
+use crate::image_types::{ExifImageInfo, FileSystemInfo, ImageDimensions};
+use crate::sd_parameters::SdParameters;
+use crate::thumbnail_handler::ThumbnailHandler;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, Runtime};
+
+/// インデックスのファイル名（アプリのデータディレクトリ直下）
+const INDEX_FILE: &str = "library-index.sqlite3";
+/// スキーマのバージョン（PRAGMA user_version）。変更した場合はインデックスを作り直す
+const SCHEMA_VERSION: i64 = 3;
+
+// 検索に使う生成設定は型付きの列に、プロンプト・タグ・名前は正規化して全文検索用の
+// image_text（rowid は images.id）に保存する。seed は u64 を大小関係を保つ
+// INTEGER（seed_column_value）で保存する。
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS images (
+    id INTEGER PRIMARY KEY,
+    path TEXT UNIQUE NOT NULL,
+    parent_dir TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    file_size INTEGER NOT NULL,
+    modified_ns INTEGER NOT NULL,
+    changed_ns INTEGER,
+    created_at INTEGER NOT NULL,
+    modified_at INTEGER NOT NULL,
+    width INTEGER,
+    height INTEGER,
+    rating INTEGER,
+    steps INTEGER,
+    cfg_scale REAL,
+    seed INTEGER,
+    model TEXT,
+    exif_info TEXT,
+    sd_parameters TEXT,
+    thumbnail_cache_key TEXT NOT NULL,
+    indexed_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS images_parent_dir ON images (parent_dir);
+CREATE INDEX IF NOT EXISTS images_rating ON images (rating);
+CREATE INDEX IF NOT EXISTS images_steps ON images (steps);
+CREATE INDEX IF NOT EXISTS images_cfg_scale ON images (cfg_scale);
+CREATE INDEX IF NOT EXISTS images_seed ON images (seed);
+CREATE INDEX IF NOT EXISTS images_model ON images (model);
+CREATE VIRTUAL TABLE IF NOT EXISTS image_text USING fts5 (
+    filename_text,
+    prompt_text,
+    model_text,
+    sampler_text,
+    positive_tags,
+    negative_tags,
+    network_names,
+    tokenize = 'trigram case_sensitive 1'
+);
+CREATE TRIGGER IF NOT EXISTS images_delete_text AFTER DELETE ON images BEGIN
+    DELETE FROM image_text WHERE rowid = old.id;
+END;
+";
+
+const SELECT_COLUMNS: &str = "path, parent_dir, filename, file_size, modified_ns, changed_ns, \
+     created_at, modified_at, width, height, exif_info, sd_parameters, thumbnail_cache_key";
+
+/// インデックスに保存された画像1枚分の情報
+#[derive(Debug, Clone)]
+pub struct IndexedImage {
+    pub path: String,
+    /// 保存時のファイルの識別情報（現在のファイルと一致しない場合は古い）
+    pub stamp: FileStamp,
+    pub file_system_info: FileSystemInfo,
+    pub dimensions: Option<ImageDimensions>,
+    pub exif_info: Option<ExifImageInfo>,
+    pub sd_parameters: Option<SdParameters>,
+    pub thumbnail_cache_key: String,
+}
+
+impl IndexedImage {
+    /// 画像ファイルからインデックスの項目を作成
+    ///
+    /// 最新のサムネイルキャッシュがある場合は埋め込まれたメタデータを使い、ファイル全体は読まない。
+    pub fn read(
+        path: &Path,
+        metadata: &fs::Metadata,
+        handler: &ThumbnailHandler,
+    ) -> Result<Self, String> {
+        let path_string = path.to_string_lossy();
+        let thumbnail_cache_key = handler.generate_cache_key(&path_string);
+        match handler.cached_metadata(&path_string, metadata) {
+            Some(cached) => Self::new(
+                path,
+                metadata,
+                cached.exif_info,
+                cached.sd_parameters,
+                thumbnail_cache_key,
+            ),
+            None => Self::read_file(path, metadata, thumbnail_cache_key),
+        }
+    }
+
+    /// 画像ファイルを読み込んでインデックスの項目を作成
+    fn read_file(
+        path: &Path,
+        metadata: &fs::Metadata,
+        thumbnail_cache_key: String,
+    ) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| format!("ファイル読み込みエラー: {}", e))?;
+        let exif_info = FileFormat::detect_with_hint(&data, &path.to_string_lossy())
+            .ok()
+            .and_then(|format| read_exif_from_bytes(&data, format));
+        let sd_parameters = read_sd_parameters_from_bytes(&data);
+        Self::new(
+            path,
+            metadata,
+            exif_info,
+            sd_parameters,
+            thumbnail_cache_key,
+        )
+    }
+
+    fn new(
+        path: &Path,
+        metadata: &fs::Metadata,
+        exif_info: Option<ExifImageInfo>,
+        sd_parameters: Option<SdParameters>,
+        thumbnail_cache_key: String,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            path: path.to_string_lossy().into_owned(),
+            stamp: FileStamp::from_metadata(metadata),
+            file_system_info: file_system_info(path, metadata)?,
+            dimensions: read_image_dimensions(path)
+                .map(|(width, height)| ImageDimensions { width, height }),
+            exif_info,
+            sd_parameters,
+            thumbnail_cache_key,
+        })
+    }
+
+    /// 保存時から現在のファイルが変更されていないか
+    pub fn is_fresh(&self, metadata: &fs::Metadata) -> bool {
+        self.stamp == FileStamp::from_metadata(metadata)
+    }
+
+    /// レーティング（EXIF / XMP）
+    pub fn rating(&self) -> Option<u16> {
+        self.exif_info
+            .as_ref()
+            .and_then(|exif_info| exif_info.rating)
+    }
+}
+
+/// メタデータインデックスの状態管理用の構造体
+pub struct LibraryIndex {
+    connection: Mutex<Connection>,
+}
+
+impl LibraryIndex {
+    /// アプリのデータディレクトリのインデックスを開く（無ければ作成）
+    ///
+    /// インデックスは画像ファイルから再構築できるため、開けない・壊れている場合はファイルを削除して作り直す。
+    /// それでも開けない場合はメモリ上のインデックスで続行し、起動を妨げない（終了時に破棄される）。
+    pub fn new<R: Runtime>(app: &AppHandle<R>) -> Self {
+        let path = match Self::index_path(app) {
+            Ok(path) => path,
+            Err(e) => {
+                log::error!("{}（メモリ上のインデックスで続行）", e);
+                return Self::in_memory();
+            }
+        };
+
+        let error = match Self::open(&path) {
+            Ok(index) => return index,
+            Err(e) => e,
+        };
+        log::warn!("{}（インデックスを作り直します）", error);
+        remove_index_files(&path);
+
+        Self::open(&path).unwrap_or_else(|e| {
+            log::error!("{}（メモリ上のインデックスで続行）", e);
+            Self::in_memory()
+        })
+    }
+
+    /// インデックスファイルのパス（保存先のディレクトリも作成）
+    fn index_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+        let dir = app
+            .path()
+            .app_data_dir()
+            .map_err(|e| format!("インデックスの保存先の取得に失敗: {}", e))?;
+        fs::create_dir_all(&dir).map_err(|e| format!("インデックスの保存先の作成に失敗: {}", e))?;
+        Ok(dir.join(INDEX_FILE))
+    }
+
+    /// メモリ上のインデックス（ファイルを開けない場合の代替）
+    fn in_memory() -> Self {
+        let connection = Connection::open_in_memory()
+            .and_then(|connection| Self::initialize(&connection).map(|_| connection))
+            .expect("in-memory SQLite database must be available");
+        Self {
+            connection: Mutex::new(connection),
+        }
+    }
+
+    /// インデックスを開き、スキーマが古い場合は作り直す
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let connection =
+            Connection::open(path).map_err(|e| format!("インデックスを開けません: {}", e))?;
+        Self::initialize(&connection).map_err(|e| format!("インデックスの初期化に失敗: {}", e))?;
+        log::info!("メタデータインデックスを開きました: {}", path.display());
+
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn initialize(connection: &Connection) -> rusqlite::Result<()> {
+        connection.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        connection.pragma_update(None, "synchronous", "NORMAL")?;
+
+        let version: i64 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            // 中身はファイルから再構築できるため、移行せずに作り直す
+            connection
+                .execute_batch("DROP TABLE IF EXISTS image_text; DROP TABLE IF EXISTS images;")?;
+        }
+        connection.execute_batch(SCHEMA)?;
+        connection.pragma_update(None, "user_version", SCHEMA_VERSION)
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.connection
+            .lock()
+            .map_err(|_| "インデックスのロックに失敗".to_string())
+    }
+
+    /// ディレクトリ内の項目をパスごとに取得（`recursive` の場合はサブディレクトリも含める）
+    pub fn load_directory(
+        &self,
+        directory: &Path,
+        recursive: bool,
+    ) -> Result<HashMap<String, IndexedImage>, String> {
+        let prefix = directory_prefix(directory);
+        let connection = self.lock()?;
+        let mut statement = connection
+            .prepare_cached(&format!(
+                "SELECT {} FROM images WHERE substr(path, 1, length(?1)) = ?1",
+                SELECT_COLUMNS
+            ))
+            .map_err(|e| format!("インデックスの読み込みに失敗: {}", e))?;
+        let rows = statement
+            .query_map([&prefix], read_row)
+            .map_err(|e| format!("インデックスの読み込みに失敗: {}", e))?;
+
+        let mut images = HashMap::new();
+        for row in rows {
+            let image = row.map_err(|e| format!("インデックスの読み込みに失敗: {}", e))?;
+            if recursive || Path::new(&image.path).parent() == Some(directory) {
+                images.insert(image.path.clone(), image);
+            }
+        }
+        Ok(images)
+    }
+
+    /// ディレクトリ以下（サブディレクトリも含む）で検索条件に一致する項目を取得
+    ///
+    /// `condition` は `images` の列（`image_text` はサブクエリ）を参照する SQL の式で、
+    /// `?` のパラメーターを `params` の順に割り当てる。
+    pub fn search(
+        &self,
+        directory: &Path,
+        condition: &str,
+        params: Vec<Value>,
+    ) -> Result<Vec<IndexedImage>, String> {
+        let prefix = directory_prefix(directory);
+        let connection = self.lock()?;
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT {} FROM images WHERE substr(path, 1, length(?1)) = ?1 AND ({})",
+                SELECT_COLUMNS, condition
+            ))
+            .map_err(|e| format!("インデックスの検索に失敗: {}", e))?;
+        let rows = statement
+            .query_map(
+                params_from_iter(std::iter::once(Value::Text(prefix)).chain(params)),
+                read_row,
+            )
+            .map_err(|e| format!("インデックスの検索に失敗: {}", e))?;
+        rows.collect::<rusqlite::Result<_>>()
+            .map_err(|e| format!("インデックスの検索に失敗: {}", e))
+    }
+
+    /// 1件取得
+    pub fn get(&self, path: &str) -> Result<Option<IndexedImage>, String> {
+        let connection = self.lock()?;
+        connection
+            .query_row(
+                &format!("SELECT {} FROM images WHERE path = ?1", SELECT_COLUMNS),
+                [path],
+                read_row,
+            )
+            .optional()
+            .map_err(|e| format!("インデックスの読み込みに失敗: {}", e))
+    }
+
+    /// 項目をまとめて追加・更新（1トランザクション）
+    pub fn upsert(&self, images: &[IndexedImage]) -> Result<(), String> {
+        if images.is_empty() {
+            return Ok(());
+        }
+        let indexed_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as i64);
+
+        let mut connection = self.lock()?;
+        let transaction = connection
+            .transaction()
+            .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+        {
+            let mut statement = transaction
+                .prepare_cached(
+                    "INSERT INTO images (path, parent_dir, filename, file_size, modified_ns, \
+                     changed_ns, created_at, modified_at, width, height, rating, steps, \
+                     cfg_scale, seed, model, exif_info, sd_parameters, thumbnail_cache_key, \
+                     indexed_at) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, \
+                     ?16, ?17, ?18, ?19) \
+                     ON CONFLICT (path) DO UPDATE SET parent_dir = excluded.parent_dir, \
+                     filename = excluded.filename, file_size = excluded.file_size, \
+                     modified_ns = excluded.modified_ns, changed_ns = excluded.changed_ns, \
+                     created_at = excluded.created_at, modified_at = excluded.modified_at, \
+                     width = excluded.width, height = excluded.height, \
+                     rating = excluded.rating, steps = excluded.steps, \
+                     cfg_scale = excluded.cfg_scale, seed = excluded.seed, \
+                     model = excluded.model, exif_info = excluded.exif_info, \
+                     sd_parameters = excluded.sd_parameters, \
+                     thumbnail_cache_key = excluded.thumbnail_cache_key, \
+                     indexed_at = excluded.indexed_at \
+                     RETURNING id",
+                )
+                .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+            let mut delete_text = transaction
+                .prepare_cached("DELETE FROM image_text WHERE rowid = ?1")
+                .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+            let mut insert_text = transaction
+                .prepare_cached(
+                    "INSERT INTO image_text (rowid, filename_text, prompt_text, model_text, \
+                     sampler_text, positive_tags, negative_tags, network_names) \
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                )
+                .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+
+            for image in images {
+                let info = &image.file_system_info;
+                let parameters = image.sd_parameters.as_ref();
+                let values = parameters
+                    .map(SdParameters::typed_values)
+                    .unwrap_or_default();
+                let id: i64 = statement
+                    .query_row(
+                        params![
+                            image.path,
+                            info.parent_dir,
+                            info.filename,
+                            image.stamp.file_size as i64,
+                            image.stamp.modified as i64,
+                            image.stamp.changed,
+                            info.file_creation_timestamp as i64,
+                            info.file_modified_timestamp as i64,
+                            image.dimensions.map(|dimensions| dimensions.width),
+                            image.dimensions.map(|dimensions| dimensions.height),
+                            image.rating(),
+                            values.steps,
+                            values.cfg_scale,
+                            values.seed.map(seed_column_value),
+                            parameters.and_then(|parameters| parameters.model.as_deref()),
+                            to_json(&image.exif_info)?,
+                            to_json(&image.sd_parameters)?,
+                            image.thumbnail_cache_key,
+                            indexed_at,
+                        ],
+                        |row| row.get(0),
+                    )
+                    .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+
+                let text = SearchText::new(image);
+                delete_text
+                    .execute([id])
+                    .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+                insert_text
+                    .execute(params![
+                        id,
+                        text.filename,
+                        text.positive_prompt,
+                        text.model,
+                        text.sampler,
+                        text.positive_tags,
+                        text.negative_tags,
+                        text.networks,
+                    ])
+                    .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+            }
+        }
+        transaction
+            .commit()
+            .map_err(|e| format!("インデックスの更新に失敗: {}", e))
+    }
+
+    /// 項目をまとめて削除
+    pub fn remove(&self, paths: &[String]) -> Result<(), String> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        let mut connection = self.lock()?;
+        let transaction = connection
+            .transaction()
+            .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+        {
+            let mut statement = transaction
+                .prepare_cached("DELETE FROM images WHERE path = ?1")
+                .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+            for path in paths {
+                statement
+                    .execute([path])
+                    .map_err(|e| format!("インデックスの更新に失敗: {}", e))?;
+            }
+        }
+        transaction
+            .commit()
+            .map_err(|e| format!("インデックスの更新に失敗: {}", e))
+    }
+
+    /// 書き込み後のファイルの項目を読み直す（インデックスに無いファイルは追加しない）
+    ///
+    /// 書き込み自体は完了しているため、失敗してもログ出力のみ行う（次回の走査で更新される）。
+    pub fn refresh(&self, paths: &[String]) {
+        let mut updated = Vec::new();
+        let mut removed = Vec::new();
+
+        for path in paths {
+            let indexed = match self.get(path) {
+                Ok(Some(indexed)) => indexed,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::warn!("{}", e);
+                    return;
+                }
+            };
+
+            let image = fs::metadata(path)
+                .map_err(|e| format!("ファイル情報取得エラー: {}", e))
+                .and_then(|metadata| {
+                    IndexedImage::read_file(Path::new(path), &metadata, indexed.thumbnail_cache_key)
+                });
+            match image {
+                Ok(image) => updated.push(image),
+                Err(e) => {
+                    log::debug!("インデックスから削除: {} ({})", path, e);
+                    removed.push(path.clone());
+                }
+            }
+        }
+
+        if let Err(e) = self.upsert(&updated).and_then(|_| self.remove(&removed)) {
+            log::warn!("{}", e);
+        }
+    }
+}
+
+/// seed 列に保存する値（符号ビットを反転し、u64 の大小関係を保ったまま i64 に収める）
+pub fn seed_column_value(seed: u64) -> i64 {
+    (seed ^ (1 << 63)) as i64
+}
+
+/// 行から項目を作成（JSON が読めない列は None。型を変更した場合は SCHEMA_VERSION を上げる）
+fn read_row(row: &Row) -> rusqlite::Result<IndexedImage> {
+    let width: Option<u32> = row.get("width")?;
+    let height: Option<u32> = row.get("height")?;
+    let exif_info: Option<String> = row.get("exif_info")?;
+    let sd_parameters: Option<String> = row.get("sd_parameters")?;
+
+    Ok(IndexedImage {
+        path: row.get("path")?,
+        stamp: FileStamp {
+            file_size: row.get::<_, i64>("file_size")? as u64,
+            modified: row.get::<_, i64>("modified_ns")? as u64,
+            changed: row.get("changed_ns")?,
+        },
+        file_system_info: FileSystemInfo {
+            filename: row.get("filename")?,
+            parent_dir: row.get("parent_dir")?,
+            file_size_bytes: row.get::<_, i64>("file_size")? as u64,
+            file_creation_timestamp: row.get::<_, i64>("created_at")? as u64,
+            file_modified_timestamp: row.get::<_, i64>("modified_at")? as u64,
+        },
+        dimensions: width
+            .zip(height)
+            .map(|(width, height)| ImageDimensions { width, height }),
+        exif_info: exif_info.and_then(|json| serde_json::from_str(&json).ok()),
+        sd_parameters: sd_parameters.and_then(|json| serde_json::from_str(&json).ok()),
+        thumbnail_cache_key: row.get("thumbnail_cache_key")?,
+    })
+}
+
+/// JSON 列の値に変換
+fn to_json<T: serde::Serialize>(value: &Option<T>) -> Result<Option<String>, String> {
+    value
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| format!("インデックスのシリアライズに失敗: {}", e))
+}
+
+/// ディレクトリ以下のパスに共通する接頭辞
+///
+/// LIKE はワイルドカード文字を含むパスで誤一致するため、接頭辞を直接比較する。
+fn directory_prefix(directory: &Path) -> String {
+    let mut prefix = directory.to_string_lossy().into_owned();
+    if !prefix.ends_with(MAIN_SEPARATOR) {
+        prefix.push(MAIN_SEPARATOR);
+    }
+    prefix
+}
+
+/// インデックスファイルと WAL / 共有メモリファイルを削除
+fn remove_index_files(path: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut file = path.as_os_str().to_owned();
+        file.push(suffix);
+        match fs::remove_file(&file) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => log::warn!(
+                "インデックスの削除に失敗: {} ({})",
+                Path::new(&file).display(),
+                e
+            ),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+// 画像ライブラリ（ディレクトリ階層）の走査用のモジュール
+//
+// A1111 は outputs/txt2img-images/2025-06-01/ のように日付ごとのサブディレクトリに出力するため、
+// 出力先の階層全体をひとつのライブラリとして扱えるように、対象の画像ファイルを再帰的に列挙する。
+use crate::file_format::FileFormat;
+use crate::image_types::ImageListOptions;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Runtime};
+
+/// 走査の進捗を通知するイベント名
+pub const LIBRARY_SCAN_PROGRESS_EVENT: &str = "library-scan-progress";
+/// 進捗を通知する最小間隔（完了時は常に通知）
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 走査の進捗（`library-scan-progress` イベントのペイロード）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LibraryScanProgress {
+    /// 走査を開始したディレクトリ
+    pub directory: String,
+    pub scanned_directories: usize,
+    pub found_images: usize,
+    /// 情報の読み込みが済んだ画像の数
+    pub processed_images: usize,
+    pub finished: bool,
+}
+
+/// 進捗をまとめてフロントエンドに通知する（並列処理から呼び出せるように内部で排他制御する）
+pub struct ScanProgressReporter<R: Runtime> {
+    app: AppHandle<R>,
+    state: Mutex<(LibraryScanProgress, Instant)>,
+}
+
+impl<R: Runtime> ScanProgressReporter<R> {
+    pub fn new(app: AppHandle<R>, directory: &str) -> Self {
+        let progress = LibraryScanProgress {
+            directory: directory.to_string(),
+            ..Default::default()
+        };
+        Self {
+            app,
+            state: Mutex::new((progress, Instant::now())),
+        }
+    }
+
+    /// ディレクトリ1つの走査が完了
+    pub fn directory_scanned(&self, found_images: usize) {
+        self.update(|progress| {
+            progress.scanned_directories += 1;
+            progress.found_images += found_images;
+        });
+    }
+
+    /// 画像1枚の情報の読み込みが完了
+    pub fn image_processed(&self) {
+        self.update(|progress| progress.processed_images += 1);
+    }
+
+    /// 走査の完了を通知
+    pub fn finish(&self) {
+        self.update(|progress| progress.finished = true);
+    }
+
+    fn update(&self, apply: impl FnOnce(&mut LibraryScanProgress)) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let (progress, last_emitted) = &mut *state;
+        apply(progress);
+
+        if !progress.finished && last_emitted.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        *last_emitted = Instant::now();
+        if let Err(e) = self.app.emit(LIBRARY_SCAN_PROGRESS_EVENT, progress.clone()) {
+            log::warn!("走査の進捗の通知に失敗: {}", e);
+        }
+    }
+}
+
+/// 一覧に含めるファイルの条件
+pub struct ScanFilter {
+    /// 対象とする拡張子（小文字、空の場合は対応形式すべて）
+    extensions: Vec<String>,
+    /// 含めるパスのパターン（None はすべて）
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    /// 走査するサブディレクトリの深さの上限（0 は開始ディレクトリのみ）
+    max_depth: u32,
+}
+
+impl ScanFilter {
+    pub fn new(options: &ImageListOptions) -> Result<Self, String> {
+        let extensions = options
+            .extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_lowercase())
+            .collect();
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(&options.include)?)
+        };
+
+        Ok(Self {
+            extensions,
+            include,
+            exclude: build_glob_set(&options.exclude)?,
+            max_depth: if options.recursive {
+                options.max_depth.unwrap_or(u32::MAX)
+            } else {
+                0
+            },
+        })
+    }
+
+    /// `root` の走査で列挙されるパスかどうか（ファイルの有無は確認しない）
+    ///
+    /// インデックス済みのパスを走査と同じ条件（深さ・隠しファイル・除外するディレクトリ・
+    /// 拡張子とパターン）で絞り込むために使う。
+    pub fn is_scanned_path(&self, root: &Path, path: &Path) -> bool {
+        let Ok(relative_path) = path.strip_prefix(root) else {
+            return false;
+        };
+        let components: Vec<_> = relative_path.components().collect();
+        if components.is_empty() || components.len() - 1 > self.max_depth as usize {
+            return false;
+        }
+
+        let is_hidden = components.iter().any(|component| {
+            component
+                .as_os_str()
+                .to_str()
+                .is_none_or(|name| name.starts_with('.'))
+        });
+        let in_excluded_directory = relative_path
+            .ancestors()
+            .skip(1)
+            .take(components.len() - 1)
+            .any(|directory| self.exclude.is_match(directory));
+
+        !is_hidden && !in_excluded_directory && self.is_listed_image(path, relative_path)
+    }
+
+    /// 一覧に含める画像ファイルかどうか（拡張子とパターンで判定）
+    fn is_listed_image(&self, path: &Path, relative_path: &Path) -> bool {
+        let Some(path_str) = path.to_str() else {
+            return false;
+        };
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_lowercase)
+            .unwrap_or_default();
+
+        FileFormat::from_extension(path_str).is_some()
+            && (self.extensions.is_empty() || self.extensions.contains(&extension))
+            && self
+                .include
+                .as_ref()
+                .is_none_or(|include| include.is_match(relative_path))
+            && !self.exclude.is_match(relative_path)
+    }
+}
+
+/// パターン一覧から GlobSet を作成（"*" はディレクトリの区切りに一致しない）
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| format!("パターンが不正です: {} ({})", pattern, e))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| format!("パターンの作成に失敗: {}", e))
+}
+
+/// ディレクトリ内の画像ファイルを列挙（`recursive` の場合はサブディレクトリも含める）
+///
+/// 隠しファイル・隠しディレクトリ（"." で始まる名前）は含めない。シンボリックリンクは辿るが、
+/// 実体が同じディレクトリは一度しか走査しないため、リンクのループがあっても終了する。
+/// 開始ディレクトリ以外の読み込みエラーはログに残して無視する。
+pub fn scan_image_paths<R: Runtime>(
+    root: &Path,
+    options: &ImageListOptions,
+    progress: &ScanProgressReporter<R>,
+) -> Result<Vec<PathBuf>, String> {
+    let filter = ScanFilter::new(options)?;
+
+    let mut image_paths = Vec::new();
+    let mut visited_directories = HashSet::new();
+    let mut pending_directories = vec![(root.to_path_buf(), 0)];
+
+    while let Some((directory, depth)) = pending_directories.pop() {
+        // リンクのループや、同じ実体を指す複数のリンクは一度だけ走査
+        if let Ok(canonical) = fs::canonicalize(&directory) {
+            if !visited_directories.insert(canonical) {
+                log::debug!("走査済みのディレクトリをスキップ: {}", directory.display());
+                continue;
+            }
+        }
+
+        let entries = match fs::read_dir(&directory) {
+            Ok(entries) => entries,
+            Err(e) if depth == 0 => {
+                return Err(format!(
+                    "ディレクトリ読み込みエラー: {} ({})",
+                    e,
+                    directory.display()
+                ))
+            }
+            Err(e) => {
+                log::warn!(
+                    "ディレクトリ読み込みエラー: {} ({})",
+                    e,
+                    directory.display()
+                );
+                continue;
+            }
+        };
+
+        let mut found_images = 0;
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let is_hidden = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_none_or(|name| name.starts_with('.'));
+            if is_hidden {
+                continue;
+            }
+            // シンボリックリンクはリンク先の種類で判定
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+
+            let relative_path = path.strip_prefix(root).unwrap_or(&path);
+            if metadata.is_dir() {
+                if depth < filter.max_depth && !filter.exclude.is_match(relative_path) {
+                    pending_directories.push((path, depth + 1));
+                }
+            } else if metadata.is_file() && filter.is_listed_image(&path, relative_path) {
+                image_paths.push(path);
+                found_images += 1;
+            }
+        }
+        progress.directory_scanned(found_images);
+    }
+
+    Ok(image_paths)
+}
@@ -0,0 +1,369 @@
+// NovelAI の Comment チャンク / stealth pnginfo 解析用のモジュール
+use crate::sd_parameters::{SdParameterSource, SdParameters};
+use flate2::read::GzDecoder;
+use image::RgbaImage;
+use serde_json::Value;
+use std::io::{Cursor, Read};
+
+/// stealth pnginfo のシグネチャ（非圧縮）
+const STEALTH_SIGNATURE_PLAIN: &[u8] = b"stealth_pnginfo";
+/// stealth pnginfo のシグネチャ（gzip圧縮）
+const STEALTH_SIGNATURE_COMPRESSED: &[u8] = b"stealth_pngcomp";
+
+/// NovelAI が書き込むテキストチャンク一式
+#[derive(Debug, Clone, Default)]
+pub struct NovelAiChunks {
+    pub comment: Option<String>,
+    pub software: Option<String>,
+    pub source: Option<String>,
+}
+
+impl NovelAiChunks {
+    /// NovelAI の生成画像かどうか
+    pub fn is_novelai(&self) -> bool {
+        self.software
+            .as_deref()
+            .is_some_and(|software| software.starts_with("NovelAI"))
+            || self.comment.as_deref().is_some_and(is_novelai_comment)
+    }
+}
+
+/// Comment がNovelAI形式のJSONかどうか（Software チャンクが欠けている場合の判定用）
+fn is_novelai_comment(comment: &str) -> bool {
+    serde_json::from_str::<Value>(comment)
+        .ok()
+        .and_then(|value| value.as_object().cloned())
+        .is_some_and(|object| object.contains_key("prompt") && object.contains_key("uc"))
+}
+
+/// JSON値を文字列として取得
+fn json_string(object: &Value, key: &str) -> Option<String> {
+    match object.get(key)? {
+        Value::String(s) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// v4 形式のキャプション（base_caption + char_captions）を1つのプロンプトに結合
+fn v4_caption_text(comment: &Value, key: &str) -> Option<String> {
+    let caption = comment.get(key)?.get("caption")?;
+    let mut texts: Vec<String> = json_string(caption, "base_caption").into_iter().collect();
+    if let Some(characters) = caption.get("char_captions").and_then(|c| c.as_array()) {
+        texts.extend(
+            characters
+                .iter()
+                .filter_map(|character| json_string(character, "char_caption")),
+        );
+    }
+    (!texts.is_empty()).then(|| texts.join(", "))
+}
+
+/// NovelAI の Comment JSON をパースする
+pub fn parse_novelai_comment(comment: &str, source: Option<&str>) -> Result<SdParameters, String> {
+    let value: Value = serde_json::from_str(comment)
+        .map_err(|e| format!("NovelAI Comment のJSON解析エラー: {}", e))?;
+    if !value.is_object() {
+        return Err("NovelAI Comment がJSONオブジェクトではありません".to_string());
+    }
+
+    let prompt = json_string(&value, "prompt")
+        .or_else(|| v4_caption_text(&value, "v4_prompt"))
+        .unwrap_or_default();
+    let negative_prompt = json_string(&value, "uc")
+        .or_else(|| v4_caption_text(&value, "v4_negative_prompt"))
+        .unwrap_or_default();
+
+    let size = match (json_string(&value, "width"), json_string(&value, "height")) {
+        (Some(width), Some(height)) => Some(format!("{}x{}", width, height)),
+        _ => None,
+    };
+
+    Ok(SdParameters {
+        source: SdParameterSource::NovelAi,
+        positive_sd_tags: SdParameters::parse_sd_tags(&prompt),
+        negative_sd_tags: SdParameters::parse_sd_tags(&negative_prompt),
+        positive_prompt: prompt,
+        negative_prompt,
+        steps: json_string(&value, "steps"),
+        sampler: json_string(&value, "sampler"),
+        schedule_type: json_string(&value, "noise_schedule"),
+        cfg_scale: json_string(&value, "scale"),
+        seed: json_string(&value, "seed"),
+        size,
+        model: source
+            .map(|source| source.trim().to_string())
+            .filter(|source| !source.is_empty()),
+        raw: comment.to_string(),
+        ..Default::default()
+    }
+    .with_networks())
+}
+
+/// NovelAI のテキストチャンク一式からSD Parametersを構築
+pub fn parse_novelai_chunks(chunks: &NovelAiChunks) -> Result<SdParameters, String> {
+    let comment = chunks
+        .comment
+        .as_deref()
+        .ok_or_else(|| "NovelAI の Comment チャンクが見つかりません".to_string())?;
+    parse_novelai_comment(comment, chunks.source.as_deref())
+}
+
+/// アルファチャンネルのLSBを列優先で読み出すビットリーダー
+struct AlphaBitReader<'a> {
+    image: &'a RgbaImage,
+    index: u64,
+}
+
+impl<'a> AlphaBitReader<'a> {
+    fn new(image: &'a RgbaImage) -> Self {
+        Self { image, index: 0 }
+    }
+
+    fn remaining_bits(&self) -> u64 {
+        let total = self.image.width() as u64 * self.image.height() as u64;
+        total.saturating_sub(self.index)
+    }
+
+    fn read_bit(&mut self) -> Option<u8> {
+        let height = self.image.height() as u64;
+        if height == 0 || self.remaining_bits() == 0 {
+            return None;
+        }
+        // NovelAI は x を外側、y を内側としてピクセルを走査する
+        let x = (self.index / height) as u32;
+        let y = (self.index % height) as u32;
+        self.index += 1;
+        Some(self.image.get_pixel(x, y)[3] & 1)
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Option<Vec<u8>> {
+        if self.remaining_bits() < count as u64 * 8 {
+            return None;
+        }
+        (0..count)
+            .map(|_| (0..8).try_fold(0u8, |byte, _| Some((byte << 1) | self.read_bit()?)))
+            .collect()
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        let bytes = self.read_bytes(4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// PNG の先頭の行だけをデコードし、stealth pnginfo のシグネチャがあるか判定
+///
+/// シグネチャは列優先で埋め込まれるため、高さが十分あれば先頭 120 行の左端の列に収まる。
+/// 8bit のノンインターレース以外の RGBA / グレースケール+アルファは判定できないため true を返す。
+pub fn has_stealth_signature(data: &[u8]) -> bool {
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let Ok(mut reader) = decoder.read_info() else {
+        return false;
+    };
+    let info = reader.info();
+    let channels = match info.color_type {
+        png::ColorType::Rgba => 4,
+        png::ColorType::GrayscaleAlpha => 2,
+        _ => return false,
+    };
+    if info.bit_depth != png::BitDepth::Eight || info.interlaced {
+        return true;
+    }
+
+    let (width, height) = (info.width as usize, info.height as usize);
+    let signature_bits = STEALTH_SIGNATURE_PLAIN.len() * 8;
+    if width * height < signature_bits {
+        return false;
+    }
+
+    let mut bits = vec![0u8; signature_bits];
+    for y in 0..height.min(signature_bits) {
+        let Ok(Some(row)) = reader.next_row() else {
+            return false;
+        };
+        let row = row.data();
+        // 列優先の通し番号（x * height + y）がシグネチャの範囲内の画素のみ
+        for (x, index) in (0..width)
+            .map(|x| (x, x * height + y))
+            .take_while(|&(_, index)| index < signature_bits)
+        {
+            bits[index] = row[x * channels + channels - 1] & 1;
+        }
+    }
+
+    let signature: Vec<u8> = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0, |value, bit| (value << 1) | bit))
+        .collect();
+    signature == STEALTH_SIGNATURE_PLAIN || signature == STEALTH_SIGNATURE_COMPRESSED
+}
+
+/// アルファチャンネルに埋め込まれた stealth pnginfo を読み取る
+pub fn decode_stealth_pnginfo(image: &RgbaImage) -> Option<NovelAiChunks> {
+    let mut reader = AlphaBitReader::new(image);
+
+    let signature = reader.read_bytes(STEALTH_SIGNATURE_PLAIN.len())?;
+    let compressed = match signature.as_slice() {
+        STEALTH_SIGNATURE_PLAIN => false,
+        STEALTH_SIGNATURE_COMPRESSED => true,
+        _ => return None,
+    };
+
+    // データ長はビット単位で格納されている
+    let bit_length = reader.read_u32()? as usize;
+    let payload = reader.read_bytes(bit_length / 8)?;
+
+    let json_text = if compressed {
+        let mut decoded = String::new();
+        GzDecoder::new(payload.as_slice())
+            .read_to_string(&mut decoded)
+            .ok()?;
+        decoded
+    } else {
+        String::from_utf8(payload).ok()?
+    };
+
+    let value: Value = serde_json::from_str(&json_text).ok()?;
+
+    // Comment は文字列化されたJSON、またはJSONオブジェクトのまま格納されている
+    let comment = match value.get("Comment") {
+        Some(Value::String(comment)) => Some(comment.clone()),
+        Some(comment @ Value::Object(_)) => Some(comment.to_string()),
+        _ => None,
+    };
+
+    Some(NovelAiChunks {
+        comment,
+        software: json_string(&value, "Software"),
+        source: json_string(&value, "Source"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use image::{ImageFormat, Rgba};
+    use std::io::Write;
+
+    /// NovelAI Diffusion V3 の生成画像の Comment チャンク
+    const V3_COMMENT: &str = r#"{"prompt": "1girl, {{blue hair}}, [smile], best quality, amazing quality, very aesthetic, absurdres", "steps": 28, "height": 1216, "width": 832, "scale": 5.0, "uncond_scale": 1.0, "cfg_rescale": 0.0, "seed": 3510236364, "n_samples": 1, "hide_debug_overlay": false, "noise_schedule": "native", "legacy_v3_extend": false, "reference_information_extracted_multiple": [], "reference_strength_multiple": [], "sampler": "k_euler_ancestral", "controlnet_strength": 1.0, "controlnet_model": null, "dynamic_thresholding": false, "dynamic_thresholding_percentile": 0.999, "dynamic_thresholding_mimic_scale": 10.0, "sm": false, "sm_dyn": false, "skip_cfg_above_sigma": null, "skip_cfg_below_sigma": 0.0, "lora_unet_weights": null, "lora_clip_weights": null, "deliberate_euler_ancestral_bug": false, "prefer_brownian": true, "cfg_sched_eligibility": "enable_for_post_summer_samplers", "explike_fine_detail": false, "minimize_sigma_inf": false, "uncond_per_vibe": true, "wonky_vibe_correlation": true, "version": 1, "uc": "nsfw, lowres, {bad}, error, fewer, extra, missing, worst quality, jpeg artifacts, bad quality", "request_type": "PromptGenerateRequest", "signed_hash": "Zr2zWl7NJ1n0hQhE0sqQqgS2yZ9ZbZs0c8jA0m4bHqk="}"#;
+
+    /// NovelAI Diffusion V4 の Comment チャンク（キャラクターごとのキャプションのみ）
+    const V4_COMMENT: &str = r#"{"steps": 23, "height": 1216, "width": 832, "scale": 6.0, "uncond_scale": 1.0, "cfg_rescale": 0.0, "seed": 1285401811, "n_samples": 1, "noise_schedule": "karras", "sampler": "k_euler_ancestral", "v4_prompt": {"caption": {"base_caption": "2girls, outdoors, cherry blossoms", "char_captions": [{"char_caption": "girl, red hair, school uniform", "centers": [{"x": 0.3, "y": 0.5}]}, {"char_caption": "girl, black hair, kimono", "centers": [{"x": 0.7, "y": 0.5}]}]}, "use_coords": true, "use_order": true}, "v4_negative_prompt": {"caption": {"base_caption": "lowres, bad anatomy", "char_captions": [{"char_caption": "", "centers": [{"x": 0.5, "y": 0.5}]}]}, "legacy_uc": false}, "request_type": "PromptGenerateRequest"}"#;
+
+    /// stealth pnginfo（gzip 圧縮）をアルファチャンネルに埋め込んだ PNG
+    fn stealth_compressed_png(json: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(json.as_bytes()).unwrap();
+        let payload = encoder.finish().unwrap();
+
+        let mut bytes = STEALTH_SIGNATURE_COMPRESSED.to_vec();
+        bytes.extend_from_slice(&((payload.len() * 8) as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
+
+        let mut image = RgbaImage::from_pixel(128, 128, Rgba([200, 160, 120, 255]));
+        let height = image.height();
+        let bits = bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1));
+        for (index, bit) in bits.enumerate() {
+            let pixel = image.get_pixel_mut(index as u32 / height, index as u32 % height);
+            pixel[3] = (pixel[3] & !1) | bit;
+        }
+
+        let mut data = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn v3_comment() {
+        let parameters =
+            parse_novelai_comment(V3_COMMENT, Some("NovelAI Diffusion V3 7BCCAA2C")).unwrap();
+        assert_eq!(
+            parameters.positive_prompt,
+            "1girl, {{blue hair}}, [smile], best quality, amazing quality, very aesthetic, absurdres"
+        );
+        assert!(parameters
+            .negative_prompt
+            .starts_with("nsfw, lowres, {bad}"));
+        assert_eq!(parameters.steps.as_deref(), Some("28"));
+        assert_eq!(parameters.sampler.as_deref(), Some("k_euler_ancestral"));
+        assert_eq!(parameters.schedule_type.as_deref(), Some("native"));
+        assert_eq!(parameters.cfg_scale.as_deref(), Some("5.0"));
+        assert_eq!(parameters.seed.as_deref(), Some("3510236364"));
+        assert_eq!(parameters.size.as_deref(), Some("832x1216"));
+        assert_eq!(
+            parameters.model.as_deref(),
+            Some("NovelAI Diffusion V3 7BCCAA2C")
+        );
+        assert_eq!(parameters.raw, V3_COMMENT);
+    }
+
+    #[test]
+    fn v4_captions_are_joined() {
+        let parameters = parse_novelai_comment(V4_COMMENT, None).unwrap();
+        assert_eq!(
+            parameters.positive_prompt,
+            "2girls, outdoors, cherry blossoms, girl, red hair, school uniform, girl, black hair, kimono"
+        );
+        assert_eq!(parameters.negative_prompt, "lowres, bad anatomy");
+        assert_eq!(parameters.schedule_type.as_deref(), Some("karras"));
+        assert_eq!(parameters.model, None);
+    }
+
+    #[test]
+    fn comment_without_software_is_detected() {
+        let chunks = NovelAiChunks {
+            comment: Some(V3_COMMENT.to_string()),
+            ..Default::default()
+        };
+        assert!(chunks.is_novelai());
+        let other = NovelAiChunks {
+            comment: Some("Made with GIMP".to_string()),
+            ..Default::default()
+        };
+        assert!(!other.is_novelai());
+        assert!(parse_novelai_chunks(&other).is_err());
+    }
+
+    #[test]
+    fn compressed_stealth_pnginfo() {
+        let json = serde_json::json!({
+            "Description": "1girl, {{blue hair}}",
+            "Software": "NovelAI",
+            "Source": "NovelAI Diffusion V3 7BCCAA2C",
+            "Comment": V3_COMMENT,
+        })
+        .to_string();
+        let data = stealth_compressed_png(&json);
+        assert!(has_stealth_signature(&data));
+
+        let image = image::load_from_memory(&data).unwrap().to_rgba8();
+        let chunks = decode_stealth_pnginfo(&image).unwrap();
+        assert_eq!(chunks.software.as_deref(), Some("NovelAI"));
+        let parameters = parse_novelai_chunks(&chunks).unwrap();
+        assert_eq!(parameters.seed.as_deref(), Some("3510236364"));
+        assert_eq!(
+            parameters.model.as_deref(),
+            Some("NovelAI Diffusion V3 7BCCAA2C")
+        );
+    }
+
+    #[test]
+    fn image_without_stealth_pnginfo() {
+        let image = RgbaImage::from_pixel(64, 64, Rgba([0, 0, 0, 255]));
+        assert!(decode_stealth_pnginfo(&image).is_none());
+        let mut data = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        assert!(!has_stealth_signature(&data));
+    }
+}
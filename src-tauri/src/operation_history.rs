@@ -0,0 +1,567 @@
+// メタデータ変更操作の履歴（元に戻す）用のモジュール
+//
+// 変更系コマンドが置き換える前のメタデータ（画素データは含まない）をアプリデータディレクトリに保存し、
+// 直前の操作をファイル単位で元に戻せるようにする。
+//
+// 保存形式: history/{操作ID}/operation.json と、ファイルごとのスナップショット {連番}.bin
+use crate::atomic_write::write_file_atomic;
+use crate::exif_handler::BatchWriteResult;
+use crate::image_metadata::{
+    image_data_digest, restore_metadata, snapshot_metadata, MetadataBlock,
+};
+use crate::library_index::LibraryIndex;
+use crate::thumbnail_handler::ThumbnailState;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, Runtime};
+
+/// 保持する操作の最大数（古いものから削除）
+const MAX_OPERATIONS: usize = 50;
+/// 操作情報のファイル名
+const OPERATION_FILE: &str = "operation.json";
+/// スナップショットファイルの識別子
+const SNAPSHOT_MAGIC: &[u8; 4] = b"SDMS";
+
+/// 操作IDの重複を避けるための連番
+static OPERATION_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// 操作の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationKind {
+    WriteRating,
+    ClearSdParameters,
+    WriteSdParameters,
+    WriteCuration,
+}
+
+/// 操作で変更されたファイルとスナップショットのファイル名
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OperationFile {
+    path: String,
+    snapshot: String,
+    /// 書き込み後のメタデータ以外（画素データ等）のハッシュ（復元先が同じ画像かの確認用）
+    image_digest: String,
+}
+
+/// ファイルを復元できなかった理由
+enum RestoreError {
+    /// 記録後に別の画像に置き換えられた（再試行しても復元できないため履歴から外す）
+    Replaced,
+    Failed(String),
+}
+
+/// 保存された操作
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OperationRecord {
+    id: String,
+    kind: OperationKind,
+    /// Unix timestamp (seconds)
+    created_at: u64,
+    files: Vec<OperationFile>,
+}
+
+impl OperationRecord {
+    fn summary(&self) -> OperationSummary {
+        OperationSummary {
+            id: self.id.clone(),
+            kind: self.kind,
+            created_at: self.created_at,
+            paths: self.files.iter().map(|file| file.path.clone()).collect(),
+        }
+    }
+}
+
+/// 操作履歴の概要
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OperationSummary {
+    pub id: String,
+    pub kind: OperationKind,
+    /// Unix timestamp (seconds)
+    pub created_at: u64,
+    pub paths: Vec<String>,
+}
+
+/// 元に戻した結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndoResult {
+    pub operation: OperationSummary,
+    /// ファイルごとの結果（失敗したファイルは置き換えられた画像を除き履歴に残り、再度元に戻せる）
+    pub results: Vec<BatchWriteResult>,
+}
+
+/// 記録中の操作（変更系コマンドの開始時に作成し、書き込みに成功したファイルがあれば commit する）
+pub struct Operation {
+    dir: PathBuf,
+    record: Mutex<OperationRecord>,
+    snapshot_counter: AtomicUsize,
+}
+
+impl Operation {
+    /// ファイルをアトミックに書き込み、成功した場合のみ書き込み前のメタデータを記録
+    ///
+    /// 書き込みに失敗したファイルはそのまま残るため、履歴には含めない。
+    pub fn write(&self, path: &str, original: &[u8], updated: &[u8]) -> Result<(), String> {
+        write_file_atomic(path, updated)?;
+        self.snapshot(path, original, updated);
+        Ok(())
+    }
+
+    /// 書き込み前のデータからメタデータを記録
+    ///
+    /// 記録できない場合（非対応の形式等）も書き込み自体は完了しており、そのファイルは元に戻せない。
+    fn snapshot(&self, path: &str, data: &[u8], updated: &[u8]) {
+        if let Err(e) = self.try_snapshot(path, data, updated) {
+            log::warn!(
+                "変更前のメタデータを記録できません（元に戻せません）: {} ({})",
+                path,
+                e
+            );
+        }
+    }
+
+    fn try_snapshot(&self, path: &str, data: &[u8], updated: &[u8]) -> Result<(), String> {
+        let blocks = snapshot_metadata(data)?;
+        let index = self.snapshot_counter.fetch_add(1, Ordering::Relaxed);
+        let snapshot = format!("{}.bin", index);
+
+        fs::create_dir_all(&self.dir)
+            .map_err(|e| format!("履歴ディレクトリの作成に失敗: {}", e))?;
+        fs::write(self.dir.join(&snapshot), encode_snapshot(&blocks))
+            .map_err(|e| format!("スナップショットの保存に失敗: {}", e))?;
+
+        self.record
+            .lock()
+            .map_err(|_| "履歴のロックに失敗".to_string())?
+            .files
+            .push(OperationFile {
+                path: path.to_string(),
+                snapshot,
+                image_digest: image_data_digest(updated),
+            });
+        Ok(())
+    }
+}
+
+/// 操作履歴の状態管理用の構造体
+pub struct OperationHistory {
+    dir: PathBuf,
+    /// commit・元に戻す・一覧取得の排他制御
+    lock: Mutex<()>,
+}
+
+impl OperationHistory {
+    /// 新しいOperationHistoryを作成（アプリのデータディレクトリの history を使用）
+    pub fn new<R: Runtime>(app: &AppHandle<R>) -> Result<Self, String> {
+        let dir = app
+            .path()
+            .app_data_dir()
+            .map(|data_dir| data_dir.join("history"))
+            .map_err(|e| format!("履歴ディレクトリの取得に失敗: {}", e))?;
+        Self::open(dir)
+    }
+
+    /// 指定ディレクトリの履歴を開く（前回の異常終了で残った未完了の操作は削除）
+    pub fn open(dir: PathBuf) -> Result<Self, String> {
+        fs::create_dir_all(&dir).map_err(|e| format!("履歴ディレクトリの作成に失敗: {}", e))?;
+
+        let history = Self {
+            dir,
+            lock: Mutex::new(()),
+        };
+        history.remove_incomplete_operations();
+        Ok(history)
+    }
+
+    /// 操作の記録を開始
+    pub fn begin(&self, kind: OperationKind) -> Operation {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let counter = OPERATION_COUNTER.fetch_add(1, Ordering::Relaxed);
+        // 辞書順が作成順になるようにゼロ埋め
+        let id = format!("{:013}-{:06}", now.as_millis(), counter % 1_000_000);
+
+        Operation {
+            dir: self.dir.join(&id),
+            record: Mutex::new(OperationRecord {
+                id,
+                kind,
+                created_at: now.as_secs(),
+                files: Vec::new(),
+            }),
+            snapshot_counter: AtomicUsize::new(0),
+        }
+    }
+
+    /// 操作を履歴に確定（記録したファイルが無い場合は破棄）
+    ///
+    /// 履歴の保存に失敗しても書き込み自体は完了しているため、ログ出力のみ行う。
+    pub fn commit(&self, operation: Operation) {
+        let Ok(record) = operation.record.into_inner() else {
+            log::warn!("操作履歴のロックに失敗しました");
+            return;
+        };
+        if record.files.is_empty() {
+            let _ = fs::remove_dir_all(&operation.dir);
+            return;
+        }
+
+        let _guard = self.lock.lock();
+        if let Err(e) = write_record(&operation.dir, &record) {
+            log::warn!("操作履歴の保存に失敗: {}", e);
+            let _ = fs::remove_dir_all(&operation.dir);
+            return;
+        }
+        self.prune();
+    }
+
+    /// 保存された操作を古い順に読み込み
+    fn load_records(&self) -> Vec<(PathBuf, OperationRecord)> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+
+        let mut records: Vec<(PathBuf, OperationRecord)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let dir = entry.path();
+                let json = fs::read(dir.join(OPERATION_FILE)).ok()?;
+                let record = serde_json::from_slice(&json).ok()?;
+                Some((dir, record))
+            })
+            .collect();
+        records.sort_by(|(_, a), (_, b)| a.id.cmp(&b.id));
+        records
+    }
+
+    /// 最大数を超えた古い操作を削除
+    ///
+    /// ディレクトリ名が操作ID（辞書順が作成順）のため、operation.json を読まずに名前順で判定する。
+    /// 記録中（commit 前）の操作は operation.json が無いため対象外。
+    fn prune(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut dirs: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|dir| dir.join(OPERATION_FILE).is_file())
+            .collect();
+        dirs.sort();
+
+        let excess = dirs.len().saturating_sub(MAX_OPERATIONS);
+        for dir in dirs.into_iter().take(excess) {
+            if let Err(e) = fs::remove_dir_all(&dir) {
+                log::warn!("古い操作履歴の削除に失敗: {} ({})", dir.display(), e);
+            }
+        }
+    }
+
+    /// operation.json の無い（commit されなかった）操作を削除
+    fn remove_incomplete_operations(&self) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for dir in entries.flatten().map(|entry| entry.path()) {
+            if dir.is_dir() && !dir.join(OPERATION_FILE).exists() {
+                let _ = fs::remove_dir_all(&dir);
+            }
+        }
+    }
+
+    /// 直前の操作を元に戻す（中核処理）
+    pub fn undo_last(&self) -> Result<Option<UndoResult>, String> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| "操作履歴のロックに失敗".to_string())?;
+        let Some((dir, mut record)) = self.load_records().pop() else {
+            return Ok(None);
+        };
+        let operation = record.summary();
+
+        // 同じファイルが複数回記録されている場合に最初の状態へ戻るよう、新しい順に復元
+        let mut results = Vec::with_capacity(record.files.len());
+        let mut failed_files = Vec::new();
+        for file in record.files.iter().rev() {
+            let error = match restore_file(&dir, file) {
+                Ok(()) => None,
+                Err(RestoreError::Replaced) => {
+                    log::warn!("記録後に画像が置き換えられています: {}", file.path);
+                    Some(
+                        "画像が記録後に置き換えられているため、メタデータを復元できません"
+                            .to_string(),
+                    )
+                }
+                Err(RestoreError::Failed(e)) => {
+                    log::warn!("メタデータの復元に失敗: {} ({})", file.path, e);
+                    failed_files.push(file.clone());
+                    Some(e)
+                }
+            };
+            results.push(BatchWriteResult {
+                path: file.path.clone(),
+                error,
+            });
+        }
+        results.reverse();
+
+        if failed_files.is_empty() {
+            fs::remove_dir_all(&dir).map_err(|e| format!("操作履歴の削除に失敗: {}", e))?;
+        } else {
+            failed_files.reverse();
+            record.files = failed_files;
+            write_record(&dir, &record)?;
+        }
+
+        log::info!(
+            "操作を元に戻しました: {:?} ({}ファイル)",
+            operation.kind,
+            operation.paths.len()
+        );
+        Ok(Some(UndoResult { operation, results }))
+    }
+
+    /// 操作履歴の一覧（新しい順）
+    pub fn list(&self) -> Result<Vec<OperationSummary>, String> {
+        let _guard = self
+            .lock
+            .lock()
+            .map_err(|_| "操作履歴のロックに失敗".to_string())?;
+        Ok(self
+            .load_records()
+            .iter()
+            .rev()
+            .map(|(_, record)| record.summary())
+            .collect())
+    }
+}
+
+/// 直前の操作を元に戻す（Tauri API）
+#[tauri::command]
+pub fn undo_last_operation(
+    state: tauri::State<'_, ThumbnailState>,
+    history: tauri::State<'_, OperationHistory>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<Option<UndoResult>, String> {
+    let undo_result = history.undo_last()?;
+    if let Some(undo_result) = &undo_result {
+        let restored_paths: Vec<String> = undo_result
+            .results
+            .iter()
+            .filter(|result| result.error.is_none())
+            .map(|result| result.path.clone())
+            .collect();
+        state.handler.refresh_cache(&restored_paths);
+        index.refresh(&restored_paths);
+    }
+    Ok(undo_result)
+}
+
+/// 操作履歴の一覧を取得（Tauri API）
+#[tauri::command]
+pub fn list_operation_history(
+    history: tauri::State<'_, OperationHistory>,
+) -> Result<Vec<OperationSummary>, String> {
+    history.list()
+}
+
+/// operation.json を書き込み
+fn write_record(dir: &Path, record: &OperationRecord) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(record)
+        .map_err(|e| format!("操作履歴のシリアライズに失敗: {}", e))?;
+    fs::write(dir.join(OPERATION_FILE), json).map_err(|e| format!("操作履歴の保存に失敗: {}", e))
+}
+
+/// スナップショットのメタデータでファイルを復元
+///
+/// 記録後にファイルが再生成・置換された場合は、別の画像のメタデータで上書きしないよう復元しない。
+fn restore_file(dir: &Path, file: &OperationFile) -> Result<(), RestoreError> {
+    let data = fs::read(&file.path)
+        .map_err(|e| RestoreError::Failed(format!("ファイル読み込みエラー: {}", e)))?;
+    if image_data_digest(&data) != file.image_digest {
+        return Err(RestoreError::Replaced);
+    }
+
+    let snapshot = fs::read(dir.join(&file.snapshot))
+        .map_err(|e| RestoreError::Failed(format!("スナップショットの読み込みに失敗: {}", e)))?;
+    let blocks = decode_snapshot(&snapshot).map_err(RestoreError::Failed)?;
+    let restored = restore_metadata(&data, &blocks).map_err(RestoreError::Failed)?;
+    write_file_atomic(&file.path, &restored).map_err(RestoreError::Failed)
+}
+
+/// スナップショットをバイト列に変換（識別子 + [種類4バイト + 長さ4バイト + データ] の繰り返し）
+fn encode_snapshot(blocks: &[MetadataBlock]) -> Vec<u8> {
+    let mut out = SNAPSHOT_MAGIC.to_vec();
+    for block in blocks {
+        out.extend_from_slice(&block.tag);
+        out.extend_from_slice(&(block.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&block.data);
+    }
+    out
+}
+
+/// バイト列からスナップショットを復元
+fn decode_snapshot(data: &[u8]) -> Result<Vec<MetadataBlock>, String> {
+    let invalid = || "スナップショットの形式が不正です".to_string();
+    let mut rest = data.strip_prefix(SNAPSHOT_MAGIC).ok_or_else(invalid)?;
+
+    let mut blocks = Vec::new();
+    while !rest.is_empty() {
+        let (header, body) = rest.split_at_checked(8).ok_or_else(invalid)?;
+        let tag: [u8; 4] = header[..4].try_into().map_err(|_| invalid())?;
+        let length = u32::from_le_bytes(header[4..].try_into().map_err(|_| invalid())?) as usize;
+        let (block, remaining) = body.split_at_checked(length).ok_or_else(invalid)?;
+        blocks.push(MetadataBlock {
+            tag,
+            data: block.to_vec(),
+        });
+        rest = remaining;
+    }
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png_chunks::{PngChunk, PngChunkFile, PngTextChunkKind};
+    use image::{ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    /// テスト毎に独立した一時ディレクトリ
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("operation-history-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// parameters テキストチャンク付きのPNG
+    fn png_with_parameters(color: u8, parameters: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        RgbaImage::from_pixel(4, 4, Rgba([color, 0, 0, 255]))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        let mut png = PngChunkFile::parse(&data).unwrap();
+        let chunk = PngChunk::new_text(PngTextChunkKind::Text, "parameters", parameters).unwrap();
+        png.replace_text_chunk("parameters", chunk);
+        png.to_bytes()
+    }
+
+    /// parameters チャンクを削除したデータ
+    fn without_parameters(data: &[u8]) -> Vec<u8> {
+        let mut png = PngChunkFile::parse(data).unwrap();
+        png.remove_text_chunks("parameters");
+        png.to_bytes()
+    }
+
+    #[test]
+    fn snapshot_encoding_round_trip() {
+        let blocks = vec![
+            MetadataBlock {
+                tag: *b"tEXt",
+                data: b"parameters\0a, b".to_vec(),
+            },
+            MetadataBlock {
+                tag: *b"eXIf",
+                data: Vec::new(),
+            },
+        ];
+        let decoded = decode_snapshot(&encode_snapshot(&blocks)).unwrap();
+        assert_eq!(decoded.len(), 2);
+        for (decoded, block) in decoded.iter().zip(&blocks) {
+            assert_eq!((decoded.tag, &decoded.data), (block.tag, &block.data));
+        }
+        assert!(decode_snapshot(&encode_snapshot(&[])).unwrap().is_empty());
+
+        let encoded = encode_snapshot(&blocks);
+        assert!(decode_snapshot(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode_snapshot(b"XXXX").is_err());
+    }
+
+    #[test]
+    fn undo_restores_metadata() {
+        let directory = test_directory("undo");
+        let path = directory.join("image.png");
+        let original = png_with_parameters(10, "1girl\nSteps: 20");
+        fs::write(&path, &original).unwrap();
+        let path = path.to_str().unwrap();
+
+        let history = OperationHistory::open(directory.join("history")).unwrap();
+        let operation = history.begin(OperationKind::ClearSdParameters);
+        operation
+            .write(path, &original, &without_parameters(&original))
+            .unwrap();
+        history.commit(operation);
+        assert_eq!(history.list().unwrap().len(), 1);
+
+        let undo_result = history.undo_last().unwrap().unwrap();
+        assert!(undo_result.results[0].error.is_none());
+        assert_eq!(fs::read(path).unwrap(), original);
+        assert!(history.undo_last().unwrap().is_none());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn undo_refuses_replaced_image() {
+        let directory = test_directory("replaced");
+        let path = directory.join("image.png");
+        let original = png_with_parameters(10, "old prompt");
+        fs::write(&path, &original).unwrap();
+        let path = path.to_str().unwrap();
+
+        let history = OperationHistory::open(directory.join("history")).unwrap();
+        let operation = history.begin(OperationKind::WriteSdParameters);
+        let updated = png_with_parameters(10, "new prompt");
+        operation.write(path, &original, &updated).unwrap();
+        history.commit(operation);
+
+        // 同じパスで別の画像に再生成された
+        let regenerated = png_with_parameters(200, "regenerated prompt");
+        fs::write(path, &regenerated).unwrap();
+
+        let undo_result = history.undo_last().unwrap().unwrap();
+        assert!(undo_result.results[0].error.is_some());
+        assert_eq!(fs::read(path).unwrap(), regenerated);
+        // 再試行しても復元できないため履歴には残さない
+        assert!(history.list().unwrap().is_empty());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn prune_keeps_latest_operations() {
+        let directory = test_directory("prune");
+        let path = directory.join("image.png");
+        let original = png_with_parameters(10, "prompt");
+        fs::write(&path, &original).unwrap();
+        let path = path.to_str().unwrap();
+
+        let history = OperationHistory::open(directory.join("history")).unwrap();
+        let mut ids = Vec::new();
+        for _ in 0..MAX_OPERATIONS + 3 {
+            let operation = history.begin(OperationKind::WriteSdParameters);
+            ids.push(operation.record.lock().unwrap().id.clone());
+            operation.write(path, &original, &original).unwrap();
+            history.commit(operation);
+        }
+        // 記録中（commit 前）の操作は削除しない
+        let pending = history.begin(OperationKind::WriteSdParameters);
+        pending.snapshot(path, &original, &original);
+
+        let listed: Vec<String> = history
+            .list()
+            .unwrap()
+            .into_iter()
+            .rev()
+            .map(|summary| summary.id)
+            .collect();
+        assert_eq!(listed, ids[3..]);
+        assert!(pending.dir.exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,433 @@
+// PNGチャンク単位での読み書き用のモジュール
+//
+// 画素データ（IDAT）や色情報（PLTE / tRNS / iCCP / gAMA 等）を一切デコードせずに
+// メタデータチャンクだけを差し替えるために使用する。
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+/// PNGファイルシグネチャ
+pub const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// テキストチャンクの種類（tEXt / zTXt / iTXt）
+const TEXT_CHUNK_TYPES: [&[u8; 4]; 3] = [b"tEXt", b"zTXt", b"iTXt"];
+
+/// PNGテキストチャンクの格納形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngTextChunkKind {
+    /// tEXt（非圧縮 Latin-1）
+    Text,
+    /// zTXt（圧縮 Latin-1）
+    CompressedText,
+    /// iTXt（UTF-8、compressed が true の場合は zlib 圧縮）
+    InternationalText { compressed: bool },
+}
+
+/// PNGチャンク（長さ・種類・データ・CRC）
+#[derive(Debug, Clone)]
+pub struct PngChunk {
+    pub chunk_type: [u8; 4],
+    pub data: Vec<u8>,
+    crc: u32,
+}
+
+impl PngChunk {
+    /// 新しいチャンクを作成（CRCを計算）
+    pub fn new(chunk_type: [u8; 4], data: Vec<u8>) -> Self {
+        let crc = Self::compute_crc(&chunk_type, &data);
+        Self {
+            chunk_type,
+            data,
+            crc,
+        }
+    }
+
+    /// チャンク種類 + データからCRC-32を計算
+    fn compute_crc(chunk_type: &[u8; 4], data: &[u8]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(chunk_type);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// テキストチャンクを指定形式でエンコードして作成
+    ///
+    /// tEXt / zTXt に Latin-1 で表現できない文字が含まれる場合は iTXt にフォールバックする。
+    pub fn new_text(kind: PngTextChunkKind, keyword: &str, text: &str) -> Result<Self, String> {
+        if keyword.is_empty() || 79 < keyword.len() || keyword.contains('\0') {
+            return Err(format!(
+                "テキストチャンクのキーワードが不正です: {}",
+                keyword
+            ));
+        }
+
+        let latin1 = encode_latin1(text);
+        let kind = match (kind, &latin1) {
+            (PngTextChunkKind::Text | PngTextChunkKind::CompressedText, None) => {
+                PngTextChunkKind::InternationalText { compressed: false }
+            }
+            (kind, _) => kind,
+        };
+
+        let mut data = keyword.as_bytes().to_vec();
+        data.push(0);
+        let chunk = match kind {
+            PngTextChunkKind::Text => {
+                data.extend(latin1.unwrap_or_default());
+                Self::new(*b"tEXt", data)
+            }
+            PngTextChunkKind::CompressedText => {
+                data.push(0); // 圧縮方式: zlib
+                data.extend(zlib_compress(&latin1.unwrap_or_default())?);
+                Self::new(*b"zTXt", data)
+            }
+            PngTextChunkKind::InternationalText { compressed } => {
+                data.push(compressed as u8);
+                data.push(0); // 圧縮方式: zlib
+                data.push(0); // 言語タグ（空）
+                data.push(0); // 翻訳キーワード（空）
+                if compressed {
+                    data.extend(zlib_compress(text.as_bytes())?);
+                } else {
+                    data.extend_from_slice(text.as_bytes());
+                }
+                Self::new(*b"iTXt", data)
+            }
+        };
+        Ok(chunk)
+    }
+
+    /// チャンク種類が一致するか
+    pub fn is_type(&self, chunk_type: &[u8; 4]) -> bool {
+        &self.chunk_type == chunk_type
+    }
+
+    /// テキストチャンク（tEXt / zTXt / iTXt）かどうか
+    pub fn is_text(&self) -> bool {
+        TEXT_CHUNK_TYPES.iter().any(|t| self.is_type(t))
+    }
+
+    /// テキストチャンクの格納形式を取得
+    pub fn text_kind(&self) -> Option<PngTextChunkKind> {
+        match &self.chunk_type {
+            b"tEXt" => Some(PngTextChunkKind::Text),
+            b"zTXt" => Some(PngTextChunkKind::CompressedText),
+            b"iTXt" => {
+                // キーワード終端の直後が圧縮フラグ
+                let keyword_end = self.data.iter().position(|&b| b == 0)?;
+                let compressed = self
+                    .data
+                    .get(keyword_end + 1)
+                    .is_some_and(|&flag| flag == 1);
+                Some(PngTextChunkKind::InternationalText { compressed })
+            }
+            _ => None,
+        }
+    }
+
+    /// テキストチャンクのキーワードを取得（Latin-1 のため ASCII 以外は非対応）
+    pub fn text_keyword(&self) -> Option<&str> {
+        if !self.is_text() {
+            return None;
+        }
+        let end = self.data.iter().position(|&b| b == 0)?;
+        std::str::from_utf8(&self.data[..end]).ok()
+    }
+
+    /// チャンクをバイト列として書き出し（元のCRCをそのまま使用）
+    fn write_to(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.chunk_type);
+        out.extend_from_slice(&self.data);
+        out.extend_from_slice(&self.crc.to_be_bytes());
+    }
+}
+
+/// チャンク列として分解したPNGファイル
+#[derive(Debug, Clone)]
+pub struct PngChunkFile {
+    pub chunks: Vec<PngChunk>,
+    /// IEND 以降の余剰データ（そのまま保持）
+    trailing: Vec<u8>,
+}
+
+impl PngChunkFile {
+    /// バイトデータをチャンク列に分解
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < PNG_SIGNATURE.len() || data[..PNG_SIGNATURE.len()] != PNG_SIGNATURE {
+            return Err("PNGシグネチャが不正です".to_string());
+        }
+
+        let mut chunks = Vec::new();
+        let mut pos = PNG_SIGNATURE.len();
+        loop {
+            // チャンクヘッダー（長さ4バイト + 種類4バイト）
+            if pos + 8 > data.len() {
+                return Err("IENDチャンクが見つかりません".to_string());
+            }
+            let length =
+                u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]])
+                    as usize;
+            let chunk_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+
+            let data_start = pos + 8;
+            let data_end = data_start
+                .checked_add(length)
+                .filter(|end| end + 4 <= data.len())
+                .ok_or_else(|| {
+                    format!(
+                        "チャンク長が不正です: {}",
+                        String::from_utf8_lossy(&chunk_type)
+                    )
+                })?;
+            let crc = u32::from_be_bytes([
+                data[data_end],
+                data[data_end + 1],
+                data[data_end + 2],
+                data[data_end + 3],
+            ]);
+
+            chunks.push(PngChunk {
+                chunk_type,
+                data: data[data_start..data_end].to_vec(),
+                crc,
+            });
+            pos = data_end + 4;
+
+            if &chunk_type == b"IEND" {
+                break;
+            }
+        }
+
+        if !chunks.first().is_some_and(|chunk| chunk.is_type(b"IHDR")) {
+            return Err("IHDRチャンクが先頭にありません".to_string());
+        }
+
+        Ok(Self {
+            chunks,
+            trailing: data[pos..].to_vec(),
+        })
+    }
+
+    /// チャンク列をPNGバイトデータとして書き出し
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let capacity = PNG_SIGNATURE.len()
+            + self
+                .chunks
+                .iter()
+                .map(|chunk| chunk.data.len() + 12)
+                .sum::<usize>()
+            + self.trailing.len();
+
+        let mut out = Vec::with_capacity(capacity);
+        out.extend_from_slice(&PNG_SIGNATURE);
+        for chunk in &self.chunks {
+            chunk.write_to(&mut out);
+        }
+        out.extend_from_slice(&self.trailing);
+        out
+    }
+
+    /// 指定キーワードのテキストチャンクの格納形式を取得
+    pub fn find_text_kind(&self, keyword: &str) -> Option<PngTextChunkKind> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.text_keyword() == Some(keyword))
+            .and_then(|chunk| chunk.text_kind())
+    }
+
+    /// 指定キーワードのテキストチャンクを差し替え
+    ///
+    /// 既存チャンクがある場合は最初の位置に置き換えて残りを削除し、
+    /// 無い場合は最初の IDAT の直前に挿入する。
+    pub fn replace_text_chunk(&mut self, keyword: &str, chunk: PngChunk) {
+        let position = self
+            .chunks
+            .iter()
+            .position(|c| c.text_keyword() == Some(keyword))
+            .or_else(|| self.chunks.iter().position(|c| c.is_type(b"IDAT")))
+            .unwrap_or(self.chunks.len().saturating_sub(1)); // IEND の直前
+
+        self.remove_text_chunks(keyword);
+        let position = position.min(self.chunks.len());
+        self.chunks.insert(position, chunk);
+    }
+
+    /// 指定キーワードのテキストチャンクを全て削除し、削除数を返す
+    pub fn remove_text_chunks(&mut self, keyword: &str) -> usize {
+        let before = self.chunks.len();
+        self.chunks
+            .retain(|chunk| chunk.text_keyword() != Some(keyword));
+        before - self.chunks.len()
+    }
+}
+
+/// 文字列を Latin-1 にエンコード（表現できない文字が含まれる場合は None）
+fn encode_latin1(text: &str) -> Option<Vec<u8>> {
+    text.chars()
+        .map(|c| u8::try_from(u32::from(c)).ok())
+        .collect()
+}
+
+/// zlib 形式で圧縮
+fn zlib_compress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| format!("テキストチャンクの圧縮に失敗: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba, RgbaImage};
+    use std::io::{Cursor, Read};
+
+    /// テキストチャンクを IDAT の前後に持つ PNG
+    fn png_file() -> PngChunkFile {
+        let mut data = Vec::new();
+        RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255]))
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+        let mut file = PngChunkFile::parse(&data).unwrap();
+        let text = |keyword: &str, text: &str| {
+            PngChunk::new_text(PngTextChunkKind::Text, keyword, text).unwrap()
+        };
+        let idat = file.chunks.iter().position(|c| c.is_type(b"IDAT")).unwrap();
+        file.chunks.insert(idat, text("Title", "before"));
+        file.chunks.insert(idat + 1, text("parameters", "old"));
+        file.chunks.insert(idat + 2, text("Software", "after"));
+        file
+    }
+
+    fn chunk_names(file: &PngChunkFile) -> Vec<String> {
+        file.chunks
+            .iter()
+            .map(|chunk| match chunk.text_keyword() {
+                Some(keyword) => keyword.to_string(),
+                None => String::from_utf8_lossy(&chunk.chunk_type).into_owned(),
+            })
+            .collect()
+    }
+
+    /// 書き出したバイト列の全チャンクの CRC が正しいか
+    fn assert_valid_crcs(data: &[u8]) {
+        for chunk in PngChunkFile::parse(data).unwrap().chunks {
+            assert_eq!(
+                chunk.crc,
+                PngChunk::compute_crc(&chunk.chunk_type, &chunk.data)
+            );
+        }
+    }
+
+    /// テキストチャンクの本文（iTXt は非圧縮のみ）
+    fn text_body(chunk: &PngChunk) -> String {
+        let keyword_end = chunk.data.iter().position(|&b| b == 0).unwrap();
+        match chunk.text_kind().unwrap() {
+            PngTextChunkKind::Text => chunk.data[keyword_end + 1..]
+                .iter()
+                .map(|&b| b as char)
+                .collect(),
+            PngTextChunkKind::CompressedText => {
+                let mut text = Vec::new();
+                flate2::read::ZlibDecoder::new(&chunk.data[keyword_end + 2..])
+                    .read_to_end(&mut text)
+                    .unwrap();
+                text.iter().map(|&b| b as char).collect()
+            }
+            PngTextChunkKind::InternationalText { .. } => {
+                // 圧縮フラグ・圧縮方式・空の言語タグ・空の翻訳キーワード
+                String::from_utf8(chunk.data[keyword_end + 5..].to_vec()).unwrap()
+            }
+        }
+    }
+
+    #[test]
+    fn replace_keeps_order_and_crcs() {
+        let mut file = png_file();
+        let original = file.clone();
+        let chunk = PngChunk::new_text(PngTextChunkKind::Text, "parameters", "new").unwrap();
+        file.replace_text_chunk("parameters", chunk);
+        assert_eq!(chunk_names(&file), chunk_names(&original));
+
+        let data = file.to_bytes();
+        assert_valid_crcs(&data);
+        let reparsed = PngChunkFile::parse(&data).unwrap();
+        let parameters = reparsed
+            .chunks
+            .iter()
+            .find(|chunk| chunk.text_keyword() == Some("parameters"))
+            .unwrap();
+        assert_eq!(text_body(parameters), "new");
+        // 他のチャンクはバイト単位で同じ
+        for (chunk, original) in reparsed.chunks.iter().zip(&original.chunks) {
+            if chunk.text_keyword() != Some("parameters") {
+                assert_eq!(
+                    (chunk.data.as_slice(), chunk.crc),
+                    (original.data.as_slice(), original.crc)
+                );
+            }
+        }
+        assert!(image::load_from_memory(&data).is_ok());
+    }
+
+    #[test]
+    fn replace_inserts_before_idat_and_removes_duplicates() {
+        let mut file = png_file();
+        let duplicate = PngChunk::new_text(PngTextChunkKind::Text, "parameters", "dup").unwrap();
+        file.chunks.insert(file.chunks.len() - 1, duplicate);
+        let chunk = PngChunk::new_text(PngTextChunkKind::Text, "parameters", "new").unwrap();
+        file.replace_text_chunk("parameters", chunk);
+        assert_eq!(
+            chunk_names(&file)
+                .iter()
+                .filter(|name| *name == "parameters")
+                .count(),
+            1
+        );
+
+        let chunk = PngChunk::new_text(PngTextChunkKind::Text, "Comment", "added").unwrap();
+        file.replace_text_chunk("Comment", chunk);
+        let names = chunk_names(&file);
+        let comment = names.iter().position(|name| name == "Comment").unwrap();
+        assert_eq!(names[comment + 1], "IDAT");
+        assert_valid_crcs(&file.to_bytes());
+    }
+
+    #[test]
+    fn new_text_falls_back_to_itxt() {
+        let latin1 = PngChunk::new_text(PngTextChunkKind::Text, "parameters", "café").unwrap();
+        assert_eq!(latin1.text_kind(), Some(PngTextChunkKind::Text));
+        assert_eq!(text_body(&latin1), "café");
+
+        for kind in [PngTextChunkKind::Text, PngTextChunkKind::CompressedText] {
+            let chunk = PngChunk::new_text(kind, "parameters", "1girl, 笑顔").unwrap();
+            assert_eq!(
+                chunk.text_kind(),
+                Some(PngTextChunkKind::InternationalText { compressed: false })
+            );
+            assert_eq!(chunk.text_keyword(), Some("parameters"));
+            assert_eq!(text_body(&chunk), "1girl, 笑顔");
+        }
+
+        let compressed =
+            PngChunk::new_text(PngTextChunkKind::CompressedText, "parameters", "café").unwrap();
+        assert_eq!(text_body(&compressed), "café");
+    }
+
+    #[test]
+    fn invalid_keyword_is_rejected() {
+        for keyword in ["", "a\0b", &"k".repeat(80)] {
+            assert!(PngChunk::new_text(PngTextChunkKind::Text, keyword, "x").is_err());
+        }
+    }
+
+    #[test]
+    fn trailing_data_is_kept() {
+        let mut data = png_file().to_bytes();
+        data.extend_from_slice(b"trailer");
+        let file = PngChunkFile::parse(&data).unwrap();
+        assert_eq!(file.to_bytes(), data);
+    }
+}
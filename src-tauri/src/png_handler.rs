@@ -1,8 +1,25 @@
-use crate::image_types::PngImageInfo;
-use crate::sd_parameters::SdParameters;
-use png::{Decoder, Encoder};
-use std::fs::File;
-use std::io::{BufWriter, Cursor};
+use crate::comfyui_parameters::parse_comfyui_chunks;
+use crate::exif_handler::BatchWriteResult;
+use crate::image_types::{PngImageDetails, PngImageInfo};
+use crate::library_index::LibraryIndex;
+use crate::novelai_parameters::{
+    decode_stealth_pnginfo, has_stealth_signature, parse_novelai_chunks, NovelAiChunks,
+};
+use crate::operation_history::{Operation, OperationHistory, OperationKind};
+use crate::png_chunks::{PngChunk, PngChunkFile, PngTextChunkKind};
+use crate::sd_parameters::{SdParameterSource, SdParameters};
+use crate::thumbnail_handler::ThumbnailState;
+use png::{ColorType, Decoder, Info};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::io::Cursor;
+
+/// A1111 形式のSD Parametersを格納するテキストチャンクのキーワード
+const SD_PARAMETERS_KEYWORD: &str = "parameters";
+/// SD Parameters を格納するテキストチャンクのキーワード（A1111 / NovelAI / ComfyUI）
+///
+/// EXIF・XMP 等の他のテキストチャンクは、レーティングの書き込み等で後から追加されるため含めない。
+const SD_TEXT_KEYWORDS: &[&str] = &[SD_PARAMETERS_KEYWORD, "Comment", "prompt", "workflow"];
 
 /// PNG画像情報を読み込み（Tauri API）
 #[tauri::command]
@@ -20,27 +37,39 @@ pub fn read_png_info_from_bytes(data: &[u8]) -> Result<PngImageInfo, String> {
         .map_err(|e| format!("PNG解析エラー: {}", e))?;
     let info = reader.info();
 
-    // SD Parameters を検索・解析
-    let mut sd_parameters = None;
-    for entry in &info.uncompressed_latin1_text {
-        if entry.keyword == "parameters" {
-            match SdParameters::parse(&entry.text) {
-                Ok(params) => sd_parameters = Some(params),
-                Err(_) => {} // 解析失敗は無視
-            }
-            break;
-        }
-    }
+    // SD Parameters を検索・解析（解析失敗は無視）
+    let sd_parameters = sd_parameters_from_info(data, info);
 
     Ok(PngImageInfo {
         width: info.width,
         height: info.height,
         bit_depth: info.bit_depth as u8,
         color_type: format!("{:?}", info.color_type),
+        sd_values: sd_parameters.as_ref().map(SdParameters::typed_values),
         sd_parameters,
     })
 }
 
+/// バイトデータからPNG固有の画像情報を読み取り（中核処理）
+pub fn read_png_details_from_bytes(data: &[u8]) -> Result<PngImageDetails, String> {
+    let decoder = Decoder::new(Cursor::new(data));
+    let reader = decoder
+        .read_info()
+        .map_err(|e| format!("PNG解析エラー: {}", e))?;
+    let info = reader.info();
+
+    Ok(PngImageDetails {
+        bit_depth: info.bit_depth as u8,
+        color_type: format!("{:?}", info.color_type),
+        interlaced: info.interlaced,
+        animation_frames: info.animation_control.map(|control| control.num_frames),
+        text_keywords: collect_text_entries(info)
+            .into_iter()
+            .map(|entry| entry.keyword)
+            .collect(),
+    })
+}
+
 /// バイトデータからSDパラメーターのみを読み取り（中核処理）
 pub fn read_png_sd_parameters_from_bytes(data: &[u8]) -> Option<SdParameters> {
     let cursor = Cursor::new(data);
@@ -49,76 +78,430 @@ pub fn read_png_sd_parameters_from_bytes(data: &[u8]) -> Option<SdParameters> {
     let info = reader.info();
 
     // SD Parameters のみを検索
-    for entry in &info.uncompressed_latin1_text {
-        if entry.keyword == "parameters" {
-            if let Ok(params) = SdParameters::parse(&entry.text) {
-                return Some(params);
-            }
-            break;
+    sd_parameters_from_info(data, info)
+}
+
+/// 読み取り済みのチャンク情報からSD Parametersを取得（テキストチャンク → stealth pnginfo）
+///
+/// stealth pnginfo の画素デコードは重いため、呼び出し側は1ファイルにつき1回だけ呼ぶ。
+fn sd_parameters_from_info(data: &[u8], info: &Info) -> Option<SdParameters> {
+    extract_sd_parameters(info).or_else(|| read_stealth_sd_parameters(data, info))
+}
+
+/// デコード済みのPNGテキストチャンク
+#[derive(Debug, Clone)]
+pub struct PngTextEntry {
+    pub keyword: String,
+    pub text: String,
+}
+
+/// tEXt / zTXt / iTXt の全テキストチャンクをデコードして収集（同一キーワードは tEXt を優先）
+pub fn collect_text_entries(info: &Info) -> Vec<PngTextEntry> {
+    let mut entries: Vec<PngTextEntry> = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| PngTextEntry {
+            keyword: chunk.keyword.clone(),
+            text: chunk.text.clone(),
+        })
+        .collect();
+
+    // 圧縮テキストは展開に失敗したものを無視
+    entries.extend(info.compressed_latin1_text.iter().filter_map(|chunk| {
+        Some(PngTextEntry {
+            keyword: chunk.keyword.clone(),
+            text: chunk.get_text().ok()?,
+        })
+    }));
+    entries.extend(info.utf8_text.iter().filter_map(|chunk| {
+        Some(PngTextEntry {
+            keyword: chunk.keyword.clone(),
+            text: chunk.get_text().ok()?,
+        })
+    }));
+
+    entries
+}
+
+/// 指定キーワードのテキストチャンクを取得
+fn find_text_chunk<'a>(entries: &'a [PngTextEntry], keyword: &str) -> Option<&'a str> {
+    entries
+        .iter()
+        .find(|entry| entry.keyword == keyword)
+        .map(|entry| entry.text.as_str())
+}
+
+/// テキストチャンクからSD Parametersを抽出（A1111 → NovelAI → ComfyUI の順）
+fn extract_sd_parameters(info: &Info) -> Option<SdParameters> {
+    let entries = collect_text_entries(info);
+
+    if let Some(text) = find_text_chunk(&entries, SD_PARAMETERS_KEYWORD) {
+        if let Ok(params) = SdParameters::parse(text) {
+            return Some(params);
+        }
+    }
+
+    let novelai_chunks = NovelAiChunks {
+        comment: find_text_chunk(&entries, "Comment").map(str::to_string),
+        software: find_text_chunk(&entries, "Software").map(str::to_string),
+        source: find_text_chunk(&entries, "Source").map(str::to_string),
+    };
+    if novelai_chunks.is_novelai() {
+        match parse_novelai_chunks(&novelai_chunks) {
+            Ok(params) => return Some(params),
+            Err(e) => log::debug!("NovelAI メタデータの解析に失敗: {}", e),
+        }
+    }
+
+    let prompt = find_text_chunk(&entries, "prompt");
+    let workflow = find_text_chunk(&entries, "workflow");
+    if prompt.is_none() && workflow.is_none() {
+        return None;
+    }
+
+    match parse_comfyui_chunks(prompt, workflow) {
+        Ok(params) => Some(params),
+        Err(e) => {
+            log::debug!("ComfyUI メタデータの解析に失敗: {}", e);
+            None
+        }
+    }
+}
+
+/// アルファチャンネルの stealth pnginfo からSD Parametersを抽出
+///
+/// テキストチャンクを削除して再アップロードされた NovelAI 画像向け。
+/// 画素のデコードが必要なため、アルファ付きPNGでSD Parametersのテキストチャンクが無い場合のみ呼び出す。
+fn read_stealth_sd_parameters(data: &[u8], info: &Info) -> Option<SdParameters> {
+    // SD Parameters のテキストチャンクがある画像（解析に失敗したものも含む）は画素をデコードしない
+    let has_sd_text_chunks = info
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| &chunk.keyword)
+        .chain(
+            info.compressed_latin1_text
+                .iter()
+                .map(|chunk| &chunk.keyword),
+        )
+        .chain(info.utf8_text.iter().map(|chunk| &chunk.keyword))
+        .any(|keyword| SD_TEXT_KEYWORDS.contains(&keyword.as_str()));
+    if has_sd_text_chunks || !matches!(info.color_type, ColorType::Rgba | ColorType::GrayscaleAlpha)
+    {
+        return None;
+    }
+    // 全体をデコードする前に、先頭の行だけでシグネチャを確認
+    if !has_stealth_signature(data) {
+        return None;
+    }
+
+    let image = image::load_from_memory_with_format(data, image::ImageFormat::Png).ok()?;
+    let chunks = decode_stealth_pnginfo(&image.to_rgba8())?;
+
+    match parse_novelai_chunks(&chunks) {
+        Ok(params) => Some(params),
+        Err(e) => {
+            log::debug!("stealth pnginfo の解析に失敗: {}", e);
+            None
         }
     }
-    None
 }
 
 /// SD Parameters をPNGファイルから削除（Tauri API）
+///
+/// チャンク単位で SD Parameters のテキストチャンク（A1111 の parameters、NovelAI の Comment /
+/// Description、ComfyUI の prompt / workflow）のみを取り除き、画素データや他の全チャンクは
+/// バイト単位でそのまま保持する。stealth pnginfo として画素に埋め込まれている場合はエラー。
 #[tauri::command]
-pub fn clear_png_sd_parameters(path: String) -> Result<(), String> {
-    // 必要な情報を抽出するため、まず読み込み
-    let (image_data, width, height, color_type, bit_depth, text_chunks) = {
-        let input_file =
-            File::open(&path).map_err(|e| format!("入力ファイル読み込みエラー: {}", e))?;
-        let decoder = Decoder::new(input_file);
-        let mut reader = decoder
-            .read_info()
-            .map_err(|e| format!("PNG解析エラー: {}", e))?;
-
-        // 画像データを取得
-        let mut buf = vec![0; reader.output_buffer_size()];
-        let info = reader
-            .next_frame(&mut buf)
-            .map_err(|e| format!("画像データ読み込みエラー: {}", e))?;
-
-        // parametersチャンク以外のテキストチャンクを保存
-        let text_chunks: Vec<(String, String)> = reader
-            .info()
-            .uncompressed_latin1_text
+pub fn clear_png_sd_parameters(
+    path: String,
+    state: tauri::State<'_, ThumbnailState>,
+    history: tauri::State<'_, OperationHistory>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<(), String> {
+    let operation = history.begin(OperationKind::ClearSdParameters);
+    if clear_sd_parameters(&path, &operation)? {
+        history.commit(operation);
+        state.handler.refresh_cache(std::slice::from_ref(&path));
+        index.refresh(std::slice::from_ref(&path));
+    }
+    Ok(())
+}
+
+/// 複数のPNGファイルから SD Parameters を並列で削除（Tauri API）
+///
+/// 全ファイルを1つの操作として記録するため、元に戻す際もまとめて復元される。
+#[tauri::command]
+pub async fn clear_png_sd_parameters_batch(
+    paths: Vec<String>,
+    state: tauri::State<'_, ThumbnailState>,
+    history: tauri::State<'_, OperationHistory>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<Vec<BatchWriteResult>, String> {
+    let operation = history.begin(OperationKind::ClearSdParameters);
+
+    let results: Vec<(BatchWriteResult, bool)> = state.handler.thread_pool().install(|| {
+        paths
+            .par_iter()
+            .map(|path| {
+                let result = clear_sd_parameters(path, &operation);
+                let cleared = matches!(result, Ok(true));
+                let result = BatchWriteResult {
+                    path: path.clone(),
+                    error: result.err(),
+                };
+                (result, cleared)
+            })
+            .collect()
+    });
+    history.commit(operation);
+
+    let cleared_paths: Vec<String> = results
+        .iter()
+        .filter(|(_, cleared)| *cleared)
+        .map(|(result, _)| result.path.clone())
+        .collect();
+    state.handler.refresh_cache(&cleared_paths);
+    index.refresh(&cleared_paths);
+    log::info!(
+        "SD Parameters 一括削除完了: {}/{}ファイル削除",
+        cleared_paths.len(),
+        results.len()
+    );
+
+    Ok(results.into_iter().map(|(result, _)| result).collect())
+}
+
+/// SD Parameters の読み込み元ごとに、削除するテキストチャンクのキーワード
+fn sd_parameter_keywords(source: SdParameterSource) -> &'static [&'static str] {
+    match source {
+        SdParameterSource::A1111 => &[SD_PARAMETERS_KEYWORD],
+        // NovelAI は Description にもプロンプトを保存する
+        SdParameterSource::NovelAi => &["Comment", "Description"],
+        SdParameterSource::ComfyUi => &["prompt", "workflow"],
+    }
+}
+
+/// PNGファイルから SD Parameters のテキストチャンクを削除（中核処理、削除対象が無い場合は false）
+///
+/// 読み込み時に使われたチャンクを削除し、別の形式のチャンクが残っていればそれも続けて削除する。
+fn clear_sd_parameters(path: &str, operation: &Operation) -> Result<bool, String> {
+    let data = std::fs::read(path).map_err(|e| format!("入力ファイル読み込みエラー: {}", e))?;
+    let mut png_file = PngChunkFile::parse(&data)?;
+
+    // tEXt / zTXt / iTXt のいずれであっても削除
+    let mut updated = data.clone();
+    while let Some(parameters) = read_png_sd_parameters_from_bytes(&updated) {
+        let removed: usize = sd_parameter_keywords(parameters.source)
             .iter()
-            .filter(|entry| entry.keyword != "parameters")
-            .map(|entry| (entry.keyword.clone(), entry.text.clone()))
-            .collect();
-
-        (
-            buf,
-            info.width,
-            info.height,
-            info.color_type,
-            info.bit_depth,
-            text_chunks,
-        )
-    }; // ここでinput_fileとreaderがdropされる
+            .map(|keyword| png_file.remove_text_chunks(keyword))
+            .sum();
+        if removed == 0 {
+            // テキストチャンクではなく画素に埋め込まれている
+            return Err(
+                "SD Parametersが画素（stealth pnginfo）に埋め込まれているため削除できません"
+                    .to_string(),
+            );
+        }
+        updated = png_file.to_bytes();
+    }
+    if updated == data {
+        return Ok(false); // 削除対象が無い場合はファイルに触れない
+    }
+
+    operation.write(path, &data, &updated)?;
+    Ok(true)
+}
 
-    // 新しいファイルを書き込み（元ファイルを上書き）
-    let output_file = File::create(&path).map_err(|e| format!("出力ファイル作成エラー: {}", e))?;
-    let w = BufWriter::new(output_file);
+/// SD Parameters の書き込み内容（生テキスト または 構造化データ）
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SdParametersInput {
+    /// A1111 形式の文字列（解析して正規化した形で書き込む）
+    Raw(String),
+    /// 構造化データを A1111 形式に変換して書き込む
+    Structured(Box<SdParameters>),
+}
 
-    let mut encoder = Encoder::new(w, width, height);
-    encoder.set_color(color_type);
-    encoder.set_depth(bit_depth);
+/// 生テキストを解析し、読み込み時と同じ解釈になる A1111 形式に正規化
+///
+/// 解析時に警告が出る（設定行が無い・解釈できない行がある）テキストは、書き込んでも
+/// 読み戻した内容が一致しないため拒否する。
+fn canonical_a1111_text(text: &str) -> Result<String, String> {
+    if text.trim().is_empty() {
+        return Err("書き込むSD Parametersが空です".to_string());
+    }
+    let parameters = SdParameters::parse(text)?;
+    if !parameters.parse_warnings.is_empty() {
+        return Err(format!(
+            "SD Parametersを A1111 形式として解釈できません: {}",
+            parameters.parse_warnings.join(", ")
+        ));
+    }
+    Ok(parameters.to_a1111_string())
+}
 
-    // parametersチャンク以外を復元
-    for (keyword, text) in text_chunks {
-        encoder
-            .add_text_chunk(keyword, text)
-            .map_err(|e| format!("テキストチャンク追加エラー: {}", e))?;
+/// SD Parameters をPNGファイルに書き込み（Tauri API）
+///
+/// 既存の parameters チャンクの格納形式（tEXt / zTXt / iTXt）を維持して差し替え、
+/// 画素データや他のチャンクには触れない。
+#[tauri::command]
+pub fn write_png_sd_parameters(
+    path: String,
+    parameters: SdParametersInput,
+    state: tauri::State<'_, ThumbnailState>,
+    history: tauri::State<'_, OperationHistory>,
+    index: tauri::State<'_, LibraryIndex>,
+) -> Result<(), String> {
+    let text = match parameters {
+        SdParametersInput::Raw(text) => canonical_a1111_text(&text)?,
+        SdParametersInput::Structured(params) => params.to_a1111_string(),
+    };
+    if text.is_empty() {
+        return Err("書き込むSD Parametersが空です".to_string());
     }
 
-    // ヘッダーと画像データを書き込み
-    let mut writer = encoder
-        .write_header()
-        .map_err(|e| format!("ヘッダー書き込みエラー: {}", e))?;
-    writer
-        .write_image_data(&image_data)
-        .map_err(|e| format!("画像データ書き込みエラー: {}", e))?;
+    let data = std::fs::read(&path).map_err(|e| format!("入力ファイル読み込みエラー: {}", e))?;
+    let mut png_file = PngChunkFile::parse(&data)?;
+
+    // 既存チャンクが無い場合は A1111 と同じく tEXt（Latin-1 外の文字を含む場合は iTXt）
+    let kind = png_file
+        .find_text_kind(SD_PARAMETERS_KEYWORD)
+        .unwrap_or(PngTextChunkKind::Text);
+    let chunk = PngChunk::new_text(kind, SD_PARAMETERS_KEYWORD, &text)?;
+    png_file.replace_text_chunk(SD_PARAMETERS_KEYWORD, chunk);
+
+    let operation = history.begin(OperationKind::WriteSdParameters);
+    operation.write(&path, &data, &png_file.to_bytes())?;
+    history.commit(operation);
+    state.handler.refresh_cache(std::slice::from_ref(&path));
+    index.refresh(std::slice::from_ref(&path));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, Rgba, RgbaImage};
+    use std::path::PathBuf;
+
+    /// テスト毎に独立した一時ディレクトリ
+    fn test_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("png-handler-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// テキストチャンクを追加したPNG（`stealth` の場合はアルファチャンネルにも埋め込む）
+    fn png_with_text(chunks: &[(&str, &str)], stealth: Option<&str>) -> Vec<u8> {
+        let mut image = RgbaImage::from_pixel(64, 64, Rgba([40, 80, 120, 255]));
+        if let Some(json) = stealth {
+            let mut bytes = b"stealth_pnginfo".to_vec();
+            bytes.extend_from_slice(&((json.len() * 8) as u32).to_be_bytes());
+            bytes.extend_from_slice(json.as_bytes());
+            let bits = bytes
+                .iter()
+                .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1));
+            for (index, bit) in bits.enumerate() {
+                let pixel = image.get_pixel_mut(index as u32 / 64, index as u32 % 64);
+                pixel[3] = (pixel[3] & !1) | bit;
+            }
+        }
+        let mut data = Vec::new();
+        image
+            .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+            .unwrap();
+
+        let mut png_file = PngChunkFile::parse(&data).unwrap();
+        for (keyword, text) in chunks {
+            let chunk = PngChunk::new_text(PngTextChunkKind::Text, keyword, text).unwrap();
+            png_file.replace_text_chunk(keyword, chunk);
+        }
+        png_file.to_bytes()
+    }
+
+    /// SD Parameters を削除し、残ったテキストチャンクのキーワードを返す
+    fn clear(name: &str, data: &[u8]) -> Result<Vec<String>, String> {
+        let directory = test_directory(name);
+        let path = directory.join("image.png");
+        std::fs::write(&path, data).unwrap();
+        let history = OperationHistory::open(directory.join("history")).unwrap();
+        let operation = history.begin(OperationKind::ClearSdParameters);
+
+        let result = clear_sd_parameters(path.to_str().unwrap(), &operation).map(|_| {
+            let updated = std::fs::read(&path).unwrap();
+            assert!(read_png_sd_parameters_from_bytes(&updated).is_none());
+            PngChunkFile::parse(&updated)
+                .unwrap()
+                .chunks
+                .iter()
+                .filter_map(|chunk| chunk.text_keyword().map(str::to_string))
+                .collect()
+        });
+        std::fs::remove_dir_all(&directory).unwrap();
+        result
+    }
+
+    const COMFYUI_PROMPT: &str = r#"{"3": {"class_type": "KSampler", "inputs": {"seed": 1, "steps": 20, "cfg": 7, "sampler_name": "euler", "positive": ["6", 0], "negative": ["6", 0]}}, "6": {"class_type": "CLIPTextEncode", "inputs": {"text": "1girl"}}}"#;
+    const NOVELAI_COMMENT: &str =
+        r#"{"prompt": "1girl, smile", "uc": "lowres", "steps": 28, "seed": 1234}"#;
+
+    #[test]
+    fn clears_comfyui_chunks() {
+        let data = png_with_text(
+            &[
+                ("prompt", COMFYUI_PROMPT),
+                ("workflow", r#"{"nodes": [], "links": []}"#),
+                ("Title", "kept"),
+            ],
+            None,
+        );
+        assert_eq!(clear("comfyui", &data).unwrap(), ["Title"]);
+    }
+
+    #[test]
+    fn clears_novelai_and_a1111_chunks() {
+        let data = png_with_text(
+            &[
+                ("parameters", "1girl\nSteps: 20, Seed: 1"),
+                ("Description", "1girl, smile"),
+                ("Software", "NovelAI"),
+                ("Comment", NOVELAI_COMMENT),
+            ],
+            None,
+        );
+        assert_eq!(clear("novelai", &data).unwrap(), ["Software"]);
+    }
+
+    #[test]
+    fn stealth_parameters_cannot_be_cleared() {
+        let json = serde_json::json!({ "Software": "NovelAI", "Comment": NOVELAI_COMMENT });
+        let data = png_with_text(
+            &[("Software", "NovelAI"), ("Comment", NOVELAI_COMMENT)],
+            Some(&json.to_string()),
+        );
+        assert!(clear("stealth", &data).is_err());
+    }
+
+    #[test]
+    fn raw_text_is_canonicalized() {
+        let text = canonical_a1111_text(
+            "  1girl, smile\nNegative prompt: lowres\nSteps: 20,Sampler: Euler a,  Note: \"a, b\"  \n",
+        )
+        .unwrap();
+        assert_eq!(
+            text,
+            "1girl, smile\nNegative prompt: lowres\nSteps: 20, Sampler: Euler a, Note: \"a, b\""
+        );
+        assert_eq!(SdParameters::parse(&text).unwrap().to_a1111_string(), text);
+    }
+
+    #[test]
+    fn raw_text_with_warnings_is_rejected() {
+        assert!(canonical_a1111_text("1girl, smile").is_err());
+        assert!(canonical_a1111_text("1girl\nSteps: 20\nnot a setting").is_err());
+        assert!(canonical_a1111_text(" \n").is_err());
+    }
+}
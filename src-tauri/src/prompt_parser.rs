@@ -0,0 +1,591 @@
+// A1111 プロンプト文法（強調・弱調・プロンプト編集・交互）解析用のモジュール
+//
+// 対応する構文:
+// - `(a)` / `((a))`: 強調（1段につき ×1.1）
+// - `[a]`: 弱調（1段につき ÷1.1）
+// - `(a, b:1.3)`: 明示的な重み
+// - `[from:to:0.5]` / `[to:10]` / `[from::0.5]`: プロンプト編集（スケジューリング）
+// - `[a|b]`: 交互
+// - `BREAK`: チャンク区切り
+// - `\(` / `\)` 等のエスケープ、`<lora:name:0.8>` 等の追加ネットワーク記法
+use crate::sd_parameters::SdTag;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// `(` / `[` 1段あたりの強調倍率
+const ATTENTION_MULTIPLIER: f32 = 1.1;
+/// 括弧の入れ子の上限（これより深い括弧は再帰せずテキストとして扱う）
+const MAX_NESTING_DEPTH: usize = 100;
+
+static BREAK_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\bBREAK\b").expect("Invalid regex pattern for BREAK"));
+
+/// プロンプトの構文木ノード
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PromptNode {
+    /// テキスト（weight はネストを考慮した実効重み）
+    Text { text: String, weight: f32 },
+    /// 強調・弱調（multiplier はこのノード自身の倍率）
+    Emphasis {
+        multiplier: f32,
+        children: Vec<PromptNode>,
+    },
+    /// プロンプト編集（step が 1 未満ならステップ割合、1 以上ならステップ数で切り替え）
+    Scheduled {
+        from: Vec<PromptNode>,
+        to: Vec<PromptNode>,
+        step: f32,
+    },
+    /// 交互（ステップ毎に options を順番に使用）
+    Alternate { options: Vec<Vec<PromptNode>> },
+    /// BREAK（チャンク区切り）
+    Break,
+}
+
+/// `[` 内の区切り文字
+#[derive(Clone, Copy, PartialEq)]
+enum BracketSeparator {
+    Colon,
+    Pipe,
+}
+
+/// 再帰下降パーサー
+struct PromptParser {
+    chars: Vec<char>,
+    pos: usize,
+    /// 現在の括弧の入れ子の深さ
+    depth: usize,
+}
+
+impl PromptParser {
+    fn new(prompt: &str) -> Self {
+        Self {
+            chars: prompt.chars().collect(),
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// 終端文字（消費しない）またはEOFまでノード列をパース
+    fn parse_sequence(&mut self, terminators: &[char]) -> Vec<PromptNode> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+
+        while let Some(c) = self.peek() {
+            if terminators.contains(&c) {
+                break;
+            }
+            self.pos += 1;
+
+            match c {
+                '\\' => {
+                    // エスケープ文字は次の1文字をそのままテキストとして扱う
+                    if let Some(next) = self.peek() {
+                        text.push(next);
+                        self.pos += 1;
+                    } else {
+                        text.push(c);
+                    }
+                }
+                '<' => {
+                    // 追加ネットワーク記法 `<lora:name:0.8>` は分解しない
+                    text.push(c);
+                    if let Some(len) = self.extra_network_length() {
+                        text.extend(&self.chars[self.pos..self.pos + len]);
+                        self.pos += len;
+                    }
+                }
+                '(' | '[' if self.depth >= MAX_NESTING_DEPTH => {
+                    // 画像から読んだプロンプトでスタックを使い切らないよう、深すぎる括弧は展開しない
+                    text.push(c);
+                    self.take_literal_group(&mut text);
+                }
+                '(' | '[' => {
+                    flush_text(&mut text, &mut nodes);
+                    self.depth += 1;
+                    let node = if c == '(' {
+                        self.parse_parenthesis()
+                    } else {
+                        self.parse_bracket()
+                    };
+                    self.depth -= 1;
+                    nodes.push(node);
+                }
+                // 対応の取れない閉じ括弧や区切り文字はテキストとして扱う
+                _ => text.push(c),
+            }
+        }
+
+        flush_text(&mut text, &mut nodes);
+        nodes
+    }
+
+    /// 開き括弧の直後から対応する閉じ括弧まで（無い場合は末尾まで）をテキストに追加
+    fn take_literal_group(&mut self, text: &mut String) {
+        let mut depth = 1;
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '\\' => {
+                    // エスケープされた括弧は入れ子の深さに数えない
+                    if let Some(next) = self.peek() {
+                        text.push(next);
+                        self.pos += 1;
+                    } else {
+                        text.push(c);
+                    }
+                    continue;
+                }
+                '(' | '[' => depth += 1,
+                ')' | ']' => depth -= 1,
+                _ => {}
+            }
+            text.push(c);
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+
+    /// `<` の直後から `>` までの長さ（改行・括弧を含む場合は None）
+    fn extra_network_length(&self) -> Option<usize> {
+        self.chars[self.pos..]
+            .iter()
+            .take_while(|c| !matches!(c, '\n' | '(' | ')' | '[' | ']' | '<'))
+            .position(|&c| c == '>')
+            .map(|end| end + 1)
+    }
+
+    /// `:` の直後から ` 1.3 )` 形式の重みを読み取る（成功時のみ消費）
+    fn try_parse_weight(&mut self) -> Option<f32> {
+        let skip_whitespace = |mut pos: usize| {
+            while self.chars.get(pos).is_some_and(|c| c.is_whitespace()) {
+                pos += 1;
+            }
+            pos
+        };
+
+        let start = skip_whitespace(self.pos);
+        let mut end = start;
+        while self
+            .chars
+            .get(end)
+            .is_some_and(|&c| c.is_ascii_digit() || matches!(c, '.' | '+' | '-'))
+        {
+            end += 1;
+        }
+        let weight = self.chars[start..end]
+            .iter()
+            .collect::<String>()
+            .parse::<f32>()
+            .ok()?;
+
+        let close = skip_whitespace(end);
+        if self.chars.get(close) != Some(&')') {
+            return None;
+        }
+
+        self.pos = close + 1;
+        Some(weight)
+    }
+
+    /// `(` の直後からパース（`(a)` / `(a:1.3)`）
+    fn parse_parenthesis(&mut self) -> PromptNode {
+        let mut children = Vec::new();
+        let mut multiplier = ATTENTION_MULTIPLIER;
+
+        loop {
+            append_nodes(&mut children, self.parse_sequence(&[')', ':']));
+            match self.peek() {
+                Some(')') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(':') => {
+                    self.pos += 1;
+                    if let Some(weight) = self.try_parse_weight() {
+                        multiplier = weight;
+                        break;
+                    }
+                    // 重みでない `:` はテキストとして扱う
+                    push_text(&mut children, ":");
+                }
+                // 閉じ括弧が無い場合は末尾まで強調（A1111 と同じ挙動）
+                _ => break,
+            }
+        }
+
+        PromptNode::Emphasis {
+            multiplier,
+            children,
+        }
+    }
+
+    /// `[` の直後からパース（弱調・プロンプト編集・交互）
+    fn parse_bracket(&mut self) -> PromptNode {
+        let mut parts = vec![self.parse_sequence(&[']', ':', '|'])];
+        let mut separators = Vec::new();
+        let mut closed = false;
+
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                ']' => {
+                    closed = true;
+                    break;
+                }
+                ':' => separators.push(BracketSeparator::Colon),
+                _ => separators.push(BracketSeparator::Pipe),
+            }
+            parts.push(self.parse_sequence(&[']', ':', '|']));
+        }
+
+        if closed {
+            let all_pipes = separators.iter().all(|s| *s == BracketSeparator::Pipe);
+            let all_colons = separators.iter().all(|s| *s == BracketSeparator::Colon);
+
+            if !separators.is_empty() && all_pipes {
+                return PromptNode::Alternate { options: parts };
+            }
+
+            if all_colons && matches!(parts.len(), 2 | 3) {
+                if let Some(step) = parts.last().and_then(|last| plain_number(last)) {
+                    let to = parts[parts.len() - 2].clone();
+                    let from = if parts.len() == 3 {
+                        parts[0].clone()
+                    } else {
+                        Vec::new()
+                    };
+                    return PromptNode::Scheduled { from, to, step };
+                }
+            }
+        }
+
+        // それ以外は弱調（区切り文字はテキストとして戻す）
+        let mut children = Vec::new();
+        for (index, part) in parts.into_iter().enumerate() {
+            if index > 0 {
+                let separator = match separators[index - 1] {
+                    BracketSeparator::Colon => ":",
+                    BracketSeparator::Pipe => "|",
+                };
+                push_text(&mut children, separator);
+            }
+            append_nodes(&mut children, part);
+        }
+
+        PromptNode::Emphasis {
+            multiplier: 1.0 / ATTENTION_MULTIPLIER,
+            children,
+        }
+    }
+}
+
+/// 蓄積したテキストをノードとして追加（BREAK で分割）
+fn flush_text(text: &mut String, nodes: &mut Vec<PromptNode>) {
+    if text.is_empty() {
+        return;
+    }
+
+    let mut last = 0;
+    for m in BREAK_REGEX.find_iter(text) {
+        push_text(nodes, &text[last..m.start()]);
+        nodes.push(PromptNode::Break);
+        last = m.end();
+    }
+    push_text(nodes, &text[last..]);
+    text.clear();
+}
+
+/// テキストノードを追加（直前がテキストなら連結）
+fn push_text(nodes: &mut Vec<PromptNode>, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    if let Some(PromptNode::Text { text: last, .. }) = nodes.last_mut() {
+        last.push_str(text);
+        return;
+    }
+    nodes.push(PromptNode::Text {
+        text: text.to_string(),
+        weight: 1.0,
+    });
+}
+
+/// ノード列を追加（隣接するテキストは連結）
+fn append_nodes(nodes: &mut Vec<PromptNode>, appended: Vec<PromptNode>) {
+    for node in appended {
+        match node {
+            PromptNode::Text { text, .. } => push_text(nodes, &text),
+            other => nodes.push(other),
+        }
+    }
+}
+
+/// テキストノードに実効重み（外側の強調の倍率の積）を設定
+///
+/// `(a:1.3)` の倍率は閉じ括弧まで読むまで決まらないため、パース後に外側から1度だけ辿る。
+fn assign_weights(nodes: &mut [PromptNode], weight: f32) {
+    for node in nodes {
+        match node {
+            PromptNode::Text {
+                weight: text_weight,
+                ..
+            } => *text_weight = weight,
+            PromptNode::Emphasis {
+                multiplier,
+                children,
+            } => assign_weights(children, weight * *multiplier),
+            PromptNode::Scheduled { from, to, .. } => {
+                assign_weights(from, weight);
+                assign_weights(to, weight);
+            }
+            PromptNode::Alternate { options } => {
+                for option in options {
+                    assign_weights(option, weight);
+                }
+            }
+            PromptNode::Break => {}
+        }
+    }
+}
+
+/// ノード列が数値のみのテキストであればその値
+fn plain_number(nodes: &[PromptNode]) -> Option<f32> {
+    match nodes {
+        [PromptNode::Text { text, .. }] => text.trim().parse::<f32>().ok(),
+        _ => None,
+    }
+}
+
+/// プロンプト文字列を構文木にパースする
+pub fn parse_prompt(prompt: &str) -> Vec<PromptNode> {
+    let mut nodes = PromptParser::new(prompt).parse_sequence(&[]);
+    assign_weights(&mut nodes, 1.0);
+    nodes
+}
+
+/// 実効重みを表示用に丸める（1.0 は重み無しとして扱う）
+fn normalize_weight(weight: f32) -> Option<f32> {
+    let rounded = (weight * 10000.0).round() / 10000.0;
+    if (rounded - 1.0).abs() < f32::EPSILON {
+        None
+    } else {
+        Some(rounded)
+    }
+}
+
+/// 構文木をSDタグ列に平坦化（カンマ区切り・実効重み付き）
+pub fn flatten_to_tags(nodes: &[PromptNode], tags: &mut Vec<SdTag>) {
+    for node in nodes {
+        match node {
+            PromptNode::Text { text, weight } => {
+                tags.extend(
+                    text.split(',')
+                        .map(|piece| piece.trim())
+                        .filter(|piece| !piece.is_empty())
+                        .map(|piece| SdTag {
+                            name: piece.to_string(),
+                            weight: normalize_weight(*weight),
+                        }),
+                );
+            }
+            PromptNode::Emphasis { children, .. } => flatten_to_tags(children, tags),
+            // プロンプト編集・交互はいずれのタグも生成に使われるため両方を含める
+            PromptNode::Scheduled { from, to, .. } => {
+                flatten_to_tags(from, tags);
+                flatten_to_tags(to, tags);
+            }
+            PromptNode::Alternate { options } => {
+                for option in options {
+                    flatten_to_tags(option, tags);
+                }
+            }
+            PromptNode::Break => {}
+        }
+    }
+}
+
+/// プロンプト文字列をSDタグ列に変換
+pub fn prompt_to_tags(prompt: &str) -> Vec<SdTag> {
+    let mut tags = Vec::new();
+    flatten_to_tags(&parse_prompt(prompt), &mut tags);
+    tags
+}
+
+/// プロンプト文字列を構文木として解析（Tauri API）
+#[tauri::command]
+pub fn parse_prompt_tree(prompt: String) -> Vec<PromptNode> {
+    parse_prompt(&prompt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str, weight: Option<f32>) -> SdTag {
+        SdTag {
+            name: name.to_string(),
+            weight,
+        }
+    }
+
+    fn text(text: &str, weight: f32) -> PromptNode {
+        PromptNode::Text {
+            text: text.to_string(),
+            weight,
+        }
+    }
+
+    #[test]
+    fn emphasis() {
+        assert_eq!(
+            prompt_to_tags("a, (b), ((c)), [d], (e:1.3), ( f : 0.5 )"),
+            [
+                tag("a", None),
+                tag("b", Some(1.1)),
+                tag("c", Some(1.21)),
+                tag("d", Some(0.9091)),
+                tag("e", Some(1.3)),
+                tag("f", Some(0.5)),
+            ]
+        );
+        // 入れ子の明示的な重みは外側の倍率と掛け合わされる
+        assert_eq!(prompt_to_tags("((a:1.5))"), [tag("a", Some(1.65))]);
+    }
+
+    #[test]
+    fn colon_without_weight_is_text() {
+        assert_eq!(prompt_to_tags("(a:b)"), [tag("a:b", Some(1.1))]);
+        assert_eq!(prompt_to_tags("(a:1.2 b)"), [tag("a:1.2 b", Some(1.1))]);
+    }
+
+    #[test]
+    fn scheduling() {
+        assert_eq!(
+            parse_prompt("[a:b:0.5]"),
+            [PromptNode::Scheduled {
+                from: vec![text("a", 1.0)],
+                to: vec![text("b", 1.0)],
+                step: 0.5,
+            }]
+        );
+        assert_eq!(
+            parse_prompt("[b:10]"),
+            [PromptNode::Scheduled {
+                from: vec![],
+                to: vec![text("b", 1.0)],
+                step: 10.0,
+            }]
+        );
+        assert_eq!(
+            prompt_to_tags("[a::0.3], [x:y:z]"),
+            [tag("a", None), tag("x:y:z", Some(0.9091))]
+        );
+    }
+
+    #[test]
+    fn alternation() {
+        assert_eq!(
+            parse_prompt("[cat|dog|(fox)]"),
+            [PromptNode::Alternate {
+                options: vec![
+                    vec![text("cat", 1.0)],
+                    vec![text("dog", 1.0)],
+                    vec![PromptNode::Emphasis {
+                        multiplier: 1.1,
+                        children: vec![text("fox", 1.1)],
+                    }],
+                ],
+            }]
+        );
+    }
+
+    #[test]
+    fn break_keyword() {
+        assert_eq!(
+            parse_prompt("a BREAK b, BREAKFAST"),
+            [
+                text("a ", 1.0),
+                PromptNode::Break,
+                text(" b, BREAKFAST", 1.0)
+            ]
+        );
+        assert_eq!(
+            prompt_to_tags("a BREAK b"),
+            [tag("a", None), tag("b", None)]
+        );
+    }
+
+    #[test]
+    fn escapes_and_extra_networks() {
+        assert_eq!(
+            prompt_to_tags(r"\(artist\), a \[b\], c\\d, <lora:detail_v1:0.8>"),
+            [
+                tag("(artist)", None),
+                tag("a [b]", None),
+                tag(r"c\d", None),
+                tag("<lora:detail_v1:0.8>", None),
+            ]
+        );
+        // 末尾のバックスラッシュはそのまま残す
+        assert_eq!(prompt_to_tags("a\\"), [tag("a\\", None)]);
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        // 閉じ括弧が無い場合は末尾まで強調
+        assert_eq!(
+            prompt_to_tags("a, (b, c"),
+            [tag("a", None), tag("b", Some(1.1)), tag("c", Some(1.1)),]
+        );
+        assert_eq!(prompt_to_tags("[a:b"), [tag("a:b", Some(0.9091))]);
+        // 対応の無い閉じ括弧はテキスト
+        assert_eq!(prompt_to_tags("a), b]"), [tag("a)", None), tag("b]", None)]);
+    }
+
+    #[test]
+    fn deep_nesting_is_text() {
+        let depth = 100_000;
+        let prompt = format!("{}a{}, b", "(".repeat(depth), ")".repeat(depth));
+        let nodes = parse_prompt(&prompt);
+
+        // 上限までは強調、それより深い括弧は対応する閉じ括弧までテキスト
+        let mut level = nodes.as_slice();
+        let mut levels = 0;
+        while let [PromptNode::Emphasis { children, .. }, ..] = level {
+            level = children;
+            levels += 1;
+        }
+        assert_eq!(levels, MAX_NESTING_DEPTH);
+        let literal_depth = depth - MAX_NESTING_DEPTH;
+        let literal = format!(
+            "{}a{}",
+            "(".repeat(literal_depth),
+            ")".repeat(literal_depth)
+        );
+        assert!(matches!(level, [PromptNode::Text { text, .. }] if *text == literal));
+        assert_eq!(nodes.last(), Some(&text(", b", 1.0)));
+
+        // 閉じ括弧が無い・種類が混在する場合も終了する
+        assert_eq!(prompt_to_tags(&"[(".repeat(depth)).len(), 1);
+        assert_eq!(
+            prompt_to_tags(&format!("{}\\)a", "(".repeat(depth))).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn long_weighted_prompt() {
+        let prompt = "(tag:1.1), ".repeat(5000);
+        let tags = prompt_to_tags(&prompt);
+        assert_eq!(tags.len(), 5000);
+        assert!(tags.iter().all(|t| *t == tag("tag", Some(1.1))));
+    }
+}
@@ -1,27 +1,285 @@
+use crate::prompt_parser::prompt_to_tags;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 // 正規表現を一度だけコンパイル（起動時エラーで早期発見）
-static TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"\(([^:]+):([0-9]+(?:\.[0-9]+)?)\)").expect("Invalid regex pattern for SD tags")
+// A1111 の設定行（`Key: value, Key: "quoted, value"`）の1項目（infotext_utils.py の re_param 相当）
+static SETTING_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"\s*(\w[\w \-/]+):\s*("(?:\\.|[^\\"])+"|[^,]*)(?:,|$)"#)
+        .expect("Invalid regex pattern for SD settings")
 });
 
-static FIELD_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"(Steps|Sampler|Schedule type|CFG scale|Seed|Size|Model|Denoising strength|Clip skip):\s*([^,]+)")
-        .expect("Invalid regex pattern for SD fields")
+// 追加ネットワーク記法（`<lora:name:0.8>` / `<hypernet:name:1>` 等、タグ中の任意の位置）
+static EXTRA_NETWORK_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"<(\w+):([^<>]+)>").expect("Invalid regex pattern for extra networks")
 });
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// 設定行より後ろの "Key: value" 形式の行
+static TRAILING_LINE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(\w[\w \-/]*):\s*(.*)$").expect("Invalid regex pattern for trailing lines")
+});
+
+/// 型付きフィールドとして保持する設定キー（A1111 の出力順）
+const TYPED_SETTING_KEYS: [&str; 9] = [
+    "Steps",
+    "Sampler",
+    "Schedule type",
+    "CFG scale",
+    "Seed",
+    "Size",
+    "Model",
+    "Denoising strength",
+    "Clip skip",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SdTag {
     pub name: String,
     pub weight: Option<f32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// 設定行の1項目（キーと値）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SdSetting {
+    pub key: String,
+    pub value: String,
+}
+
+/// 追加ネットワークの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetworkKind {
+    Lora,
+    Lycoris,
+    Hypernetwork,
+    Embedding,
+}
+
+impl NetworkKind {
+    /// 追加ネットワーク記法のタグ名から種類を判定
+    fn from_prompt_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "lora" => Some(Self::Lora),
+            "lyco" => Some(Self::Lycoris),
+            "hypernet" => Some(Self::Hypernetwork),
+            _ => None,
+        }
+    }
+
+    /// ハッシュが記録される設定キー
+    fn hash_setting_keys(&self) -> &'static [&'static str] {
+        match self {
+            Self::Lora | Self::Lycoris => &["Lora hashes", "Lyco hashes"],
+            Self::Hypernetwork => &["Hypernet hashes"],
+            Self::Embedding => &["TI hashes"],
+        }
+    }
+}
+
+/// プロンプトから参照される追加ネットワーク（LoRA / LyCORIS / Hypernetwork / Embedding）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkRef {
+    pub kind: NetworkKind,
+    pub name: String,
+    pub unet_weight: Option<f32>,
+    pub te_weight: Option<f32>,
+    pub hash: Option<String>,
+}
+
+impl NetworkRef {
+    /// `<lora:name:te[:unet]>` / `<lora:name:te=0.5:unet=0.8>` 形式の記法を解析
+    fn from_captures(caps: &regex::Captures) -> Option<Self> {
+        let kind = NetworkKind::from_prompt_tag(caps.get(1)?.as_str())?;
+
+        let mut args = caps.get(2)?.as_str().split(':').map(str::trim);
+        let name = args.next().filter(|name| !name.is_empty())?.to_string();
+
+        let mut positional = Vec::new();
+        let mut te_weight = None;
+        let mut unet_weight = None;
+        for arg in args {
+            match arg.split_once('=') {
+                Some(("te", value)) => te_weight = value.trim().parse::<f32>().ok(),
+                Some(("unet", value)) => unet_weight = value.trim().parse::<f32>().ok(),
+                Some(_) => {} // dyn 等は対象外
+                None => positional.extend(arg.parse::<f32>().ok()),
+            }
+        }
+
+        // A1111 と同じく第1引数がテキストエンコーダー、第2引数がUNet（省略時は第1引数と同じ）
+        let te_weight = te_weight.or(positional.first().copied()).or(Some(1.0));
+        let unet_weight = unet_weight.or(positional.get(1).copied()).or(te_weight);
+
+        let (te_weight, unet_weight) = match kind {
+            // Hypernetwork は倍率が1つのみ
+            NetworkKind::Hypernetwork => (None, te_weight),
+            _ => (te_weight, unet_weight),
+        };
+
+        Some(Self {
+            kind,
+            name,
+            unet_weight,
+            te_weight,
+            hash: None,
+        })
+    }
+
+    /// タグ名中の追加ネットワーク記法をすべて抽出し、記法を除いた残りのテキストと共に返す
+    fn extract_from_tag(tag: &str) -> (Vec<Self>, String) {
+        let mut networks = Vec::new();
+        let mut rest = Vec::new();
+        let mut last = 0;
+        for caps in EXTRA_NETWORK_REGEX.captures_iter(tag) {
+            let Some(network) = Self::from_captures(&caps) else {
+                continue;
+            };
+            let span = caps.get(0).expect("capture group 0 always exists");
+            rest.push(tag[last..span.start()].trim());
+            networks.push(network);
+            last = span.end();
+        }
+        rest.push(tag[last..].trim());
+
+        let rest = rest
+            .into_iter()
+            .filter(|piece| !piece.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        (networks, rest)
+    }
+
+    /// 追加ネットワーク記法のタグに変換（Embedding はプロンプト中の単語のため None）
+    fn to_prompt_tag(&self) -> Option<String> {
+        let tag = match self.kind {
+            NetworkKind::Lora => "lora",
+            NetworkKind::Lycoris => "lyco",
+            NetworkKind::Hypernetwork => "hypernet",
+            NetworkKind::Embedding => return None,
+        };
+        let weights = match (self.te_weight, self.unet_weight) {
+            (Some(te), Some(unet)) if te != unet => format!(":{}:{}", te, unet),
+            (Some(weight), _) | (None, Some(weight)) => format!(":{}", weight),
+            (None, None) => String::new(),
+        };
+        Some(format!("<{}:{}{}>", tag, self.name, weights))
+    }
+}
+
+/// 数値フィールドの解析エラー
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SdValueError {
+    /// 数値として解釈できない
+    InvalidNumber { field: String, value: String },
+    /// 数値だが許容範囲外
+    OutOfRange { field: String, value: String },
+    /// "WIDTHxHEIGHT" 形式ではない
+    InvalidSize { value: String },
+}
+
+impl fmt::Display for SdValueError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidNumber { field, value } => {
+                write!(f, "{} の値が数値ではありません: {}", field, value)
+            }
+            Self::OutOfRange { field, value } => {
+                write!(f, "{} の値が範囲外です: {}", field, value)
+            }
+            Self::InvalidSize { value } => write!(f, "Size の形式が不正です: {}", value),
+        }
+    }
+}
+
+/// 数値フィールドを型付きで保持した値（ソート・範囲フィルター用）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SdTypedValues {
+    pub steps: Option<u32>,
+    pub cfg_scale: Option<f32>,
+    #[serde(default, with = "seed_string")]
+    pub seed: Option<u64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub denoising_strength: Option<f32>,
+    pub clip_skip: Option<u32>,
+    /// 解釈できなかったフィールド（該当する値は None）
+    pub errors: Vec<SdValueError>,
+}
+
+/// シード値の JSON 表現（u64 は JavaScript の number では精度が落ちるため10進文字列で送受信する）
+pub mod seed_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(seed: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match seed {
+            Some(seed) => serializer.collect_str(seed),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|seed| seed.parse().map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// 文字列フィールドを数値として解析（未設定は Ok(None)）
+fn parse_number<T: std::str::FromStr>(
+    field: &str,
+    value: Option<&str>,
+) -> Result<Option<T>, SdValueError> {
+    let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    value
+        .parse::<T>()
+        .map(Some)
+        .map_err(|_| SdValueError::InvalidNumber {
+            field: field.to_string(),
+            value: value.to_string(),
+        })
+}
+
+/// 解析済みの数値が範囲内か検証
+fn check_range<T: PartialOrd + fmt::Display>(
+    field: &str,
+    value: Option<T>,
+    range: std::ops::RangeInclusive<T>,
+) -> Result<Option<T>, SdValueError> {
+    match value {
+        Some(value) if !range.contains(&value) => Err(SdValueError::OutOfRange {
+            field: field.to_string(),
+            value: value.to_string(),
+        }),
+        value => Ok(value),
+    }
+}
+
+/// SD Parametersの取得元（生成ツール）
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SdParameterSource {
+    #[default]
+    A1111,
+    ComfyUi,
+    NovelAi,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SdParameters {
+    pub source: SdParameterSource,
+    /// プロンプト原文（A1111 形式への変換時、タグ列と一致する場合はこちらを優先）
+    pub positive_prompt: String,
+    pub negative_prompt: String,
     pub positive_sd_tags: Vec<SdTag>,
     pub negative_sd_tags: Vec<SdTag>,
+    /// ポジティブプロンプトで使用された追加ネットワーク（タグ列からは除外）
+    pub networks: Vec<NetworkRef>,
     pub steps: Option<String>,
     pub sampler: Option<String>,
     pub schedule_type: Option<String>,
@@ -31,156 +289,811 @@ pub struct SdParameters {
     pub model: Option<String>,
     pub denoising_strength: Option<String>,
     pub clip_skip: Option<String>,
+    /// 設定行の全項目（出力順を保持。Model hash / VAE / Lora hashes 等も含む）
+    pub settings: Vec<SdSetting>,
+    /// 解析時の警告（設定行が無い等、一部のみ取得できた場合）
+    pub parse_warnings: Vec<String>,
     pub raw: String,
 }
 
 impl SdParameters {
-    /// SDタグ文字列をパースする
-    fn parse_sd_tags(s: &str) -> Vec<SdTag> {
-        s.split(',')
-            .map(|piece| piece.trim())
-            .filter_map(|raw_tag| {
-                if raw_tag.is_empty() {
-                    return None; // 空文字列はスキップ
-                }
-
-                // 正規表現マッチング（安全）
-                if let Some(caps) = TAG_REGEX.captures(raw_tag) {
-                    // キャプチャグループの安全な取得
-                    let name = caps.get(1)?.as_str().trim();
-                    let weight_str = caps.get(2)?.as_str().trim();
-
-                    if name.is_empty() {
-                        return None; // 空のタグ名はスキップ
-                    }
+    /// SDタグ文字列をパースする（強調・プロンプト編集等の文法を解釈し、実効重みを付与）
+    pub(crate) fn parse_sd_tags(s: &str) -> Vec<SdTag> {
+        prompt_to_tags(s)
+    }
 
-                    let weight = weight_str.parse::<f32>().ok();
+    /// 設定行を順序付きのキー・値リストにパースする
+    fn parse_settings(line: &str) -> Vec<SdSetting> {
+        SETTING_REGEX
+            .captures_iter(line)
+            .filter_map(|cap| {
+                let key = cap.get(1)?.as_str().trim();
+                let value = cap.get(2)?.as_str().trim();
 
-                    Some(SdTag {
-                        name: name.to_string(),
-                        weight,
-                    })
+                // 引用符付きの値はJSON文字列としてアンエスケープ
+                let value = if value.starts_with('"') {
+                    serde_json::from_str::<String>(value).unwrap_or_else(|_| value.to_string())
                 } else {
-                    // 通常タグ
-                    Some(SdTag {
-                        name: raw_tag.to_string(),
-                        weight: None,
-                    })
-                }
+                    value.to_string()
+                };
+
+                Some(SdSetting {
+                    key: key.to_string(),
+                    value,
+                })
             })
             .collect()
     }
 
-    /// 全フィールドの値を一括抽出
-    fn extract_all_fields(
-        text: &str,
-    ) -> (
-        Option<String>, // steps
-        Option<String>, // sampler
-        Option<String>, // schedule_type
-        Option<String>, // cfg_scale
-        Option<String>, // seed
-        Option<String>, // size
-        Option<String>, // model
-        Option<String>, // denoising_strength
-        Option<String>, // clip_skip
-    ) {
-        let mut steps = None;
-        let mut sampler = None;
-        let mut schedule_type = None;
-        let mut cfg_scale = None;
-        let mut seed = None;
-        let mut size = None;
-        let mut model = None;
-        let mut denoising_strength = None;
-        let mut clip_skip = None;
-
-        // 1回のスキャンで全フィールドを取得
-        for cap in FIELD_REGEX.captures_iter(text) {
-            if let (Some(key_match), Some(value_match)) = (cap.get(1), cap.get(2)) {
-                let key = key_match.as_str();
-                let value = value_match.as_str().trim();
-
-                if value.is_empty() {
-                    continue;
-                }
-
-                match key {
-                    "Steps" => steps = Some(value.to_string()),
-                    "Sampler" => sampler = Some(value.to_string()),
-                    "Schedule type" => schedule_type = Some(value.to_string()),
-                    "CFG scale" => cfg_scale = Some(value.to_string()),
-                    "Seed" => seed = Some(value.to_string()),
-                    "Size" => size = Some(value.to_string()),
-                    "Model" => model = Some(value.to_string()),
-                    "Denoising strength" => denoising_strength = Some(value.to_string()),
-                    "Clip skip" => clip_skip = Some(value.to_string()),
-                    _ => {}
-                }
-            }
-        }
+    /// 設定値を取得（空の値は None）
+    fn find_setting<'a>(settings: &'a [SdSetting], key: &str) -> Option<&'a str> {
+        settings
+            .iter()
+            .find(|setting| setting.key == key)
+            .map(|setting| setting.value.as_str())
+            .filter(|value| !value.is_empty())
+    }
 
-        (
-            steps,
-            sampler,
-            schedule_type,
-            cfg_scale,
-            seed,
-            size,
-            model,
-            denoising_strength,
-            clip_skip,
-        )
+    /// 設定行の候補かどうか（"Steps:" で始まるか、A1111 と同じく3項目以上の key: value を含む）
+    fn is_settings_line(line: &str) -> bool {
+        line.trim_start().starts_with("Steps:") || 3 <= Self::parse_settings(line).len()
     }
 
     /// SD Parameters文字列をパースする
+    ///
+    /// ネガティブプロンプト・設定行が欠けていても取得できた部分のみを返し、
+    /// 想定外の構造は `parse_warnings` に記録する。
     pub fn parse(parameter: &str) -> Result<SdParameters, String> {
         if parameter.trim().is_empty() {
             return Err("Empty parameter string".to_string());
         }
 
-        // "Negative prompt:" で分割
-        let pp_separated: Vec<&str> = parameter.splitn(2, "\nNegative prompt:").collect();
-        if pp_separated.len() != 2 {
-            return Err("\"Negative prompt:\" section not found".to_string());
+        let lines: Vec<&str> = parameter.trim().lines().collect();
+        let mut parse_warnings = Vec::new();
+
+        // 設定行は "Steps:" で始まる最後の行（無ければ末尾行が key: value 形式の場合のみ）
+        let settings_index = lines
+            .iter()
+            .rposition(|line| line.trim_start().starts_with("Steps:"))
+            .or_else(|| {
+                let last = lines.len() - 1;
+                (0 < last && Self::is_settings_line(lines[last])).then_some(last)
+            });
+
+        let (body, mut settings) = match settings_index {
+            Some(index) => (&lines[..index], Self::parse_settings(lines[index])),
+            None => {
+                parse_warnings.push("Settings line not found".to_string());
+                (&lines[..], Vec::new())
+            }
+        };
+        if settings_index.is_some() && Self::find_setting(&settings, "Steps").is_none() {
+            parse_warnings.push("\"Steps:\" not found in settings line".to_string());
         }
 
-        // "Steps:" で分割
-        let np_separated: Vec<&str> = pp_separated[1].splitn(2, "\nSteps:").collect();
-        if np_separated.len() != 2 {
-            return Err("\"Steps:\" section not found".to_string());
+        // 設定行より後ろの拡張機能ブロック（"Template: ..." 等）は1行1項目として保持
+        for line in settings_index.map_or(&[][..], |index| &lines[index + 1..]) {
+            match TRAILING_LINE_REGEX.captures(line.trim()) {
+                Some(cap) => settings.push(SdSetting {
+                    key: cap[1].trim().to_string(),
+                    value: cap[2].trim().to_string(),
+                }),
+                None if line.trim().is_empty() => {}
+                None => parse_warnings.push(format!("Unrecognized trailing line: {}", line)),
+            }
         }
 
-        let positive_sd_tags = Self::parse_sd_tags(pp_separated[0]);
-        let negative_sd_tags = Self::parse_sd_tags(np_separated[0]);
+        // "Negative prompt:" 行以降がネガティブプロンプト（空の場合 A1111 は行ごと省略する）
+        let (positive_lines, negative_lines) = match body
+            .iter()
+            .position(|line| line.starts_with("Negative prompt:"))
+        {
+            Some(index) => (&body[..index], &body[index..]),
+            None => (body, &[][..]),
+        };
+        let positive_prompt = positive_lines.join("\n").trim().to_string();
+        let negative_prompt = negative_lines
+            .join("\n")
+            .trim_start_matches("Negative prompt:")
+            .trim()
+            .to_string();
 
-        // フィールド部分から必要な値を一括抽出
-        let fields_section = &format!("Steps:{}", np_separated[1]);
-        let (
-            steps,
-            sampler,
-            schedule_type,
-            cfg_scale,
-            seed,
-            size,
-            model,
-            denoising_strength,
-            clip_skip,
-        ) = Self::extract_all_fields(fields_section);
+        let field = |key: &str| Self::find_setting(&settings, key).map(str::to_string);
 
         Ok(SdParameters {
-            positive_sd_tags,
-            negative_sd_tags,
+            source: SdParameterSource::A1111,
+            networks: Vec::new(),
+            positive_sd_tags: Self::parse_sd_tags(&positive_prompt),
+            negative_sd_tags: Self::parse_sd_tags(&negative_prompt),
+            positive_prompt,
+            negative_prompt,
+            steps: field("Steps"),
+            sampler: field("Sampler"),
+            schedule_type: field("Schedule type"),
+            cfg_scale: field("CFG scale"),
+            seed: field("Seed"),
+            size: field("Size"),
+            model: field("Model"),
+            denoising_strength: field("Denoising strength"),
+            clip_skip: field("Clip skip"),
+            settings,
+            parse_warnings,
+            raw: parameter.to_string(),
+        }
+        .with_networks())
+    }
+
+    /// "Lora hashes: \"a: 123, b: 456\"" 形式のハッシュ一覧を名前とハッシュの組に分解
+    fn parse_hash_list(value: &str) -> Vec<(String, String)> {
+        value
+            .split(',')
+            .filter_map(|entry| {
+                let (name, hash) = entry.rsplit_once(':')?;
+                Some((name.trim().to_string(), hash.trim().to_string()))
+            })
+            .filter(|(name, hash)| !name.is_empty() && !hash.is_empty())
+            .collect()
+    }
+
+    /// ポジティブプロンプトのタグ列から追加ネットワークを抽出し、設定行のハッシュと対応付ける
+    ///
+    /// `smile <lora:x:0.8>` のようにタグの一部として書かれた記法も抽出し、タグ名からは除去する。
+    /// Embedding はプロンプト中では通常の単語のため、タグ列に残したまま "TI hashes" から登録する。
+    pub(crate) fn with_networks(mut self) -> Self {
+        let mut networks = Vec::new();
+        self.positive_sd_tags.retain_mut(|tag| {
+            let (found, rest) = NetworkRef::extract_from_tag(&tag.name);
+            if found.is_empty() {
+                return true;
+            }
+            networks.extend(found);
+            tag.name = rest;
+            !tag.name.is_empty()
+        });
+
+        for network in &mut networks {
+            network.hash = network
+                .kind
+                .hash_setting_keys()
+                .iter()
+                .filter_map(|key| Self::find_setting(&self.settings, key))
+                .flat_map(Self::parse_hash_list)
+                .find(|(name, _)| *name == network.name)
+                .map(|(_, hash)| hash);
+        }
+
+        if let Some(ti_hashes) = Self::find_setting(&self.settings, "TI hashes") {
+            networks.extend(
+                Self::parse_hash_list(ti_hashes)
+                    .into_iter()
+                    .map(|(name, hash)| {
+                        // プロンプト中で重み付けされていればその重みを使用
+                        let weight = self
+                            .positive_sd_tags
+                            .iter()
+                            .chain(&self.negative_sd_tags)
+                            .find(|tag| tag.name == name)
+                            .map(|tag| tag.weight.unwrap_or(1.0));
+                        NetworkRef {
+                            kind: NetworkKind::Embedding,
+                            name,
+                            unet_weight: None,
+                            te_weight: weight,
+                            hash: Some(hash),
+                        }
+                    }),
+            );
+        }
+
+        self.networks = networks;
+        self
+    }
+
+    /// ステップ数（1 以上）
+    pub fn steps_value(&self) -> Result<Option<u32>, SdValueError> {
+        let steps = parse_number("Steps", self.steps.as_deref())?;
+        check_range("Steps", steps, 1..=u32::MAX)
+    }
+
+    /// CFG scale（0 以上の有限値）
+    pub fn cfg_scale_value(&self) -> Result<Option<f32>, SdValueError> {
+        let cfg_scale = parse_number("CFG scale", self.cfg_scale.as_deref())?;
+        check_range("CFG scale", cfg_scale, 0.0..=f32::MAX)
+    }
+
+    /// シード値（ComfyUI の u64 全域。-1 はランダムシードで値が決まっていないため None）
+    pub fn seed_value(&self) -> Result<Option<u64>, SdValueError> {
+        let seed = self.seed.as_deref().map(str::trim);
+        if seed == Some("-1") {
+            return Ok(None);
+        }
+        parse_number("Seed", seed).map_err(|e| {
+            // 整数として読めるが u64 に収まらない値（負の値・桁あふれ）は範囲外
+            let digits = seed.map(|value| value.strip_prefix('-').unwrap_or(value));
+            match digits {
+                Some(digits)
+                    if !digits.is_empty() && digits.bytes().all(|byte| byte.is_ascii_digit()) =>
+                {
+                    SdValueError::OutOfRange {
+                        field: "Seed".to_string(),
+                        value: seed.unwrap_or_default().to_string(),
+                    }
+                }
+                _ => e,
+            }
+        })
+    }
+
+    /// 画像サイズ（"512x768" → (512, 768)）
+    pub fn size_value(&self) -> Result<Option<(u32, u32)>, SdValueError> {
+        let Some(size) = self.size.as_deref().map(str::trim) else {
+            return Ok(None);
+        };
+        let invalid = || SdValueError::InvalidSize {
+            value: size.to_string(),
+        };
+
+        let (width, height) = size.split_once(['x', 'X', '×']).ok_or_else(invalid)?;
+        let width = width.trim().parse::<u32>().map_err(|_| invalid())?;
+        let height = height.trim().parse::<u32>().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid());
+        }
+        Ok(Some((width, height)))
+    }
+
+    /// Denoising strength（0〜1）
+    pub fn denoising_strength_value(&self) -> Result<Option<f32>, SdValueError> {
+        let strength = parse_number("Denoising strength", self.denoising_strength.as_deref())?;
+        check_range("Denoising strength", strength, 0.0..=1.0)
+    }
+
+    /// Clip skip（1 以上）
+    pub fn clip_skip_value(&self) -> Result<Option<u32>, SdValueError> {
+        let clip_skip = parse_number("Clip skip", self.clip_skip.as_deref())?;
+        check_range("Clip skip", clip_skip, 1..=u32::MAX)
+    }
+
+    /// 数値フィールドをまとめて型付きで取得（不正な値は None とし errors に記録）
+    pub fn typed_values(&self) -> SdTypedValues {
+        fn collect<T>(
+            result: Result<Option<T>, SdValueError>,
+            errors: &mut Vec<SdValueError>,
+        ) -> Option<T> {
+            result.unwrap_or_else(|e| {
+                errors.push(e);
+                None
+            })
+        }
+
+        let mut errors = Vec::new();
+        let steps = collect(self.steps_value(), &mut errors);
+        let cfg_scale = collect(self.cfg_scale_value(), &mut errors);
+        let seed = collect(self.seed_value(), &mut errors);
+        let size = collect(self.size_value(), &mut errors);
+        let denoising_strength = collect(self.denoising_strength_value(), &mut errors);
+        let clip_skip = collect(self.clip_skip_value(), &mut errors);
+
+        SdTypedValues {
             steps,
-            sampler,
-            schedule_type,
             cfg_scale,
             seed,
-            size,
-            model,
+            width: size.map(|(width, _)| width),
+            height: size.map(|(_, height)| height),
             denoising_strength,
             clip_skip,
-            raw: parameter.to_string(),
-        })
+            errors,
+        }
+    }
+
+    /// SDタグ列をプロンプト文字列に変換（重み付きタグは `(name:1.2)` 形式）
+    fn format_sd_tags(tags: &[SdTag]) -> String {
+        tags.iter()
+            .map(|tag| {
+                // タグ名中の括弧は文法記号と区別するためエスケープ
+                let name = tag
+                    .name
+                    .replace('\\', "\\\\")
+                    .replace('(', "\\(")
+                    .replace(')', "\\)")
+                    .replace('[', "\\[")
+                    .replace(']', "\\]");
+                match tag.weight {
+                    Some(weight) => format!("({}:{})", name, weight),
+                    None => name,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// プロンプト原文（無い場合・タグ列が原文と一致しない場合はタグ列から再構築）
+    fn prompt_text(prompt: &str, tags: &[SdTag]) -> String {
+        if prompt.is_empty() || Self::parse_sd_tags(prompt) != tags {
+            Self::format_sd_tags(tags)
+        } else {
+            prompt.to_string()
+        }
+    }
+
+    /// ポジティブプロンプト原文がタグ列・追加ネットワークと一致するか（タグ列が編集されていないか）
+    fn positive_prompt_matches(&self) -> bool {
+        if self.positive_prompt.is_empty() {
+            return false;
+        }
+        let reparsed = SdParameters {
+            positive_sd_tags: Self::parse_sd_tags(&self.positive_prompt),
+            negative_sd_tags: self.negative_sd_tags.clone(),
+            settings: self.settings.clone(),
+            ..Default::default()
+        }
+        .with_networks();
+        reparsed.positive_sd_tags == self.positive_sd_tags && reparsed.networks == self.networks
+    }
+
+    /// 設定値を A1111 形式で出力（区切り文字を含む値は A1111 と同じくJSON文字列として引用）
+    fn format_field_value(value: &str) -> String {
+        if value.contains([',', ':', '"', '\n']) {
+            serde_json::to_string(value).unwrap_or_else(|_| value.to_string())
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// 型付きフィールドの値
+    fn typed_field(&self, key: &str) -> Option<&str> {
+        match key {
+            "Steps" => self.steps.as_deref(),
+            "Sampler" => self.sampler.as_deref(),
+            "Schedule type" => self.schedule_type.as_deref(),
+            "CFG scale" => self.cfg_scale.as_deref(),
+            "Seed" => self.seed.as_deref(),
+            "Size" => self.size.as_deref(),
+            "Model" => self.model.as_deref(),
+            "Denoising strength" => self.denoising_strength.as_deref(),
+            "Clip skip" => self.clip_skip.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// 出力用の設定リストを構築（型付きフィールドの値を優先し、それ以外の項目は順序を保持）
+    fn merged_settings(&self) -> Vec<SdSetting> {
+        let mut merged: Vec<SdSetting> = self
+            .settings
+            .iter()
+            .filter_map(|setting| {
+                if !TYPED_SETTING_KEYS.contains(&setting.key.as_str()) {
+                    return Some(setting.clone());
+                }
+                Some(SdSetting {
+                    key: setting.key.clone(),
+                    value: self.typed_field(&setting.key)?.to_string(),
+                })
+            })
+            .collect();
+
+        // settings に無い型付きフィールドは、直前の型付きキーの後ろに挿入
+        let mut insert_at = 0;
+        for key in TYPED_SETTING_KEYS {
+            if let Some(pos) = merged.iter().position(|setting| setting.key == key) {
+                insert_at = pos + 1;
+                continue;
+            }
+            if let Some(value) = self.typed_field(key) {
+                merged.insert(
+                    insert_at,
+                    SdSetting {
+                        key: key.to_string(),
+                        value: value.to_string(),
+                    },
+                );
+                insert_at += 1;
+            }
+        }
+
+        merged
+    }
+
+    /// A1111 形式の文字列に変換（WebUI の "Read generation parameters" にそのまま貼り付け可能）
+    ///
+    /// `parse` の逆変換。`parse` の結果 `x` に対しては `parse(&x.to_a1111_string())` が `raw` 以外で `x` と一致する。
+    /// 他形式から変換した値では `source` は A1111 になり、`settings` は型付きフィールドから補完される。
+    pub fn to_a1111_string(&self) -> String {
+        let settings = self
+            .merged_settings()
+            .iter()
+            .map(|setting| {
+                format!(
+                    "{}: {}",
+                    setting.key,
+                    Self::format_field_value(&setting.value)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        // タグ列から再構築する場合は除外済みの追加ネットワークを末尾に戻す
+        let positive_prompt_matches = self.positive_prompt_matches();
+        let mut positive = if positive_prompt_matches {
+            self.positive_prompt.clone()
+        } else {
+            Self::format_sd_tags(&self.positive_sd_tags)
+        };
+        if !positive_prompt_matches {
+            for network in self.networks.iter().filter_map(NetworkRef::to_prompt_tag) {
+                if !positive.is_empty() {
+                    positive.push_str(", ");
+                }
+                positive.push_str(&network);
+            }
+        }
+
+        // A1111 と同じく空のネガティブプロンプト・設定行は行ごと省略
+        let mut lines = vec![positive];
+        let negative = Self::prompt_text(&self.negative_prompt, &self.negative_sd_tags);
+        if !negative.is_empty() {
+            lines.push(format!("Negative prompt: {}", negative));
+        }
+        if !settings.is_empty() {
+            lines.push(settings);
+        }
+        lines.join("\n")
+    }
+}
+
+impl fmt::Display for SdParameters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_a1111_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `parse` → `to_a1111_string` → `parse` で `raw` 以外が一致することを確認
+    fn assert_round_trip(parameter: &str) -> SdParameters {
+        let parsed = SdParameters::parse(parameter).unwrap();
+        let reparsed = SdParameters::parse(&parsed.to_a1111_string()).unwrap();
+        assert_eq!(
+            SdParameters {
+                raw: String::new(),
+                ..reparsed
+            },
+            SdParameters {
+                raw: String::new(),
+                ..parsed.clone()
+            },
+            "{:?}",
+            parameter
+        );
+        parsed
+    }
+
+    /// 生成テスト用の再現可能な乱数（xorshift64）
+    struct TestRng(u64);
+
+    impl TestRng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[self.below(items.len())]
+        }
+    }
+
+    /// 強調・弱め・エスケープ・追加ネットワークを含むプロンプトを生成
+    fn generated_prompt(rng: &mut TestRng) -> String {
+        const TAGS: &[&str] = &["1girl", "smile", "blue hair", "looking at viewer", "star"];
+        let mut prompt = String::new();
+        for i in 0..=rng.below(6) {
+            if i > 0 {
+                prompt.push_str(rng.pick(&[", ", ", ", ",\n"]));
+            }
+            let tag = rng.pick(TAGS);
+            let piece = match rng.below(6) {
+                0 => format!("({}:1.{})", tag, rng.below(10)),
+                1 => format!("({})", tag),
+                2 => format!("[{}]", tag),
+                3 => format!("\\({}\\)", tag),
+                4 => format!(
+                    "<lora:{}_v{}:0.{}>",
+                    tag.replace(' ', "_"),
+                    i,
+                    rng.below(10)
+                ),
+                _ => tag.to_string(),
+            };
+            prompt.push_str(&piece);
+        }
+        prompt
+    }
+
+    /// 引用が必要な文字（カンマ・コロン・引用符・改行）を含みうる設定値を生成
+    fn generated_setting_value(rng: &mut TestRng) -> String {
+        const PIECES: &[&str] = &[
+            "a",
+            "b c",
+            ",",
+            ":",
+            "\"",
+            "\n",
+            "\\",
+            "4x-UltraSharp",
+            "(x:1.2)",
+            "é",
+            "<lora:x:1>",
+        ];
+        let value: String = (0..=rng.below(5)).map(|_| rng.pick(PIECES)).collect();
+        match value.trim() {
+            "" => "x".to_string(),
+            value => value.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trip_generated_parameters() {
+        const KEYS: &[&str] = &["Hires prompt", "ADetailer prompt", "Template", "Note"];
+        let mut rng = TestRng(0x5eed_1234_abcd_0001);
+
+        for _ in 0..500 {
+            let positive = generated_prompt(&mut rng);
+            let negative = match rng.below(3) {
+                0 => String::new(),
+                _ => generated_prompt(&mut rng),
+            };
+            let values: Vec<_> = KEYS
+                .iter()
+                .filter_map(|key| {
+                    (rng.below(2) == 0).then(|| (*key, generated_setting_value(&mut rng)))
+                })
+                .collect();
+
+            let mut settings = vec![
+                format!("Steps: {}", rng.below(150) + 1),
+                "Sampler: DPM++ 2M".to_string(),
+                format!("Seed: {}", rng.below(usize::MAX)),
+            ];
+            settings.extend(values.iter().map(|(key, value)| {
+                format!("{}: {}", key, SdParameters::format_field_value(value))
+            }));
+            let mut text = positive.clone();
+            if !negative.is_empty() {
+                text.push_str(&format!("\nNegative prompt: {}", negative));
+            }
+            text.push_str(&format!("\n{}", settings.join(", ")));
+
+            let parsed = assert_round_trip(&text);
+            assert_eq!(parsed.positive_prompt, positive, "{:?}", text);
+            // タグ列から再構築した場合もタグ・追加ネットワークが一致する
+            let rebuilt = SdParameters {
+                positive_prompt: String::new(),
+                ..parsed.clone()
+            };
+            let reparsed = SdParameters::parse(&rebuilt.to_a1111_string()).unwrap();
+            assert_eq!(
+                reparsed.positive_sd_tags, parsed.positive_sd_tags,
+                "{:?}",
+                text
+            );
+            assert_eq!(reparsed.networks, parsed.networks, "{:?}", text);
+            assert_eq!(parsed.negative_prompt, negative, "{:?}", text);
+            for (key, value) in &values {
+                assert_eq!(
+                    SdParameters::find_setting(&parsed.settings, key),
+                    Some(value.as_str()),
+                    "{:?}",
+                    text
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn round_trip_basic() {
+        let parsed = assert_round_trip(
+            "masterpiece, (1girl:1.2), [smile]\nNegative prompt: lowres, bad hands\n\
+             Steps: 20, Sampler: Euler a, Schedule type: Karras, CFG scale: 7, Seed: 123, \
+             Size: 512x768, Model hash: abc123, Model: model_v1, Clip skip: 2",
+        );
+        assert_eq!(parsed.steps.as_deref(), Some("20"));
+        assert_eq!(parsed.model.as_deref(), Some("model_v1"));
+        assert!(parsed.parse_warnings.is_empty());
+    }
+
+    #[test]
+    fn round_trip_quoted_values() {
+        let parsed = assert_round_trip(
+            "1girl\nSteps: 20, Sampler: DPM++ 2M, Seed: 1, \
+             Hires upscaler: \"4x-UltraSharp, v2\", Note: \"a \\\"quoted\\\" value: x\"",
+        );
+        let note = SdParameters::find_setting(&parsed.settings, "Note");
+        assert_eq!(note, Some("a \"quoted\" value: x"));
+        let upscaler = SdParameters::find_setting(&parsed.settings, "Hires upscaler");
+        assert_eq!(upscaler, Some("4x-UltraSharp, v2"));
+    }
+
+    #[test]
+    fn round_trip_settings_order_and_trailing_lines() {
+        let parsed = assert_round_trip(
+            "1girl\nSteps: 30, VAE: vae.pt, Seed: 5, ENSD: 31337, Version: v1.9.0\nTemplate: 1girl",
+        );
+        let keys: Vec<_> = parsed.settings.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            ["Steps", "VAE", "Seed", "ENSD", "Version", "Template"]
+        );
+    }
+
+    #[test]
+    fn round_trip_networks() {
+        let parsed = assert_round_trip(
+            "1girl, <lora:detail:0.6>, <lyco:style:0.8:0.5>, <hypernet:hn:1>, easynegative\n\
+             Steps: 20, Seed: 1, Lora hashes: \"detail: aaa\", Lyco hashes: \"style: bbb\", \
+             Hypernet hashes: \"hn: ccc\", TI hashes: \"easynegative: ddd\"",
+        );
+        let networks: Vec<_> = parsed
+            .networks
+            .iter()
+            .map(|n| (n.kind, n.name.as_str(), n.hash.as_deref()))
+            .collect();
+        assert_eq!(
+            networks,
+            [
+                (NetworkKind::Lora, "detail", Some("aaa")),
+                (NetworkKind::Lycoris, "style", Some("bbb")),
+                (NetworkKind::Hypernetwork, "hn", Some("ccc")),
+                (NetworkKind::Embedding, "easynegative", Some("ddd")),
+            ]
+        );
+        assert!(parsed
+            .positive_sd_tags
+            .iter()
+            .all(|tag| !tag.name.starts_with('<')));
+    }
+
+    #[test]
+    fn networks_inside_tags() {
+        let parsed = SdParameters::parse(
+            "smile <lora:a:0.5>, (blue hair <lyco:b:0.8>:1.2), <lora:c:1><hypernet:d:0.3>\n\
+             Steps: 20, Lora hashes: \"a: 111, c: 333\"",
+        )
+        .unwrap();
+        let networks: Vec<_> = parsed
+            .networks
+            .iter()
+            .map(|n| (n.kind, n.name.as_str(), n.hash.as_deref()))
+            .collect();
+        assert_eq!(
+            networks,
+            [
+                (NetworkKind::Lora, "a", Some("111")),
+                (NetworkKind::Lycoris, "b", None),
+                (NetworkKind::Lora, "c", Some("333")),
+                (NetworkKind::Hypernetwork, "d", None),
+            ]
+        );
+        assert_eq!(
+            parsed.positive_sd_tags,
+            [
+                SdTag {
+                    name: "smile".to_string(),
+                    weight: None
+                },
+                SdTag {
+                    name: "blue hair".to_string(),
+                    weight: Some(1.2)
+                },
+            ]
+        );
+        // 未知の種類はタグとして残す
+        let parsed = SdParameters::parse("a <foo:bar>\nSteps: 20").unwrap();
+        assert!(parsed.networks.is_empty());
+        assert_eq!(parsed.positive_sd_tags[0].name, "a <foo:bar>");
+        assert_round_trip("smile <lora:a:0.5>, 1girl\nSteps: 20");
+    }
+
+    #[test]
+    fn seed_is_serialized_as_string() {
+        let values = SdTypedValues {
+            seed: Some(u64::MAX),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&values).unwrap();
+        assert_eq!(json["seed"], "18446744073709551615");
+        assert_eq!(
+            serde_json::from_value::<SdTypedValues>(json).unwrap(),
+            values
+        );
+        assert_eq!(
+            serde_json::to_value(SdTypedValues::default()).unwrap()["seed"],
+            serde_json::Value::Null
+        );
+    }
+
+    #[test]
+    fn seed_range() {
+        let seed = |value: &str| {
+            SdParameters {
+                seed: Some(value.to_string()),
+                ..Default::default()
+            }
+            .seed_value()
+        };
+        assert_eq!(seed("-1"), Ok(None));
+        assert_eq!(seed("18446744073709551615"), Ok(Some(u64::MAX)));
+        assert!(matches!(seed("-2"), Err(SdValueError::OutOfRange { .. })));
+        assert!(matches!(
+            seed("18446744073709551616"),
+            Err(SdValueError::OutOfRange { .. })
+        ));
+        assert!(matches!(
+            seed("abc"),
+            Err(SdValueError::InvalidNumber { .. })
+        ));
+    }
+
+    #[test]
+    fn round_trip_without_negative_and_settings() {
+        let parsed = assert_round_trip("1girl, solo");
+        assert_eq!(parsed.parse_warnings, ["Settings line not found"]);
+        assert!(parsed.negative_prompt.is_empty());
+    }
+
+    #[test]
+    fn edited_tags_override_prompt() {
+        let mut parameters = SdParameters::parse(
+            "1girl, (smile:1.2), <lora:detail:0.6>\nNegative prompt: lowres\nSteps: 20",
+        )
+        .unwrap();
+        // タグ列が原文と一致していれば原文をそのまま出力
+        assert!(parameters
+            .to_a1111_string()
+            .starts_with("1girl, (smile:1.2), <lora:detail:0.6>\n"));
+
+        parameters.positive_sd_tags[1].weight = Some(1.4);
+        parameters.negative_sd_tags.push(SdTag {
+            name: "bad hands".to_string(),
+            weight: None,
+        });
+        parameters.networks[0].te_weight = Some(0.3);
+        parameters.networks[0].unet_weight = Some(0.3);
+        let reparsed = SdParameters::parse(&parameters.to_a1111_string()).unwrap();
+        assert_eq!(reparsed.positive_sd_tags, parameters.positive_sd_tags);
+        assert_eq!(reparsed.negative_sd_tags, parameters.negative_sd_tags);
+        assert_eq!(reparsed.networks, parameters.networks);
+    }
+
+    #[test]
+    fn tags_only_parameters_are_rebuilt() {
+        let parameters = SdParameters {
+            positive_sd_tags: vec![
+                SdTag {
+                    name: "1girl".to_string(),
+                    weight: None,
+                },
+                SdTag {
+                    name: "smile (happy)".to_string(),
+                    weight: Some(1.2),
+                },
+            ],
+            networks: vec![NetworkRef {
+                kind: NetworkKind::Lora,
+                name: "detail".to_string(),
+                unet_weight: Some(0.6),
+                te_weight: Some(0.6),
+                hash: None,
+            }],
+            steps: Some("20".to_string()),
+            ..Default::default()
+        };
+        let reparsed = SdParameters::parse(&parameters.to_a1111_string()).unwrap();
+        assert_eq!(reparsed.positive_sd_tags, parameters.positive_sd_tags);
+        assert_eq!(reparsed.networks, parameters.networks);
+        assert_eq!(reparsed.steps.as_deref(), Some("20"));
     }
 }
@@ -1,3 +1,5 @@
+use crate::file_system_handler::FileStamp;
+use crate::webp_metadata::{SourceSignature, ThumbnailMetadata};
 use image::GenericImageView;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -5,7 +7,9 @@ use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::PathBuf;
 use tauri::{AppHandle, Manager, Runtime};
-use webp::Encoder;
+
+/// 埋め込みメタデータの形式のバージョン
+const THUMBNAIL_CACHE_VERSION: u32 = 2;
 
 /// サムネイルの設定
 #[derive(Debug, Clone)]
@@ -47,6 +51,8 @@ pub struct BatchThumbnailResult {
 pub struct ThumbnailHandler {
     config: ThumbnailConfig,
     cache_dir: PathBuf, // Mutexなし！初期化時に設定
+    /// サムネイル生成・メタデータ一括書き込みで共有するスレッドプール
+    thread_pool: rayon::ThreadPool,
 }
 
 impl ThumbnailHandler {
@@ -59,7 +65,33 @@ impl ThumbnailHandler {
                 .map_err(|e| format!("キャッシュディレクトリの作成に失敗: {}", e))?;
         }
 
-        Ok(Self { config, cache_dir })
+        // CPUコア数の75%を使用
+        let available_cores = std::thread::available_parallelism()
+            .map(|cores| cores.get())
+            .unwrap_or(4); // フォールバック値
+        let max_threads = std::cmp::max(1, (available_cores as f64 * 0.75) as usize);
+
+        log::info!(
+            "利用可能コア: {}, 使用コア: {}",
+            available_cores,
+            max_threads
+        );
+
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(max_threads)
+            .build()
+            .map_err(|e| format!("スレッドプールの作成に失敗: {}", e))?;
+
+        Ok(Self {
+            config,
+            cache_dir,
+            thread_pool,
+        })
+    }
+
+    /// 共有スレッドプール
+    pub fn thread_pool(&self) -> &rayon::ThreadPool {
+        &self.thread_pool
     }
 
     /// キャッシュディレクトリのパスを取得
@@ -77,38 +109,46 @@ impl ThumbnailHandler {
         hex::encode(path_hasher.finalize())
     }
 
-    /// 設定とファイルサイズのハッシュを生成（副キー）
-    fn generate_content_hash(&self, image_path: &str) -> String {
-        let mut content_hasher = Sha256::new();
-        content_hasher.update(self.config.size.to_le_bytes());
-        content_hasher.update(self.config.quality.to_le_bytes());
-
-        // ファイルサイズを取得
-        if let Ok(metadata) = fs::metadata(image_path) {
-            content_hasher.update(metadata.len().to_le_bytes());
-            if let Ok(modified) = metadata.modified() {
-                if let Ok(duration) = modified.duration_since(std::time::UNIX_EPOCH) {
-                    content_hasher.update(duration.as_secs().to_le_bytes());
-                }
-            }
-        }
-
-        hex::encode(content_hasher.finalize())
+    /// サムネイル設定のハッシュを生成（副キー）
+    ///
+    /// ファイルの内容はキーに含めず、埋め込みメタデータの生成元情報で検証する。
+    fn generate_config_hash(&self) -> String {
+        let mut config_hasher = Sha256::new();
+        config_hasher.update(self.config.size.to_le_bytes());
+        config_hasher.update(self.config.quality.to_le_bytes());
+        hex::encode(config_hasher.finalize())
     }
 
     /// 画像ファイルパスからキャッシュキーを生成
-    fn generate_cache_key(&self, image_path: &str) -> String {
+    pub fn generate_cache_key(&self, image_path: &str) -> String {
         let path_hash = self.generate_path_hash(image_path);
-        let content_hash = self.generate_content_hash(image_path);
-        format!("{}_{}", path_hash, content_hash)
+        let config_hash = self.generate_config_hash();
+        format!("{}_{}", path_hash, config_hash)
     }
 
-    /// キャッシュが有効かチェック
-    fn is_cache_valid(&self, cache_path: &PathBuf, original_path: &str) -> bool {
-        let cache_exists = cache_path.exists();
-        let original_exists = std::path::Path::new(original_path).exists();
+    /// 画像ファイルパスからキャッシュファイルのパスを取得
+    fn cache_path(&self, image_path: &str) -> PathBuf {
+        self.cache_dir
+            .join(format!("{}.webp", self.generate_cache_key(image_path)))
+    }
+
+    /// ファイル情報から生成元の識別情報を作成
+    fn source_signature(file_metadata: &fs::Metadata, image_digest: String) -> SourceSignature {
+        let stamp = FileStamp::from_metadata(file_metadata);
+        SourceSignature {
+            file_size: stamp.file_size,
+            modified: stamp.modified,
+            changed: stamp.changed,
+            image_digest,
+        }
+    }
 
-        matches!((cache_exists, original_exists), (true, true))
+    /// キャッシュ生成時からファイルが変更されていないか（ファイル情報のみで判定）
+    fn is_same_file(cached: &SourceSignature, file_metadata: &fs::Metadata) -> bool {
+        let current = FileStamp::from_metadata(file_metadata);
+        cached.file_size == current.file_size
+            && cached.modified == current.modified
+            && cached.changed == current.changed
     }
 
     /// キャッシュファイルが古いキャッシュかどうかをチェック
@@ -169,85 +209,145 @@ impl ThumbnailHandler {
         Ok(())
     }
 
+    /// キャッシュに埋め込まれたメタデータを取得（サムネイルは生成しない）
+    ///
+    /// キャッシュが無い場合や、生成後にファイルが変更されている場合は None を返す。
+    pub fn cached_metadata(
+        &self,
+        image_path: &str,
+        file_metadata: &fs::Metadata,
+    ) -> Option<ThumbnailMetadata> {
+        let data = fs::read(self.cache_path(image_path)).ok()?;
+        crate::webp_metadata::extract_metadata_from_webp(&data)
+            .ok()
+            .flatten()
+            .filter(|metadata| {
+                metadata
+                    .source
+                    .as_ref()
+                    .is_some_and(|source| Self::is_same_file(source, file_metadata))
+            })
+    }
+
+    /// 指定された画像のキャッシュを更新（メタデータ書き込み後用）
+    ///
+    /// 更新日時を保持した書き込みは Unix 以外ではファイル情報から検出できないため、
+    /// 既存のキャッシュを画素データのハッシュで検証し直す。メタデータのみの変更なら再生成しない。
+    /// キャッシュのメタデータが古いまま残らないよう、メタデータを書き込むコマンドはすべて書き込み後に呼び出す。
+    pub fn refresh_cache(&self, image_paths: &[String]) -> usize {
+        self.thread_pool.install(|| {
+            image_paths
+                .par_iter()
+                .filter(|path| self.cache_path(path).exists())
+                .filter(|path| match self.load_or_generate_thumbnail(path, true) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        log::warn!("サムネイルキャッシュの更新に失敗: {} ({})", path, e);
+                        false
+                    }
+                })
+                .count()
+        })
+    }
+
     /// バッチでサムネイルを処理（並列読み込み・生成）
     pub fn process_thumbnails_batch<R: Runtime>(
         &self,
         image_paths: &[String],
         _app: &AppHandle<R>,
     ) -> Vec<BatchThumbnailResult> {
-        // CPUコア数の75%を使用
-        let available_cores = std::thread::available_parallelism()
-            .map(|cores| cores.get())
-            .unwrap_or(4); // フォールバック値
-        let max_threads = std::cmp::max(1, (available_cores as f64 * 0.75) as usize);
-
-        log::info!(
-            "利用可能コア: {}, 使用コア: {}",
-            available_cores,
-            max_threads
-        );
-
         // 並列処理でサムネイルを生成（高並列度）
-        rayon::ThreadPoolBuilder::new()
-            .num_threads(max_threads)
-            .build()
-            .unwrap()
-            .install(|| {
-                image_paths
-                    .par_iter()
-                    .map(
-                        |path| match self.load_or_generate_thumbnail(path, &self.cache_dir) {
-                            Ok(thumbnail) => BatchThumbnailResult {
-                                path: path.clone(),
-                                thumbnail: Some(thumbnail),
-                                error: None,
-                            },
-                            Err(e) => BatchThumbnailResult {
-                                path: path.clone(),
-                                thumbnail: None,
-                                error: Some(e),
-                            },
-                        },
-                    )
-                    .collect()
-            })
+        self.thread_pool.install(|| {
+            image_paths
+                .par_iter()
+                .map(|path| match self.load_or_generate_thumbnail(path, false) {
+                    Ok(thumbnail) => BatchThumbnailResult {
+                        path: path.clone(),
+                        thumbnail: Some(thumbnail),
+                        error: None,
+                    },
+                    Err(e) => BatchThumbnailResult {
+                        path: path.clone(),
+                        thumbnail: None,
+                        error: Some(e),
+                    },
+                })
+                .collect()
+        })
     }
 
     /// サムネイルを読み込みまたは生成（キャッシュ優先）
+    ///
+    /// ファイルが変更されていても画素データが同じ場合（レーティング等の編集）は、
+    /// 画像を再デコードせずに埋め込みメタデータのみを更新する。
+    /// `verify_image_data` が true の場合はファイル情報が一致していても画素データを比較する。
     fn load_or_generate_thumbnail(
         &self,
         image_path: &str,
-        cache_dir: &PathBuf,
+        verify_image_data: bool,
     ) -> Result<ThumbnailInfo, String> {
-        let cache_key = self.generate_cache_key(image_path);
-        let cache_path = cache_dir.join(format!("{}.webp", cache_key));
-
-        // キャッシュが有効かチェック
-        if self.is_cache_valid(&cache_path, image_path) {
-            let data = fs::read(&cache_path)
-                .map_err(|e| format!("キャッシュファイルの読み込みに失敗: {}", e))?;
+        let cache_path = self.cache_path(image_path);
+        let file_metadata =
+            fs::metadata(image_path).map_err(|e| format!("ファイル情報の取得に失敗: {}", e))?;
 
-            // 既存のWebPからメタデータを読み取り
-            let metadata = crate::webp_metadata::extract_metadata_from_webp(&data)
+        // 既存のWebPからメタデータを読み取り（生成元情報の無い旧キャッシュは使用しない）
+        let cached = fs::read(&cache_path).ok().and_then(|data| {
+            crate::webp_metadata::extract_metadata_from_webp(&data)
                 .ok()
-                .flatten();
+                .flatten()
+                .filter(|metadata| metadata.source.is_some())
+                .map(|metadata| (data, metadata))
+        });
+
+        if let Some((data, metadata)) = &cached {
+            let unchanged = metadata
+                .source
+                .as_ref()
+                .is_some_and(|source| Self::is_same_file(source, &file_metadata));
+            if unchanged && !verify_image_data {
+                return Ok(ThumbnailInfo {
+                    data: data.clone(),
+                    width: self.config.size,
+                    height: self.config.size,
+                    mime_type: "image/webp".to_string(),
+                    metadata: Some(metadata.clone()),
+                });
+            }
+        }
+
+        let file_data =
+            std::fs::read(image_path).map_err(|e| format!("ファイル読み込みエラー: {}", e))?;
+        let image_digest = crate::image_metadata::image_data_digest(&file_data);
+        let metadata = self.build_thumbnail_metadata(
+            &file_data,
+            image_path,
+            Self::source_signature(&file_metadata, image_digest),
+        );
 
-            return Ok(ThumbnailInfo {
-                data,
+        let same_image = cached.as_ref().is_some_and(|(_, cached_metadata)| {
+            cached_metadata
+                .source
+                .as_ref()
+                .map(|source| &source.image_digest)
+                == metadata.source.as_ref().map(|source| &source.image_digest)
+        });
+        let thumbnail_info = match cached {
+            // メタデータのみの変更
+            Some((data, _)) if same_image => ThumbnailInfo {
+                data: crate::webp_metadata::inject_metadata_to_webp(&data, &metadata)?,
                 width: self.config.size,
                 height: self.config.size,
                 mime_type: "image/webp".to_string(),
-                metadata,
-            });
-        }
-
-        // キャッシュが無効な場合、古いキャッシュファイルを削除
-        if let Err(e) = self.remove_old_cache_files(image_path) {
-            log::warn!("古いキャッシュファイルの削除に失敗: {}", e);
-        }
-
-        // 新しいサムネイルを生成（統合版）
-        let thumbnail_info = self.generate_thumbnail(image_path)?;
+                metadata: Some(metadata),
+            },
+            _ => {
+                // 画素データが変わった場合、古いキャッシュファイルを削除
+                if let Err(e) = self.remove_old_cache_files(image_path) {
+                    log::warn!("古いキャッシュファイルの削除に失敗: {}", e);
+                }
+                self.generate_thumbnail(&file_data, metadata)?
+            }
+        };
 
         // キャッシュに保存
         if let Err(e) = fs::write(&cache_path, &thumbnail_info.data) {
@@ -258,69 +358,76 @@ impl ThumbnailHandler {
     }
 
     /// サムネイルを生成
-    fn generate_thumbnail(&self, image_path: &str) -> Result<ThumbnailInfo, String> {
-        // 1. ファイルを一度だけ読み込み（統一されたアプローチ）
-        let file_data =
-            std::fs::read(image_path).map_err(|e| format!("ファイル読み込みエラー: {}", e))?;
-
-        // 2. バイトデータから画像を読み込み
-        let img = crate::image_handler::load_image_from_bytes(&file_data)?;
+    fn generate_thumbnail(
+        &self,
+        file_data: &[u8],
+        metadata: ThumbnailMetadata,
+    ) -> Result<ThumbnailInfo, String> {
+        // 1. バイトデータから画像を読み込み
+        let img = crate::image_handler::load_image_from_bytes(file_data)?;
 
-        // 3. サムネイル生成（アスペクト比を維持）
+        // 2. サムネイル生成（アスペクト比を維持）
         let thumbnail = img.thumbnail(self.config.size, self.config.size);
         let (width, height) = thumbnail.dimensions();
 
-        // 4. RGBAバイト配列に変換
+        // 3. RGBAバイト配列に変換
         let rgba_image = thumbnail.to_rgba8();
         let rgba_data = rgba_image.as_raw();
 
-        // 5. 条件付きでメタデータを軽量読み取り（同じバイトデータから）
-        let metadata = if self.config.include_metadata {
-            self.extract_metadata_from_bytes(&file_data, image_path)
-                .ok()
-        } else {
-            None
-        };
-
-        // 6. メタデータ付きWebPを一度で生成
-        let webp_data = if let Some(ref meta) = metadata {
-            let encoder =
-                crate::webp_metadata::WebPMetadataEncoder::new(self.config.quality as f32);
-            encoder.encode_with_metadata(rgba_data, width, height, Some(meta.clone()))?
-        } else {
-            // メタデータがない場合は従来の方式
-            let encoder = Encoder::from_rgba(rgba_data, width, height);
-            let webp_memory = encoder.encode(self.config.quality as f32);
-            webp_memory.to_vec()
-        };
+        // 4. メタデータ付きWebPを一度で生成（生成元情報は常に埋め込む）
+        let encoder = crate::webp_metadata::WebPMetadataEncoder::new(self.config.quality as f32);
+        let webp_data =
+            encoder.encode_with_metadata(rgba_data, width, height, Some(metadata.clone()))?;
 
         Ok(ThumbnailInfo {
             data: webp_data,
             width,
             height,
             mime_type: "image/webp".to_string(),
-            metadata,
+            metadata: Some(metadata),
         })
     }
 
+    /// 埋め込みメタデータを作成（メタデータを含めない設定でも生成元情報は記録する）
+    fn build_thumbnail_metadata(
+        &self,
+        file_data: &[u8],
+        image_path: &str,
+        source: SourceSignature,
+    ) -> ThumbnailMetadata {
+        let metadata = if self.config.include_metadata {
+            self.extract_metadata_from_bytes(file_data, image_path).ok()
+        } else {
+            None
+        };
+        let metadata = metadata.unwrap_or(ThumbnailMetadata {
+            exif_info: None,
+            sd_parameters: None,
+            sd_values: None,
+            cache_version: THUMBNAIL_CACHE_VERSION,
+            source: None,
+        });
+
+        ThumbnailMetadata {
+            source: Some(source),
+            ..metadata
+        }
+    }
+
     /// バイトデータからメタデータを抽出（統一アプローチ）
     fn extract_metadata_from_bytes(
         &self,
         file_data: &[u8],
         image_path: &str,
-    ) -> Result<crate::webp_metadata::ThumbnailMetadata, String> {
-        // ファイル拡張子を判定
-        let file_extension = crate::exif_handler::determine_file_extension(image_path);
+    ) -> Result<ThumbnailMetadata, String> {
+        // ファイル内容から形式を判定（拡張子はヒントとしてのみ使用）
+        let format = crate::file_format::FileFormat::detect_with_hint(file_data, image_path)?;
 
         // EXIF情報をバイトデータから読み取り（直接処理）
-        let exif_info = crate::exif_handler::read_exif_from_bytes(file_data, file_extension);
+        let exif_info = crate::exif_handler::read_exif_from_bytes(file_data, format);
 
-        // SDパラメーター情報をバイトデータから読み取り（PNGの場合のみ）
-        let sd_parameters = if image_path.to_lowercase().ends_with(".png") {
-            crate::png_handler::read_png_sd_parameters_from_bytes(file_data)
-        } else {
-            None
-        };
+        // SDパラメーター情報をバイトデータから読み取り（PNG / JPEG / WebP / AVIF）
+        let sd_parameters = crate::image_metadata::read_sd_parameters_from_bytes(file_data);
 
         // デバッグログ：抽出されたメタデータの情報
         log::debug!(
@@ -330,10 +437,14 @@ impl ThumbnailHandler {
             sd_parameters.is_some()
         );
 
-        Ok(crate::webp_metadata::ThumbnailMetadata {
+        Ok(ThumbnailMetadata {
             exif_info,
+            sd_values: sd_parameters
+                .as_ref()
+                .map(crate::sd_parameters::SdParameters::typed_values),
             sd_parameters,
-            cache_version: 1,
+            cache_version: THUMBNAIL_CACHE_VERSION,
+            source: None,
         })
     }
 
@@ -464,8 +575,7 @@ pub async fn extract_thumbnail_metadata<R: Runtime>(
     _app: AppHandle<R>,
     state: tauri::State<'_, ThumbnailState>,
 ) -> Result<Option<crate::webp_metadata::ThumbnailMetadata>, String> {
-    let cache_key = state.handler.generate_cache_key(&image_path);
-    let cache_path = state.handler.cache_dir.join(format!("{}.webp", cache_key));
+    let cache_path = state.handler.cache_path(&image_path);
 
     // キャッシュファイルが存在するかチェック
     if !cache_path.exists() {
@@ -1,13 +1,31 @@
 // WebPメタデータ埋め込み用のモジュール
 use crate::image_types::ExifImageInfo;
-use crate::sd_parameters::SdParameters;
+use crate::sd_parameters::{SdParameters, SdTypedValues};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ThumbnailMetadata {
     pub exif_info: Option<ExifImageInfo>,
     pub sd_parameters: Option<SdParameters>,
+    /// sd_parameters の数値フィールドを型付きにしたもの（旧キャッシュには無い）
+    #[serde(default)]
+    pub sd_values: Option<SdTypedValues>,
     pub cache_version: u32,
+    /// 生成元ファイルの識別情報（旧キャッシュには無い）
+    #[serde(default)]
+    pub source: Option<SourceSignature>,
+}
+
+/// サムネイルの生成元ファイルの識別情報（キャッシュの有効性判定用）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceSignature {
+    pub file_size: u64,
+    /// 更新日時（UNIXエポックからのナノ秒）
+    pub modified: u64,
+    /// inode の変更日時（Unix のみ。更新日時を保持したメタデータ編集も検出する）
+    pub changed: Option<i64>,
+    /// メタデータ以外（画素データ等）のハッシュ
+    pub image_digest: String,
 }
 
 pub struct WebPMetadataEncoder {
@@ -37,90 +55,87 @@ impl WebPMetadataEncoder {
         };
 
         // 3. メタデータをWebPに追加
-        self.inject_metadata_to_webp(webp_data.as_ref(), &meta)
+        inject_metadata_to_webp(webp_data.as_ref(), &meta)
     }
+}
 
-    /// 既存のWebPデータにメタデータチャンクを注入
-    fn inject_metadata_to_webp(
-        &self,
-        webp_data: &[u8],
-        metadata: &ThumbnailMetadata,
-    ) -> Result<Vec<u8>, String> {
-        // WebPフォーマット: 'RIFF' + size + 'WEBP' + chunks
+/// 既存のWebPデータにメタデータチャンクを注入（既存のメタデータチャンクは置き換え）
+pub fn inject_metadata_to_webp(
+    webp_data: &[u8],
+    metadata: &ThumbnailMetadata,
+) -> Result<Vec<u8>, String> {
+    // WebPフォーマット: 'RIFF' + size + 'WEBP' + chunks
 
-        if webp_data.len() < 12 || &webp_data[0..4] != b"RIFF" || &webp_data[8..12] != b"WEBP" {
-            return Err("無効なWebPファイル形式".to_string());
-        }
+    if webp_data.len() < 12 || &webp_data[0..4] != b"RIFF" || &webp_data[8..12] != b"WEBP" {
+        return Err("無効なWebPファイル形式".to_string());
+    }
 
-        let mut result = Vec::new();
+    let mut result = Vec::new();
 
-        // RIFFヘッダーをコピー（サイズは後で更新）
-        result.extend_from_slice(&webp_data[0..8]);
-        result.extend_from_slice(b"WEBP");
+    // RIFFヘッダーをコピー（サイズは後で更新）
+    result.extend_from_slice(&webp_data[0..8]);
+    result.extend_from_slice(b"WEBP");
 
-        // 既存のチャンクをコピー
-        let mut pos = 12;
-        while pos < webp_data.len() {
-            if pos + 8 > webp_data.len() {
-                break;
-            }
+    // 既存のチャンクをコピー
+    let mut pos = 12;
+    while pos < webp_data.len() {
+        if pos + 8 > webp_data.len() {
+            break;
+        }
 
-            let _chunk_id = &webp_data[pos..pos + 4];
-            let chunk_size = u32::from_le_bytes([
-                webp_data[pos + 4],
-                webp_data[pos + 5],
-                webp_data[pos + 6],
-                webp_data[pos + 7],
-            ]) as usize;
-
-            let chunk_end = pos + 8 + chunk_size + (chunk_size % 2); // パディング考慮
-            if chunk_end > webp_data.len() {
-                break;
-            }
+        let chunk_id = &webp_data[pos..pos + 4];
+        let chunk_size = u32::from_le_bytes([
+            webp_data[pos + 4],
+            webp_data[pos + 5],
+            webp_data[pos + 6],
+            webp_data[pos + 7],
+        ]) as usize;
 
+        let chunk_end = pos + 8 + chunk_size + (chunk_size % 2); // パディング考慮
+        if chunk_end > webp_data.len() {
+            break;
+        }
+
+        if chunk_id != b"XMP " {
             result.extend_from_slice(&webp_data[pos..chunk_end]);
-            pos = chunk_end;
         }
+        pos = chunk_end;
+    }
 
-        // メタデータチャンクを追加
-        self.add_metadata_chunk(&mut result, metadata)?;
+    // メタデータチャンクを追加
+    add_metadata_chunk(&mut result, metadata)?;
 
-        // RIFFサイズを更新
-        let total_size = (result.len() - 8) as u32;
-        result[4..8].copy_from_slice(&total_size.to_le_bytes());
+    // RIFFサイズを更新
+    let total_size = (result.len() - 8) as u32;
+    result[4..8].copy_from_slice(&total_size.to_le_bytes());
 
-        Ok(result)
-    }
+    Ok(result)
+}
 
-    /// メタデータチャンクを追加
-    fn add_metadata_chunk(
-        &self,
-        webp_data: &mut Vec<u8>,
-        metadata: &ThumbnailMetadata,
-    ) -> Result<(), String> {
-        // WebP仕様準拠：XMPチャンクとして保存（標準的なメタデータ格納方法）
-        // XMPはXMLベースだが、ここではJSON形式のカスタムデータを保存
-        // 注: 完全なXMP準拠ではないが、XMPチャンクを利用してアプリケーション固有データを格納
-        let json_data = serde_json::to_string(metadata)
-            .map_err(|e| format!("メタデータのシリアライズに失敗: {}", e))?;
-
-        let json_bytes = json_data.as_bytes();
-        let chunk_size = json_bytes.len() as u32;
-
-        // WebP仕様準拠：XMPチャンクヘッダー
-        webp_data.extend_from_slice(b"XMP "); // FourCC（4番目はスペース0x20）
-        webp_data.extend_from_slice(&chunk_size.to_le_bytes()); // Size (little-endian)
-
-        // チャンクペイロード（JSON形式のアプリケーション固有メタデータ）
-        webp_data.extend_from_slice(json_bytes);
-
-        // WebP仕様必須：偶数バイト境界へのパディング
-        if json_bytes.len() % 2 != 0 {
-            webp_data.push(0); // パディングバイトは0でなければならない
-        }
+/// メタデータチャンクを追加
+fn add_metadata_chunk(webp_data: &mut Vec<u8>, metadata: &ThumbnailMetadata) -> Result<(), String> {
+    // WebP仕様準拠：XMPチャンクとして保存（標準的なメタデータ格納方法）
+    // XMPはXMLベースだが、ここではJSON形式のカスタムデータを保存
+    // 注: 完全なXMP準拠ではないが、XMPチャンクを利用してアプリケーション固有データを格納
+    let json_data = serde_json::to_string(metadata)
+        .map_err(|e| format!("メタデータのシリアライズに失敗: {}", e))?;
 
-        Ok(())
+    let json_bytes = json_data.as_bytes();
+    let chunk_size = json_bytes.len() as u32;
+
+    // WebP仕様準拠：XMPチャンクヘッダー
+    webp_data.extend_from_slice(b"XMP "); // FourCC（4番目はスペース0x20）
+    webp_data.extend_from_slice(&chunk_size.to_le_bytes()); // Size (little-endian)
+
+    // チャンクペイロード（JSON形式のアプリケーション固有メタデータ）
+    webp_data.extend_from_slice(json_bytes);
+
+    // WebP仕様必須：偶数バイト境界へのパディング
+    if json_bytes.len() % 2 != 0 {
+        webp_data.push(0); // パディングバイトは0でなければならない
     }
+
+    Ok(())
 }
 
 /// WebPからメタデータを抽出
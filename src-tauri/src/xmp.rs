@@ -0,0 +1,490 @@
+// XMP（Extensible Metadata Platform）パケット解析用のモジュール
+//
+// XMLパーサーを使わず、プロパティ単位の簡易的な抽出のみを行う。
+// 属性形式（`dc:format="image/png"`）と要素形式（`<dc:description><rdf:Alt><rdf:li>...`）の両方に対応する。
+// 書き込みは既存の記述形式を保ったまま値だけを差し替え、無い場合は属性として追加する。
+use std::ops::Range;
+
+/// XMPパケットの開始・終了タグ
+const XMPMETA_START: &str = "<x:xmpmeta";
+const XMPMETA_END: &str = "</x:xmpmeta>";
+
+/// PNG の iTXt チャンクに XMP を格納する際のキーワード
+pub const PNG_XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// プロパティを持たない XMP パケット
+const EMPTY_PACKET: &str = concat!(
+    "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+    " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+    "  <rdf:Description rdf:about=\"\"/>\n",
+    " </rdf:RDF>\n",
+    "</x:xmpmeta>"
+);
+
+/// 書き込み時に宣言する名前空間（接頭辞と URI）
+const NAMESPACES: [(&str, &str); 6] = [
+    ("xmp", "http://ns.adobe.com/xap/1.0/"),
+    ("dc", "http://purl.org/dc/elements/1.1/"),
+    ("lr", "http://ns.adobe.com/lightroom/1.0/"),
+    ("digiKam", "http://www.digikam.org/ns/1.0/"),
+    ("exif", "http://ns.adobe.com/exif/1.0/"),
+    ("tiff", "http://ns.adobe.com/tiff/1.0/"),
+];
+
+/// バイトデータ中の `<x:xmpmeta ...>...</x:xmpmeta>` を取り出す
+pub fn find_xmp_packet(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    let start = text.find(XMPMETA_START)?;
+    let end = text[start..].find(XMPMETA_END)? + start + XMPMETA_END.len();
+    Some(text[start..end].to_string())
+}
+
+/// XMPプロパティの値を取得（言語別・配列の場合は最初の rdf:li）
+pub fn find_property(xmp: &str, name: &str) -> Option<String> {
+    find_attribute(xmp, name).or_else(|| find_element(xmp, name))
+}
+
+/// 空の XMP パケット
+pub fn new_packet() -> String {
+    EMPTY_PACKET.to_string()
+}
+
+/// `<?xpacket?>` 処理命令で囲んだ、ファイル埋め込み用のパケット
+pub fn wrap_packet(xmp: &str) -> String {
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n{}\n<?xpacket end=\"w\"?>",
+        xmp
+    )
+}
+
+/// 単純値のXMPプロパティを設定したパケットを返す
+///
+/// 属性形式・要素形式の既存値は置き換え、無い場合は最初の rdf:Description に属性として追加する。
+pub fn set_property(xmp: &str, name: &str, value: &str) -> String {
+    let escaped = escape_xml(value);
+    if let Some(range) =
+        attribute_value_range(xmp, name).or_else(|| element_content_range(xmp, name))
+    {
+        return format!("{}{}{}", &xmp[..range.start], escaped, &xmp[range.end..]);
+    }
+
+    let (xmp, tag) = prepare_description(xmp, name);
+    // 空要素（`<rdf:Description .../>`）の場合は `/>` の前に挿入
+    let insert_at = tag.end - if xmp[tag].ends_with("/>") { 2 } else { 1 };
+    let attribute = format!(" {}=\"{}\"", name, escaped);
+    format!("{}{}{}", &xmp[..insert_at], attribute, &xmp[insert_at..])
+}
+
+/// 配列プロパティ（rdf:Bag / rdf:Seq）の全要素を取得（属性形式の単一値にも対応）
+pub fn find_list(xmp: &str, name: &str) -> Vec<String> {
+    let Some(range) = element_content_range(xmp, name) else {
+        return find_attribute(xmp, name)
+            .filter(|value| !value.is_empty())
+            .into_iter()
+            .collect();
+    };
+
+    let mut content = &xmp[range];
+    let mut items = Vec::new();
+    while let Some(item) = element_content_range(content, "rdf:li") {
+        items.push(unescape_xml(strip_tags(&content[item.clone()]).trim()));
+        content = &content[item.end..];
+    }
+    items
+}
+
+/// 配列プロパティを rdf:Bag として設定したパケットを返す（空の場合は削除）
+pub fn set_list(xmp: &str, name: &str, values: &[String]) -> String {
+    let xmp = remove_property(xmp, name);
+    if values.is_empty() {
+        return xmp;
+    }
+
+    let items: String = values
+        .iter()
+        .map(|value| format!("<rdf:li>{}</rdf:li>", escape_xml(value)))
+        .collect();
+    let element = format!("<{0}><rdf:Bag>{1}</rdf:Bag></{0}>", name, items);
+
+    let (xmp, tag) = prepare_description(&xmp, name);
+    if xmp[tag.clone()].ends_with("/>") {
+        // 空要素の rdf:Description を開始・終了タグに展開
+        format!(
+            "{}>{}</rdf:Description>{}",
+            &xmp[..tag.end - 2],
+            element,
+            &xmp[tag.end..]
+        )
+    } else {
+        format!("{}{}{}", &xmp[..tag.end], element, &xmp[tag.end..])
+    }
+}
+
+/// プロパティを削除したパケットを返す（属性形式・要素形式の両方）
+pub fn remove_property(xmp: &str, name: &str) -> String {
+    if let Some(value) = attribute_value_range(xmp, name) {
+        let name_start = xmp[..value.start].rfind(name).unwrap_or(value.start);
+        let start = xmp[..name_start].trim_end().len();
+        return format!("{}{}", &xmp[..start], &xmp[value.end + 1..]);
+    }
+    match element_range(xmp, name) {
+        Some(range) => format!("{}{}", &xmp[..range.start], &xmp[range.end..]),
+        None => xmp.to_string(),
+    }
+}
+
+/// プロパティを追加する rdf:Description を用意し、名前空間を宣言したパケットと開始タグの位置を返す
+fn prepare_description(xmp: &str, name: &str) -> (String, Range<usize>) {
+    let (mut xmp, mut tag) = ensure_description(xmp);
+    if let Some(declaration) = missing_namespace(&xmp, tag.clone(), name) {
+        let insert_at = tag.start + "<rdf:Description".len();
+        xmp.insert_str(insert_at, &declaration);
+        tag.end += declaration.len();
+    }
+    (xmp, tag)
+}
+
+/// rdf:Description が無いパケットに空の rdf:Description を追加し、開始タグの位置を返す
+fn ensure_description(xmp: &str) -> (String, Range<usize>) {
+    const EMPTY_DESCRIPTION: &str = "<rdf:Description rdf:about=\"\"/>";
+    if let Some(tag) = description_tag_range(xmp) {
+        return (xmp.to_string(), tag);
+    }
+    match xmp.find("</rdf:RDF>") {
+        Some(end) => (
+            format!("{}{}{}", &xmp[..end], EMPTY_DESCRIPTION, &xmp[end..]),
+            end..end + EMPTY_DESCRIPTION.len(),
+        ),
+        // rdf:RDF も無い場合は空のパケットから作り直す
+        None => ensure_description(EMPTY_PACKET),
+    }
+}
+
+/// 最初の rdf:Description 開始タグの位置（`<` から `>` まで）
+fn description_tag_range(xmp: &str) -> Option<Range<usize>> {
+    const OPEN: &str = "<rdf:Description";
+    let mut search_from = 0;
+    while let Some(found) = xmp[search_from..].find(OPEN) {
+        let start = search_from + found;
+        search_from = start + OPEN.len();
+        let next = xmp[search_from..].chars().next()?;
+        if next == '>' || next == '/' || next.is_whitespace() {
+            let end = search_from + xmp[search_from..].find('>')? + 1;
+            return Some(start..end);
+        }
+    }
+    None
+}
+
+/// プロパティの接頭辞が未宣言の場合の名前空間宣言（` xmlns:xmp="..."`）
+///
+/// 対象は最初の rdf:Description のため、その開始タグまでに祖先要素の宣言が含まれる。
+fn missing_namespace(xmp: &str, description: Range<usize>, name: &str) -> Option<String> {
+    let (prefix, _) = name.split_once(':')?;
+    let attribute = format!("xmlns:{}=", prefix);
+    if xmp[..description.end].contains(&attribute) {
+        return None;
+    }
+    let (_, uri) = NAMESPACES.iter().find(|(known, _)| *known == prefix)?;
+    Some(format!(" {}\"{}\"", attribute, uri))
+}
+
+/// 属性形式 `name="value"` の値
+fn find_attribute(xmp: &str, name: &str) -> Option<String> {
+    attribute_value_range(xmp, name).map(|range| unescape_xml(&xmp[range]))
+}
+
+/// 属性形式 `name="value"` の値の位置（引用符を除く）
+fn attribute_value_range(xmp: &str, name: &str) -> Option<Range<usize>> {
+    let mut search_from = 0;
+    while let Some(found) = xmp[search_from..].find(name) {
+        let start = search_from + found;
+        search_from = start + name.len();
+
+        // 要素名の一部（`<dc:description` 等）や別名の末尾との一致を除外
+        let preceded_by_space = xmp[..start]
+            .chars()
+            .next_back()
+            .is_some_and(char::is_whitespace);
+        let rest = xmp[search_from..].trim_start();
+        if !preceded_by_space || !rest.starts_with('=') {
+            continue;
+        }
+
+        let rest = rest[1..].trim_start();
+        let quote = rest.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+        let value_start = xmp.len() - rest.len() + 1;
+        let value_end = value_start + xmp[value_start..].find(quote)?;
+        return Some(value_start..value_end);
+    }
+    None
+}
+
+/// 要素形式 `<name ...>content</name>` の内容（子要素がある場合は最初の rdf:li）
+fn find_element(xmp: &str, name: &str) -> Option<String> {
+    let content = &xmp[element_content_range(xmp, name)?];
+    let content = match find_element(content, "rdf:li") {
+        Some(item) => item,
+        None => unescape_xml(strip_tags(content).trim()),
+    };
+    Some(content)
+}
+
+/// 要素形式 `<name ...>content</name>` の内容の位置（空要素は None）
+fn element_content_range(xmp: &str, name: &str) -> Option<Range<usize>> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+
+    let mut search_from = 0;
+    while let Some(found) = xmp[search_from..].find(&open) {
+        let start = search_from + found + open.len();
+        search_from = start;
+
+        // `<dc:descriptionX` のような前方一致を除外
+        let next = xmp[start..].chars().next()?;
+        if !(next == '>' || next == '/' || next.is_whitespace()) {
+            continue;
+        }
+
+        let tag_end = start + xmp[start..].find('>')?;
+        if xmp[..tag_end].ends_with('/') {
+            return None; // 空要素
+        }
+        let content_end = tag_end + xmp[tag_end..].find(&close)?;
+        return Some(tag_end + 1..content_end);
+    }
+    None
+}
+
+/// 要素形式 `<name ...>content</name>` / `<name/>` の要素全体の位置
+fn element_range(xmp: &str, name: &str) -> Option<Range<usize>> {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+
+    let mut search_from = 0;
+    while let Some(found) = xmp[search_from..].find(&open) {
+        let start = search_from + found;
+        search_from = start + open.len();
+
+        let next = xmp[search_from..].chars().next()?;
+        if !(next == '>' || next == '/' || next.is_whitespace()) {
+            continue;
+        }
+
+        let tag_end = search_from + xmp[search_from..].find('>')? + 1;
+        if xmp[..tag_end].ends_with("/>") {
+            return Some(start..tag_end);
+        }
+        let end = tag_end + xmp[tag_end..].find(&close)? + close.len();
+        return Some(start..end);
+    }
+    None
+}
+
+/// 要素タグを取り除いたテキスト
+fn strip_tags(xml: &str) -> String {
+    let mut text = String::with_capacity(xml.len());
+    let mut in_tag = false;
+    for c in xml.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// XMLの特殊文字をエスケープ
+fn escape_xml(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            '\n' => result.push_str("&#xA;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// XMLの実体参照を展開
+fn unescape_xml(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let Some(semicolon) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semicolon];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#')?.parse::<u32>().ok())
+                .and_then(char::from_u32),
+        };
+
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[semicolon + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Photoshop で保存した画像の XMP（xmp / dc の名前空間は未宣言）
+    const PHOTOSHOP_PACKET: &str = concat!(
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\" x:xmptk=\"Adobe XMP Core 7.1-c000\">\n",
+        " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+        "  <rdf:Description rdf:about=\"\"\n",
+        "    xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"\n",
+        "    xmlns:xmpMM=\"http://ns.adobe.com/xap/1.0/mm/\"\n",
+        "   photoshop:ColorMode=\"3\"\n",
+        "   xmpMM:InstanceID=\"xmp.iid:0a1b2c3d\">\n",
+        "   <xmpMM:History>\n",
+        "    <rdf:Seq>\n",
+        "     <rdf:li xmpMM:action=\"saved\"/>\n",
+        "    </rdf:Seq>\n",
+        "   </xmpMM:History>\n",
+        "  </rdf:Description>\n",
+        " </rdf:RDF>\n",
+        "</x:xmpmeta>"
+    );
+
+    /// Lightroom で書き出した画像の XMP（要素形式の評価・キーワード）
+    const LIGHTROOM_PACKET: &str = concat!(
+        "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n",
+        " <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n",
+        "  <rdf:Description rdf:about=\"\"\n",
+        "    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n",
+        "    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n",
+        "    xmlns:tiff=\"http://ns.adobe.com/tiff/1.0/\">\n",
+        "   <xmp:Rating>2</xmp:Rating>\n",
+        "   <tiff:Orientation>1</tiff:Orientation>\n",
+        "   <dc:subject>\n",
+        "    <rdf:Bag>\n",
+        "     <rdf:li>landscape</rdf:li>\n",
+        "     <rdf:li>sunset</rdf:li>\n",
+        "    </rdf:Bag>\n",
+        "   </dc:subject>\n",
+        "  </rdf:Description>\n",
+        " </rdf:RDF>\n",
+        "</x:xmpmeta>"
+    );
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn inserts_into_packet_with_other_namespaces() {
+        let xmp = set_property(PHOTOSHOP_PACKET, "xmp:Rating", "4");
+        let xmp = set_list(&xmp, "dc:subject", &strings(&["1girl", "smile"]));
+
+        assert_eq!(find_property(&xmp, "xmp:Rating").as_deref(), Some("4"));
+        assert_eq!(find_list(&xmp, "dc:subject"), ["1girl", "smile"]);
+        assert!(xmp.contains("xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\""));
+        assert!(xmp.contains("xmlns:dc=\"http://purl.org/dc/elements/1.1/\""));
+        // 既存の名前空間・プロパティはそのまま
+        assert_eq!(
+            find_property(&xmp, "photoshop:ColorMode").as_deref(),
+            Some("3")
+        );
+        assert_eq!(
+            find_property(&xmp, "xmpMM:InstanceID").as_deref(),
+            Some("xmp.iid:0a1b2c3d")
+        );
+        assert!(xmp.contains("<rdf:li xmpMM:action=\"saved\"/>"));
+        assert!(xmp.ends_with("</x:xmpmeta>"));
+    }
+
+    #[test]
+    fn updates_existing_properties() {
+        let xmp = set_property(LIGHTROOM_PACKET, "xmp:Rating", "5");
+        let xmp = set_list(&xmp, "dc:subject", &strings(&["portrait"]));
+
+        assert_eq!(find_property(&xmp, "xmp:Rating").as_deref(), Some("5"));
+        assert_eq!(xmp.matches("xmp:Rating").count(), 2); // 開始・終了タグのみ
+        assert_eq!(find_list(&xmp, "dc:subject"), ["portrait"]);
+        assert_eq!(xmp.matches("<dc:subject>").count(), 1);
+        assert_eq!(
+            find_property(&xmp, "tiff:Orientation").as_deref(),
+            Some("1")
+        );
+        assert_eq!(xmp.matches("xmlns:xmp=").count(), 1);
+
+        // 空のリストはプロパティごと削除
+        let xmp = set_list(&xmp, "dc:subject", &[]);
+        assert!(!xmp.contains("dc:subject"));
+        assert!(find_list(&xmp, "dc:subject").is_empty());
+    }
+
+    #[test]
+    fn updates_attribute_form_rating() {
+        let xmp = set_property(&new_packet(), "xmp:Rating", "1");
+        let xmp = set_property(&xmp, "xmp:Rating", "3");
+        assert_eq!(xmp.matches("xmp:Rating=").count(), 1);
+        assert_eq!(find_property(&xmp, "xmp:Rating").as_deref(), Some("3"));
+
+        let xmp = remove_property(&xmp, "xmp:Rating");
+        assert_eq!(find_property(&xmp, "xmp:Rating"), None);
+        assert!(description_tag_range(&xmp).is_some());
+    }
+
+    #[test]
+    fn creates_packet_from_scratch() {
+        let xmp = set_list(
+            &new_packet(),
+            "dc:subject",
+            &strings(&["a & b", "<tag>", "\"quoted\""]),
+        );
+        let xmp = set_property(&xmp, "xmp:Rating", "2");
+
+        assert_eq!(find_property(&xmp, "xmp:Rating").as_deref(), Some("2"));
+        assert_eq!(
+            find_list(&xmp, "dc:subject"),
+            ["a & b", "<tag>", "\"quoted\""]
+        );
+        // 埋め込み用に包んだパケットからも読み取れる
+        let wrapped = wrap_packet(&xmp);
+        assert_eq!(find_xmp_packet(wrapped.as_bytes()).as_deref(), Some(&*xmp));
+    }
+
+    #[test]
+    fn packet_without_description() {
+        let xmp = concat!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+            "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"></rdf:RDF>",
+            "</x:xmpmeta>"
+        );
+        let xmp = set_property(xmp, "xmp:Rating", "4");
+        assert_eq!(find_property(&xmp, "xmp:Rating").as_deref(), Some("4"));
+        assert!(xmp.contains("xmlns:xmp="));
+    }
+}
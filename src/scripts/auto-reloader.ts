@@ -1,8 +1,15 @@
+import { invoke } from "@tauri-apps/api/core";
+import { listen, type UnlistenFn } from "@tauri-apps/api/event";
+import type { DirectoryChangedPayload } from "./rust-synced-types";
+
 /**
  * 自動リロード機能クラス（状態管理 + 実行制御）
+ *
+ * バックエンドのディレクトリ監視（`watch_directory`）からの通知で、新しい画像が追加されたときだけ実行する。
  */
 class AutoReloader {
-  private intervalId: number | null = null;
+  private watchedDirectory: string | null = null;
+  private unlisten: UnlistenFn | null = null;
   private isActive = false;
 
   constructor() {
@@ -14,51 +21,88 @@ class AutoReloader {
 
   /**
    * 自動リロードを開始
-   * @param callback 実行するコールバック関数
-   * @param interval 実行間隔（ミリ秒）
+   * @param directory 監視するディレクトリ
+   * @param callback 画像が追加されたときに実行するコールバック関数
+   * @param recursive サブディレクトリ内の画像も監視するか
    */
-  start(callback: () => Promise<void>, interval: number = 2000): void {
-    if (this.isActive || this.intervalId !== null) {
+  async start(
+    directory: string,
+    callback: () => Promise<void>,
+    recursive = false,
+  ): Promise<void> {
+    if (this.isActive) {
       console.warn("Auto reload is already active");
       return;
     }
 
-    console.log(`Starting auto reload with interval ${interval}ms`);
+    console.log(`Starting auto reload for ${directory}`);
     this.isActive = true;
+    this.watchedDirectory = directory;
+    this.notifyStateChange();
 
     // 即座に1回実行
     callback().catch(console.error);
 
-    // 定期実行を開始
-    this.intervalId = window.setInterval(async () => {
-      try {
-        await callback();
-      } catch (error) {
-        console.error("Auto reload execution failed:", error);
+    try {
+      const unlisten = await listen<DirectoryChangedPayload>(
+        "directory-changed",
+        async (event) => {
+          if (event.payload.directory !== this.watchedDirectory) {
+            return;
+          }
+
+          const hasNewImage = event.payload.changes.some(
+            (change) => change.kind === "created" || change.kind === "renamed",
+          );
+          if (!hasNewImage) {
+            return;
+          }
+
+          try {
+            await callback();
+          } catch (error) {
+            console.error("Auto reload execution failed:", error);
+          }
+        },
+      );
+
+      // 登録中に停止された場合
+      if (this.watchedDirectory !== directory) {
+        unlisten();
+        return;
       }
-    }, interval);
+      this.unlisten = unlisten;
 
-    this.notifyStateChange();
+      await invoke("watch_directory", { path: directory, recursive });
+    } catch (error) {
+      console.error("Failed to watch directory:", error);
+      await this.stop();
+    }
   }
 
   /**
    * 自動リロードを停止
    */
-  stop(): void {
-    if (!this.isActive && this.intervalId === null) {
+  async stop(): Promise<void> {
+    if (!this.isActive) {
       console.warn("Auto reload is not active");
       return;
     }
 
     console.log("Stopping auto reload");
 
-    if (this.intervalId !== null) {
-      window.clearInterval(this.intervalId);
-      this.intervalId = null;
-    }
-
+    const directory = this.watchedDirectory;
+    this.unlisten?.();
+    this.unlisten = null;
+    this.watchedDirectory = null;
     this.isActive = false;
     this.notifyStateChange();
+
+    if (directory !== null) {
+      await invoke("unwatch_directory", { path: directory }).catch(
+        console.error,
+      );
+    }
   }
 
   /**
@@ -75,7 +119,7 @@ class AutoReloader {
     document.dispatchEvent(
       new CustomEvent("auto-reload-state-changed", {
         detail: { isActive: this.isActive },
-      })
+      }),
     );
   }
 }
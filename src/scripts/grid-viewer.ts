@@ -2,10 +2,13 @@ import "@scripts/image-card";
 import type { ImageCard } from "@scripts/image-card";
 import { path } from "@tauri-apps/api";
 import { invoke } from "@tauri-apps/api/core";
-import { readDir } from "@tauri-apps/plugin-fs";
 import type { WriteImageRatingWithPathEventDetail } from "./global";
 import { SUPPORTED_IMAGE_EXTS } from "./mine-type";
-import type { BatchThumbnailResult, ThumbnailInfo } from "./rust-synced-types";
+import type {
+  BatchThumbnailResult,
+  ImageListEntry,
+  ThumbnailInfo,
+} from "./rust-synced-types";
 
 // サムネイルからObjectURLを作成する関数
 const createThumbnailUrl = (thumbnailInfo: ThumbnailInfo): string => {
@@ -73,20 +76,15 @@ class GridViewer extends HTMLElement {
     console.log({ "urlParams.get('dir')": urlParams.get("dir") });
     const TARGET_DIR = urlParams.get("dir") ?? (await path.downloadDir());
 
-    const dirEntries = await readDir(TARGET_DIR);
-    const imageEntries = dirEntries.filter((entry) => {
-      if (!entry.isFile) return false;
-      const ext = entry.name.split(".").at(-1)?.toLowerCase();
-      return (
-        ext && SUPPORTED_IMAGE_EXTS.some((supportedExt) => supportedExt === ext)
-      );
-    });
-
-    const imageFullPaths = await Promise.all(
-      imageEntries
-        .sort((a, b) => a.name.localeCompare(b.name))
-        .map(async (entry) => await path.join(TARGET_DIR, entry.name)),
+    // 対応形式の画像をファイル名順（数字部分は数値順）で取得
+    const imageEntries: ImageListEntry[] = await invoke(
+      "list_images_in_directory",
+      {
+        directory: TARGET_DIR,
+        options: { sort_by: "name", extensions: [...SUPPORTED_IMAGE_EXTS] },
+      },
     );
+    const imageFullPaths = imageEntries.map((entry) => entry.path);
 
     // 先にDOM更新（プレースホルダー表示）
     for (const imageFullPath of imageFullPaths) {
@@ -130,8 +130,14 @@ class ImageViewer extends HTMLElement {
       return;
     }
 
+    if (!this.currentImagePath) {
+      console.error("No image path available");
+      return;
+    }
+
     const callback = this.createAutoReloadCallback();
-    autoReloader.start(callback);
+    const directory = await path.dirname(this.currentImagePath);
+    await autoReloader.start(directory, callback);
   };
 
   private requestStopAutoReload = async () => {
@@ -142,7 +148,7 @@ class ImageViewer extends HTMLElement {
       return;
     }
 
-    autoReloader.stop();
+    await autoReloader.stop();
   };
 
   connectedCallback() {
@@ -200,7 +206,9 @@ class ImageViewer extends HTMLElement {
     );
 
     // 自動リロードを停止
-    autoReloader.stop();
+    if (autoReloader.getState()) {
+      autoReloader.stop().catch(console.error);
+    }
 
     // URLオブジェクトのクリーンアップ
     this.cleanupCurrentImageUrl();
@@ -259,7 +267,7 @@ class ImageViewer extends HTMLElement {
     // 自動リロード停止の制御
     if (options.stopAutoReload && autoReloader.getState()) {
       console.log("Stopping auto reload due to manual navigation");
-      autoReloader.stop();
+      await autoReloader.stop();
     }
 
     const newImagePath = await ImageNavigator.findImageInDirection(
@@ -11,14 +11,121 @@ export type FileSystemInfo = Readonly<{
   file_modified_timestamp: number;
 }>;
 
+export type ImageDimensions = Readonly<{
+  width: number;
+  height: number;
+}>;
+
+export type SdSummary = Readonly<{
+  model: string | null;
+  sampler: string | null;
+  steps: number | null;
+  cfg_scale: number | null;
+  seed: string | null;
+}>;
+
+export type ImageListEntry = Readonly<{
+  path: string;
+  file_system_info: FileSystemInfo;
+  dimensions: ImageDimensions | null;
+  rating: number | null;
+  sd_summary: SdSummary | null;
+}>;
+
+export type ImageSortKey =
+  | "name"
+  | "modified"
+  | "size"
+  | "rating"
+  | "seed"
+  | "steps";
+
+export type ImageListOptions = Readonly<{
+  sort_by?: ImageSortKey;
+  descending?: boolean;
+  extensions?: string[];
+  recursive?: boolean;
+  max_depth?: number | null;
+  include?: string[];
+  exclude?: string[];
+}>;
+
+export type LibraryScanProgress = Readonly<{
+  directory: string;
+  scanned_directories: number;
+  found_images: number;
+  processed_images: number;
+  finished: boolean;
+}>;
+
+export type SearchField =
+  | "filename"
+  | "positive_prompt"
+  | "positive_tag"
+  | "negative_tag"
+  | "model"
+  | "sampler"
+  | "network"
+  | "steps"
+  | "cfg_scale"
+  | "seed"
+  | "rating"
+  | "width"
+  | "height";
+
+export type SearchHighlight = Readonly<{
+  field: SearchField;
+  text: string;
+  start: number;
+  end: number;
+}>;
+
+export type ImageSearchResult = Readonly<{
+  path: string;
+  highlights: SearchHighlight[];
+}>;
+
 export type SdTag = Readonly<{
   name: string;
   weight: number | null;
 }>;
 
+export type PromptNode =
+  | Readonly<{ type: "text"; text: string; weight: number }>
+  | Readonly<{ type: "emphasis"; multiplier: number; children: PromptNode[] }>
+  | Readonly<{
+      type: "scheduled";
+      from: PromptNode[];
+      to: PromptNode[];
+      step: number;
+    }>
+  | Readonly<{ type: "alternate"; options: PromptNode[][] }>
+  | Readonly<{ type: "break" }>;
+
+export type SdSetting = Readonly<{
+  key: string;
+  value: string;
+}>;
+
+export type NetworkKind = "Lora" | "Lycoris" | "Hypernetwork" | "Embedding";
+
+export type NetworkRef = Readonly<{
+  kind: NetworkKind;
+  name: string;
+  unet_weight: number | null;
+  te_weight: number | null;
+  hash: string | null;
+}>;
+
+export type SdParameterSource = "A1111" | "ComfyUi" | "NovelAi";
+
 export type SdParameters = Readonly<{
+  source: SdParameterSource;
+  positive_prompt: string;
+  negative_prompt: string;
   positive_sd_tags: SdTag[];
   negative_sd_tags: SdTag[];
+  networks: NetworkRef[];
   steps: string | null;
   sampler: string | null;
   schedule_type: string | null;
@@ -28,9 +135,31 @@ export type SdParameters = Readonly<{
   model: string | null;
   denoising_strength: string | null;
   clip_skip: string | null;
+  settings: SdSetting[];
+  parse_warnings: string[];
   raw: string;
 }>;
 
+export type SdValueError =
+  | Readonly<{ kind: "invalid_number"; field: string; value: string }>
+  | Readonly<{ kind: "out_of_range"; field: string; value: string }>
+  | Readonly<{ kind: "invalid_size"; value: string }>;
+
+export type SdTypedValues = Readonly<{
+  steps: number | null;
+  cfg_scale: number | null;
+  seed: string | null;
+  width: number | null;
+  height: number | null;
+  denoising_strength: number | null;
+  clip_skip: number | null;
+  errors: SdValueError[];
+}>;
+
+export type SdParametersInput =
+  | Readonly<{ raw: string }>
+  | Readonly<{ structured: SdParameters }>;
+
 export type PngImageInfo = Readonly<{
   width: number;
   height: number;
@@ -38,25 +167,110 @@ export type PngImageInfo = Readonly<{
   color_type: string;
   has_alpha: boolean;
   sd_parameters: SdParameters | null;
+  sd_values: SdTypedValues | null;
+}>;
+
+export type PngImageDetails = Readonly<{
+  bit_depth: number;
+  color_type: string;
+  interlaced: boolean;
+  animation_frames: number | null;
+  text_keywords: string[];
+}>;
+
+export type ImageInfo = Readonly<{
+  format: string;
+  width: number;
+  height: number;
+  color_type: string;
+  bit_depth: number;
+  has_alpha: boolean;
+  is_animated: boolean;
+  has_icc_profile: boolean;
+  png: PngImageDetails | null;
 }>;
 
+export type RatingSource = "exif" | "xmp" | "exif_and_xmp";
+
 export type ExifImageInfo = Readonly<{
   rating: number | null;
+  rating_source?: RatingSource | null;
   create_date: string | null;
   datetime_original: string | null;
   modify_date: string | null;
 }>;
 
+export type PickFlag = "pick" | "reject";
+
+export type ImageCuration = Readonly<{
+  label: string | null;
+  pick: PickFlag | null;
+  keywords: string[];
+  hierarchical_keywords: string[];
+}>;
+
 export type ComprehensiveImageInfo = Readonly<{
   file_system_info: FileSystemInfo;
-  png_info: PngImageInfo;
+  image_info: ImageInfo;
   exif_info: ExifImageInfo;
+  curation: ImageCuration;
+  sd_parameters: SdParameters | null;
+  sd_values: SdTypedValues | null;
+}>;
+
+export type BatchWriteResult = Readonly<{
+  path: string;
+  error: string | null;
+}>;
+
+export type MetadataWriteOptions = Readonly<{
+  preserve_timestamps: boolean;
+}>;
+
+export type DirectoryChangeKind = "created" | "modified" | "removed" | "renamed";
+
+export type DirectoryChange = Readonly<{
+  kind: DirectoryChangeKind;
+  path: string;
+  old_path: string | null;
+}>;
+
+export type DirectoryChangedPayload = Readonly<{
+  directory: string;
+  changes: DirectoryChange[];
+}>;
+
+export type OperationKind =
+  | "write_rating"
+  | "clear_sd_parameters"
+  | "write_sd_parameters"
+  | "write_curation";
+
+export type OperationSummary = Readonly<{
+  id: string;
+  kind: OperationKind;
+  created_at: number;
+  paths: string[];
+}>;
+
+export type UndoResult = Readonly<{
+  operation: OperationSummary;
+  results: BatchWriteResult[];
 }>;
 
 export type ThumbnailMetadata = Readonly<{
   exif_info: ExifImageInfo | null;
   sd_parameters: SdParameters | null;
+  sd_values?: SdTypedValues | null;
   cache_version: number;
+  source?: SourceSignature | null;
+}>;
+
+export type SourceSignature = Readonly<{
+  file_size: number;
+  modified: number;
+  changed: number | null;
+  image_digest: string;
 }>;
 
 export type ThumbnailInfo = Readonly<{